@@ -16,7 +16,7 @@ _oerec() {
 
     case ${prev} in 
         oerec)
-            local sub='add-server list-server update-server delete-server add-servergroup list-servergroup update-servergroup delete-servergroup add-server-to-servergroup add-servergroup-to-servergroup delete-server-from-servergroup delete-servergroup-from-servergroup add-user list-user update-user delete-user add-key list-key update-key delete-key add-usergroup list-usergroup update-usergroup delete-usergroup add-user-to-usergroup add-usergroup-to-usergroup delete-user-from-usergroup delete-usergroup-from-usergroup add-serveraccess list-serveraccess update-serveraccess delete-serveraccess add-useraccess list-useraccess delete-useraccess enable-dns disable-dns enable-server disable-server enable-user disable-user write-serverauth'
+            local sub='add-server list-server update-server delete-server add-servergroup list-servergroup update-servergroup delete-servergroup add-server-to-servergroup add-servergroup-to-servergroup delete-server-from-servergroup delete-servergroup-from-servergroup add-user list-user update-user delete-user add-key list-key update-key delete-key import-keys rotate-key block-key unblock-key list-blocked-keys add-usergroup list-usergroup update-usergroup delete-usergroup add-user-to-usergroup add-usergroup-to-usergroup delete-user-from-usergroup delete-usergroup-from-usergroup add-serveraccess list-serveraccess update-serveraccess delete-serveraccess add-useraccess list-useraccess delete-useraccess add-certauthority list-certauthority delete-certauthority add-certauthority-to-serveraccess delete-certauthority-from-serveraccess issue-cert enable-dns disable-dns enable-server disable-server enable-user disable-user add-server-token delete-server-token explain-access check-groups check-keys audit-keys verify-keys list-serverauth write-serverauth serverauth-history deploy serve authorized-keys-command'
             COMPREPLY=( $(compgen -W "${sub}" -- "${cur}") )
             ;;
         add-server)
@@ -25,7 +25,7 @@ _oerec() {
             return 0
             ;;
         list-server)
-            opts="-e -h -j --server --ip --id --exact --json --comment --help"
+            opts="-e -h -j --server --ip --id --exact --json --comment --added-by --added-after --help"
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
@@ -85,7 +85,7 @@ _oerec() {
             return 0
             ;;
         list-user)
-            opts="-e -h -j --email --name --id --exact --json --help"
+            opts="-e -h -j --email --name --id --exact --json --added-by --added-after --help"
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
@@ -100,17 +100,57 @@ _oerec() {
             return 0
             ;;
         add-key)
-            opts="-h --email --sshkey --comment --help"
+            opts="-h --email --sshkey --comment --until --sshfrom --sshcommand --sshoption --help"
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
         list-key)
-            opts="-h -j --email --fingerprint --id --with-key --json --help"
+            opts="-h -j --email --fingerprint --id --expiring-within --with-key --fingerprint-format --visual --json --added-by --added-after --help"
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
         update-key)
-            opts="-h --id --sshkey --comment --help"
+            opts="-h --id --sshkey --comment --until --sshfrom --sshcommand --sshoption --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        check-keys)
+            opts="-h -j --json --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        audit-keys)
+            opts="-h -j --threshold --json --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        verify-keys)
+            opts="-h -j --fix --json --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        block-key)
+            opts="-h --fingerprint --sshkey --file --comment --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        unblock-key)
+            opts="-h --fingerprint --confirm --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        list-blocked-keys)
+            opts="-h -j --fingerprint --json --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        rotate-key)
+            opts="-h --id --sshkey --overlap --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        import-keys)
+            opts="-h -j --email --file --json --help"
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
@@ -160,17 +200,17 @@ _oerec() {
             return 0
             ;;
         add-serveraccess)
-            opts="-h --serveraccess --sshuser --sshfrom --sshcommand --sshoption --server --servergroup --comment --help"
+            opts="-h --serveraccess --sshuser --sshfrom --sshcommand --sshoption --keytypes --verify-required --server --servergroup --comment --help"
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
         list-serveraccess)
-            opts="-e -h -j --server --ip --sshuser --exact --json --help"
+            opts="-e -h -j --server --ip --sshuser --exact --json --added-by --added-after --help"
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
         update-serveraccess)
-            opts="-h --serveraccess --newname --sshuser --sshfrom --sshcomment --sshoption --server --servergroup --comment --help"
+            opts="-h --serveraccess --newname --sshuser --sshfrom --sshcomment --sshoption --keytypes --verify-required --no-verify-required --server --servergroup --comment --help"
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
@@ -185,7 +225,7 @@ _oerec() {
             return 0
             ;;
         list-useraccess)
-            opts="-e -h -j --server --ip --email --sshuser --serveraccess --expired --disabled --exact --json --servergroup --usergroup --help"
+            opts="-e -h -j --server --ip --email --sshuser --serveraccess --expired --disabled --exact --json --servergroup --usergroup --added-by --added-after --help"
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
@@ -194,6 +234,36 @@ _oerec() {
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
+        add-certauthority)
+            opts="-h --certauthority --sshkey --comment --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        list-certauthority)
+            opts="-h -e -j --certauthority --serveraccess --exact --json --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        delete-certauthority)
+            opts="-h --certauthority --confirm --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        add-certauthority-to-serveraccess)
+            opts="-h --certauthority --serveraccess --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        delete-certauthority-from-serveraccess)
+            opts="-h --certauthority --serveraccess --confirm --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        issue-cert)
+            opts="-h --email --serveraccess --id --validity --ca-key --output --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
         enable-dns)
             opts="-h --server --confirm --help"
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
@@ -224,8 +294,53 @@ _oerec() {
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
+        add-server-token)
+            opts="-h --server --confirm --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        delete-server-token)
+            opts="-h --server --confirm --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        explain-access)
+            opts="-h -j --email --server --sshuser --json --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        check-groups)
+            opts="-h -j --json --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        list-serverauth)
+            opts="-h -j --server --ip --filtered --json --ndjson --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
         write-serverauth)
-            opts="-h --workdir --force --help"
+            opts="-h --workdir --force --dry-run --git --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        serverauth-history)
+            opts="-h --workdir --server --sshuser --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        deploy)
+            opts="-h -j --workdir --transport --login --target --jobs --all --server --root-key --create-users --min-size --dry-run --json --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        authorized-keys-command)
+            opts="-h --server --user --fingerprint --help"
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        serve)
+            opts="-h --listen --cache-ttl --help"
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
@@ -249,4 +364,4 @@ _oerec() {
 
 }
 
-complete -F _oerec oerec add-server list-server update-server delete-server add-servergroup list-servergroup update-servergroup delete-servergroup add-server-to-servergroup add-servergroup-to-servergroup delete-server-from-servergroup delete-servergroup-from-servergroup add-user list-user update-user delete-user add-key list-key update-key delete-key add-usergroup list-usergroup update-usergroup delete-usergroup add-user-to-usergroup add-usergroup-to-usergroup delete-user-from-usergroup delete-usergroup-from-usergroup add-serveraccess list-serveraccess update-serveraccess delete-serveraccess add-useraccess list-useraccess delete-useraccess enable-dns disable-dns enable-server disable-server enable-user disable-user write-serverauth
+complete -F _oerec oerec add-server list-server update-server delete-server add-servergroup list-servergroup update-servergroup delete-servergroup add-server-to-servergroup add-servergroup-to-servergroup delete-server-from-servergroup delete-servergroup-from-servergroup add-user list-user update-user delete-user add-key list-key update-key delete-key import-keys rotate-key block-key unblock-key list-blocked-keys add-usergroup list-usergroup update-usergroup delete-usergroup add-user-to-usergroup add-usergroup-to-usergroup delete-user-from-usergroup delete-usergroup-from-usergroup add-serveraccess list-serveraccess update-serveraccess delete-serveraccess add-useraccess list-useraccess delete-useraccess add-certauthority list-certauthority delete-certauthority add-certauthority-to-serveraccess delete-certauthority-from-serveraccess issue-cert enable-dns disable-dns enable-server disable-server enable-user disable-user add-server-token delete-server-token explain-access check-groups check-keys audit-keys verify-keys list-serverauth write-serverauth serverauth-history deploy serve authorized-keys-command
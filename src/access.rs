@@ -0,0 +1,680 @@
+use owo_colors::{OwoColorize, Stream::Stdout};
+use postgres::{Client, Error};
+use prettytable::{format, Table};
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::IpAddr;
+
+use crate::{ask_for, exit_with_message, ListObject};
+
+#[derive(Debug)]
+pub struct User {
+    pub email: String,
+    pub disabled: bool,
+}
+
+#[derive(Debug)]
+pub struct Server {
+    pub name: String,
+    pub ip: IpAddr,
+    pub disabled: bool,
+}
+
+#[derive(Debug)]
+pub struct Key {
+    pub id: i64,
+    pub sshkey: String,
+    pub fingerprint: String,
+    pub comment: Option<String>,
+    pub sshfrom: Option<String>,
+    pub sshcommand: Option<String>,
+    pub sshoption: Option<String>,
+    // best_before as YYYYMMDDHHMM (expiry-time= format), time-limited keys only
+    pub expiry_time: Option<String>,
+    pub expired: bool,
+    pub blocked: bool,
+}
+
+#[derive(Debug)]
+pub struct UserAccess {
+    pub id: i64,
+    pub user_id: Option<i64>,
+    pub usergroup_id: Option<i64>,
+    pub serveraccess_id: i64,
+    pub until: String,
+    // YYYYMMDDHHMM, time-limited access only
+    pub expiry_time: Option<String>,
+    pub expired: bool,
+}
+
+#[derive(Debug)]
+pub struct ServerAccess {
+    pub name: String,
+    pub sshuser: String,
+    pub sshfrom: Option<String>,
+    pub sshcommand: Option<String>,
+    pub sshoption: Option<String>,
+    pub server_id: Option<i64>,
+    pub servergroup_id: Option<i64>,
+    pub keytypes: Option<String>,
+    pub verify_required: bool,
+}
+
+#[derive(Debug)]
+pub struct CertAuthority {
+    pub name: String,
+    pub sshkey: String,
+    pub fingerprint: String,
+    pub comment: Option<String>,
+    pub blocked: bool,
+}
+
+// users, servers, groups and access entries (incl. disabled / expired ones)
+#[derive(Debug, Default)]
+pub struct AccessGraph {
+    pub users: HashMap<i64, User>,
+    pub usergroups: HashMap<i64, String>,
+    pub user_usergroups: HashMap<i64, Vec<i64>>,
+    pub usergroup_parents: HashMap<i64, Vec<i64>>,
+    pub keys: HashMap<i64, Vec<Key>>,
+    pub useraccess: Vec<UserAccess>,
+    pub serveraccess: HashMap<i64, ServerAccess>,
+    pub certauthorities: HashMap<i64, CertAuthority>,
+    pub serveraccess_certauthorities: HashMap<i64, Vec<i64>>,
+    pub servers: HashMap<i64, Server>,
+    pub servergroups: HashMap<i64, String>,
+    pub server_servergroups: HashMap<i64, Vec<i64>>,
+    pub servergroup_parents: HashMap<i64, Vec<i64>>,
+}
+
+#[derive(Debug, Serialize)]
+struct GrantChain {
+    pub email: String,
+    pub user_disabled: bool,
+    pub usergroups: Vec<String>,
+    pub useraccess: i64,
+    pub until: String,
+    pub expired: bool,
+    pub serveraccess: String,
+    pub sshuser: String,
+    pub servergroups: Vec<String>,
+    pub server: String,
+    pub ip: IpAddr,
+    pub server_disabled: bool,
+    pub active: bool,
+}
+
+pub fn load(pgclient: &mut Client) -> Result<AccessGraph, Error> {
+    let mut graph = AccessGraph::default();
+
+    for row in pgclient.query(r#"SELECT id, email, disabled FROM "user""#, &[])? {
+        graph.users.insert(
+            row.get("id"),
+            User {
+                email: row.get("email"),
+                disabled: row.get("disabled"),
+            },
+        );
+    }
+
+    for row in pgclient.query(r"SELECT id, name FROM usergroup", &[])? {
+        graph.usergroups.insert(row.get("id"), row.get("name"));
+    }
+
+    for row in pgclient.query(r"SELECT user_id, usergroup_id FROM user_usergroup", &[])? {
+        graph
+            .user_usergroups
+            .entry(row.get("user_id"))
+            .or_default()
+            .push(row.get("usergroup_id"));
+    }
+
+    for row in pgclient.query(
+        r"SELECT subgroup_id, supergroup_id FROM usergroup_usergroup",
+        &[],
+    )? {
+        graph
+            .usergroup_parents
+            .entry(row.get("subgroup_id"))
+            .or_default()
+            .push(row.get("supergroup_id"));
+    }
+
+    for row in pgclient.query(
+        r"SELECT id,
+                  user_id,
+                  sshkey,
+                  fingerprint,
+                  SUBSTRING(comment, 1, 64) AS comment,
+                  sshfrom,
+                  sshcommand,
+                  sshoption,
+                  CASE WHEN best_before < '2256-05-11' THEN TO_CHAR(best_before, 'YYYYMMDDHH24MI') END AS expiry_time,
+                  COALESCE(best_before <= NOW(), FALSE) AS expired,
+                  EXISTS (SELECT 1 FROM blocked_keys WHERE blocked_keys.fingerprint = sshkeys.fingerprint) AS blocked
+           FROM sshkeys
+           ORDER BY id",
+        &[],
+    )? {
+        graph.keys.entry(row.get("user_id")).or_default().push(Key {
+            id: row.get("id"),
+            sshkey: row.get("sshkey"),
+            fingerprint: row.get("fingerprint"),
+            comment: row.get("comment"),
+            sshfrom: row.get("sshfrom"),
+            sshcommand: row.get("sshcommand"),
+            sshoption: row.get("sshoption"),
+            expiry_time: row.get("expiry_time"),
+            expired: row.get("expired"),
+            blocked: row.get("blocked"),
+        });
+    }
+
+    for row in pgclient.query(
+        r"SELECT id,
+                  user_id,
+                  usergroup_id,
+                  serveraccess_id,
+                  best_before::VARCHAR AS until,
+                  CASE WHEN best_before < '2256-05-11' THEN TO_CHAR(best_before, 'YYYYMMDDHH24MI') END AS expiry_time,
+                  best_before <= NOW() AS expired
+           FROM useraccess
+           ORDER BY id",
+        &[],
+    )? {
+        graph.useraccess.push(UserAccess {
+            id: row.get("id"),
+            user_id: row.get("user_id"),
+            usergroup_id: row.get("usergroup_id"),
+            serveraccess_id: row.get("serveraccess_id"),
+            until: row.get("until"),
+            expiry_time: row.get("expiry_time"),
+            expired: row.get("expired"),
+        });
+    }
+
+    for row in pgclient.query(
+        r"SELECT id,
+                  name,
+                  sshuser,
+                  sshfrom,
+                  sshcommand,
+                  sshoption,
+                  server_id,
+                  servergroup_id,
+                  keytypes,
+                  verify_required
+           FROM serveraccess",
+        &[],
+    )? {
+        graph.serveraccess.insert(
+            row.get("id"),
+            ServerAccess {
+                name: row.get("name"),
+                sshuser: row.get("sshuser"),
+                sshfrom: row.get("sshfrom"),
+                sshcommand: row.get("sshcommand"),
+                sshoption: row.get("sshoption"),
+                server_id: row.get("server_id"),
+                servergroup_id: row.get("servergroup_id"),
+                keytypes: row.get("keytypes"),
+                verify_required: row.get("verify_required"),
+            },
+        );
+    }
+
+    for row in pgclient.query(
+        r"SELECT id,
+                  name,
+                  sshkey,
+                  fingerprint,
+                  SUBSTRING(comment, 1, 64) AS comment,
+                  EXISTS (SELECT 1 FROM blocked_keys WHERE blocked_keys.fingerprint = certauthority.fingerprint) AS blocked
+           FROM certauthority",
+        &[],
+    )? {
+        graph.certauthorities.insert(
+            row.get("id"),
+            CertAuthority {
+                name: row.get("name"),
+                sshkey: row.get("sshkey"),
+                fingerprint: row.get("fingerprint"),
+                comment: row.get("comment"),
+                blocked: row.get("blocked"),
+            },
+        );
+    }
+
+    for row in pgclient.query(
+        r"SELECT serveraccess_id, certauthority_id
+           FROM serveraccess_certauthority
+           ORDER BY certauthority_id",
+        &[],
+    )? {
+        graph
+            .serveraccess_certauthorities
+            .entry(row.get("serveraccess_id"))
+            .or_default()
+            .push(row.get("certauthority_id"));
+    }
+
+    for row in pgclient.query(r"SELECT id, name, ip, disabled FROM server", &[])? {
+        graph.servers.insert(
+            row.get("id"),
+            Server {
+                name: row.get("name"),
+                ip: row.get("ip"),
+                disabled: row.get("disabled"),
+            },
+        );
+    }
+
+    for row in pgclient.query(r"SELECT id, name FROM servergroup", &[])? {
+        graph.servergroups.insert(row.get("id"), row.get("name"));
+    }
+
+    for row in pgclient.query(
+        r"SELECT server_id, servergroup_id FROM server_servergroup",
+        &[],
+    )? {
+        graph
+            .server_servergroups
+            .entry(row.get("server_id"))
+            .or_default()
+            .push(row.get("servergroup_id"));
+    }
+
+    for row in pgclient.query(
+        r"SELECT subgroup_id, supergroup_id FROM servergroup_servergroup",
+        &[],
+    )? {
+        graph
+            .servergroup_parents
+            .entry(row.get("subgroup_id"))
+            .or_default()
+            .push(row.get("supergroup_id"));
+    }
+
+    Ok(graph)
+}
+
+// group itself and all of its (transitive) parent groups
+pub fn expand(
+    group: i64,
+    parents: &HashMap<i64, Vec<i64>>,
+    memo: &mut HashMap<i64, BTreeSet<i64>>,
+) -> BTreeSet<i64> {
+    if let Some(known) = memo.get(&group) {
+        return known.clone();
+    }
+
+    let mut seen = BTreeSet::from([group]);
+    let mut stack = vec![group];
+
+    while let Some(g) = stack.pop() {
+        for parent in parents.get(&g).into_iter().flatten() {
+            if seen.contains(parent) {
+                continue;
+            }
+
+            // already expanded groups are complete, no need to walk them again
+            if let Some(known) = memo.get(parent) {
+                seen.extend(known);
+            } else {
+                seen.insert(*parent);
+                stack.push(*parent);
+            }
+        }
+    }
+
+    memo.insert(group, seen.clone());
+
+    seen
+}
+
+// every path from the given (direct) groups to their (transitive) parent groups
+fn group_paths(groups: &[i64], parents: &HashMap<i64, Vec<i64>>) -> Vec<Vec<i64>> {
+    let mut paths = Vec::new();
+    let mut stack = groups.iter().map(|g| vec![*g]).collect::<Vec<Vec<i64>>>();
+
+    while let Some(path) = stack.pop() {
+        let last = path[path.len() - 1];
+
+        for parent in parents.get(&last).into_iter().flatten() {
+            // cycles end the path
+            if !path.contains(parent) {
+                let mut next = path.clone();
+                next.push(*parent);
+                stack.push(next);
+            }
+        }
+
+        paths.push(path);
+    }
+
+    paths
+}
+
+// one cycle per back edge found in a depth-first walk over the parent links
+pub fn find_cycles(parents: &HashMap<i64, Vec<i64>>) -> Vec<Vec<i64>> {
+    let mut cycles = BTreeSet::new();
+    let mut done = BTreeSet::new();
+
+    let mut groups = parents.keys().copied().collect::<Vec<i64>>();
+    groups.sort_unstable();
+
+    for group in groups {
+        if done.contains(&group) {
+            continue;
+        }
+
+        // (group, index of the next parent to visit)
+        let mut stack: Vec<(i64, usize)> = vec![(group, 0)];
+
+        while let Some((g, i)) = stack.last().copied() {
+            let next = parents.get(&g).and_then(|p| p.get(i)).copied();
+
+            let Some(next) = next else {
+                done.insert(g);
+                stack.pop();
+                continue;
+            };
+
+            if let Some(top) = stack.last_mut() {
+                top.1 += 1;
+            }
+
+            if let Some(pos) = stack.iter().position(|(x, _)| *x == next) {
+                let mut cycle = stack[pos..].iter().map(|(x, _)| *x).collect::<Vec<i64>>();
+
+                // start at the smallest id, so every cycle is reported once
+                if let Some(min) = cycle
+                    .iter()
+                    .min()
+                    .and_then(|m| cycle.iter().position(|x| x == m))
+                {
+                    cycle.rotate_left(min);
+                }
+
+                cycles.insert(cycle);
+            } else if !done.contains(&next) {
+                stack.push((next, 0));
+            }
+        }
+    }
+
+    cycles.into_iter().collect()
+}
+
+fn group_names(path: &[i64], names: &HashMap<i64, String>) -> Vec<String> {
+    path.iter()
+        .map(|id| names.get(id).cloned().unwrap_or_else(|| id.to_string()))
+        .collect()
+}
+
+fn grant_chains(
+    graph: &AccessGraph,
+    userid: i64,
+    serverid: i64,
+    sshuser: Option<&str>,
+) -> Vec<GrantChain> {
+    let mut chains = Vec::new();
+
+    let (Some(user), Some(server)) = (graph.users.get(&userid), graph.servers.get(&serverid))
+    else {
+        return chains;
+    };
+
+    // user -> usergroup -> parent usergroups -> useraccess
+    let mut userpaths: Vec<(&UserAccess, Vec<i64>)> = Vec::new();
+
+    for ua in &graph.useraccess {
+        if ua.user_id == Some(userid) {
+            userpaths.push((ua, Vec::new()));
+        }
+    }
+
+    let usergroups = graph
+        .user_usergroups
+        .get(&userid)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    for path in group_paths(usergroups, &graph.usergroup_parents) {
+        let last = path[path.len() - 1];
+        for ua in &graph.useraccess {
+            if ua.usergroup_id == Some(last) {
+                userpaths.push((ua, path.clone()));
+            }
+        }
+    }
+
+    // servergroup -> parent servergroups -> server
+    let servergroups = graph
+        .server_servergroups
+        .get(&serverid)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let serverpaths = group_paths(servergroups, &graph.servergroup_parents);
+
+    for (ua, userpath) in userpaths {
+        let Some(sa) = graph.serveraccess.get(&ua.serveraccess_id) else {
+            continue;
+        };
+
+        if let Some(sshuser) = sshuser {
+            if !sa.sshuser.eq(sshuser) {
+                continue;
+            }
+        }
+
+        let mut paths: Vec<Vec<i64>> = Vec::new();
+
+        if sa.server_id == Some(serverid) {
+            paths.push(Vec::new());
+        }
+
+        if let Some(sgid) = sa.servergroup_id {
+            for path in serverpaths.iter().filter(|p| p[p.len() - 1] == sgid) {
+                // display the way down from serveraccess to server
+                paths.push(path.iter().rev().copied().collect());
+            }
+        }
+
+        for path in paths {
+            chains.push(GrantChain {
+                email: user.email.clone(),
+                user_disabled: user.disabled,
+                usergroups: group_names(&userpath, &graph.usergroups),
+                useraccess: ua.id,
+                until: ua.until.clone(),
+                expired: ua.expired,
+                serveraccess: sa.name.clone(),
+                sshuser: sa.sshuser.clone(),
+                servergroups: group_names(&path, &graph.servergroups),
+                server: server.name.clone(),
+                ip: server.ip,
+                server_disabled: server.disabled,
+                active: !user.disabled && !ua.expired && !server.disabled,
+            });
+        }
+    }
+
+    chains
+}
+
+pub fn explain(
+    pgclient: &mut Client,
+    email: Option<&str>,
+    servername: Option<&str>,
+    sshuser: Option<&str>,
+    json: bool,
+) -> Result<(), Error> {
+    let email = ask_for(&ListObject::UserEmail, email, None, pgclient);
+
+    if email.is_empty() {
+        exit_with_message("User email cannot be empty.");
+    }
+
+    let servername = ask_for(&ListObject::ServerName, servername, None, pgclient);
+
+    if servername.is_empty() {
+        exit_with_message("Server name cannot be empty.");
+    }
+
+    let graph = load(pgclient)?;
+
+    let Some(userid) = graph
+        .users
+        .iter()
+        .find(|(_, u)| u.email.eq(&email))
+        .map(|(id, _)| *id)
+    else {
+        exit_with_message("User not found.")
+    };
+
+    let Some(serverid) = graph
+        .servers
+        .iter()
+        .find(|(_, s)| s.name.eq(&servername) || s.ip.to_string().eq(&servername))
+        .map(|(id, _)| *id)
+    else {
+        exit_with_message("Server not found.")
+    };
+
+    let mut chains = grant_chains(&graph, userid, serverid, sshuser);
+    chains.sort_by(|a, b| {
+        (&a.sshuser, &a.serveraccess, a.useraccess).cmp(&(
+            &b.sshuser,
+            &b.serveraccess,
+            b.useraccess,
+        ))
+    });
+
+    if json {
+        println!("{}", serde_json::to_string(&chains).unwrap_or_default());
+        return Ok(());
+    }
+
+    if chains.is_empty() {
+        println!("No access found for '{email}' on '{servername}'.");
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+    table.set_titles(row![
+        "email",
+        "user groups",
+        "useraccess",
+        "until",
+        "serveraccess",
+        "ssh user",
+        "server groups",
+        "server",
+        "status"
+    ]);
+
+    for c in chains {
+        let mut status = Vec::new();
+        if c.user_disabled {
+            status.push("user disabled");
+        }
+        if c.expired {
+            status.push("expired");
+        }
+        if c.server_disabled {
+            status.push("server disabled");
+        }
+
+        let status = if status.is_empty() {
+            "active"
+                .if_supports_color(Stdout, owo_colors::OwoColorize::green)
+                .to_string()
+        } else {
+            status
+                .join(", ")
+                .if_supports_color(Stdout, owo_colors::OwoColorize::red)
+                .to_string()
+        };
+
+        let usergroups = if c.usergroups.is_empty() {
+            "-".to_string()
+        } else {
+            c.usergroups.join(" -> ")
+        };
+
+        let servergroups = if c.servergroups.is_empty() {
+            "-".to_string()
+        } else {
+            c.servergroups.join(" -> ")
+        };
+
+        table.add_row(row![
+            c.email,
+            usergroups,
+            c.useraccess,
+            c.until,
+            c.serveraccess,
+            c.sshuser,
+            servergroups,
+            format!("{} ({})", c.server, c.ip),
+            status
+        ]);
+    }
+
+    table.printstd();
+
+    Ok(())
+}
+
+pub fn check_groups(pgclient: &mut Client, json: bool) -> Result<(), Error> {
+    let graph = load(pgclient)?;
+
+    let mut cycles: BTreeMap<&str, Vec<Vec<String>>> = BTreeMap::new();
+
+    cycles.insert(
+        "usergroup",
+        find_cycles(&graph.usergroup_parents)
+            .iter()
+            .map(|c| group_names(c, &graph.usergroups))
+            .collect(),
+    );
+    cycles.insert(
+        "servergroup",
+        find_cycles(&graph.servergroup_parents)
+            .iter()
+            .map(|c| group_names(c, &graph.servergroups))
+            .collect(),
+    );
+
+    let found = cycles.values().any(|c| !c.is_empty());
+
+    if json {
+        println!("{}", serde_json::to_string(&cycles).unwrap_or_default());
+    } else if found {
+        for (kind, list) in &cycles {
+            for cycle in list {
+                println!(
+                    "{} {} cycle: {} -> {}",
+                    "warning:".if_supports_color(Stdout, owo_colors::OwoColorize::yellow),
+                    kind,
+                    cycle.join(" -> "),
+                    cycle[0]
+                );
+            }
+        }
+    } else {
+        println!(
+            "{}",
+            "No cycles found.".if_supports_color(Stdout, owo_colors::OwoColorize::green)
+        );
+    }
+
+    if found {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use base64::{engine, Engine};
+use log::info;
+use owo_colors::{OwoColorize, Stream::Stdout};
+use postgres::{Client, Error};
+use prettytable::{format, Table};
+use serde_derive::Serialize;
+use std::io::{self, BufRead, Write};
+
+use crate::key::{generate_fingerprint, key_line};
+use crate::logging::get_ssh_client;
+use crate::metadata::Metadata;
+use crate::{exit_with_message, set_or_ask_for};
+
+#[derive(Debug, Serialize)]
+struct BlockedKeyQuery {
+    pub fingerprint: String,
+    pub comment: Option<String>,
+    pub keys: Vec<i64>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
+}
+
+// SHA256:<43 chars base64 w/o padding>, as printed by ssh-keygen -l
+pub fn valid_fingerprint(fingerprint: &str) -> bool {
+    fingerprint.strip_prefix("SHA256:").is_some_and(|hash| {
+        engine::general_purpose::STANDARD_NO_PAD
+            .decode(hash)
+            .is_ok_and(|h| h.len() == 32)
+    })
+}
+
+pub fn is_blocked(pgclient: &mut Client, fingerprint: &str) -> Result<bool, Error> {
+    Ok(!pgclient
+        .query(
+            r"SELECT id FROM blocked_keys WHERE fingerprint = $1 LIMIT 1",
+            &[&fingerprint],
+        )?
+        .is_empty())
+}
+
+pub fn block(
+    pgclient: &mut Client,
+    fingerprint: Option<&str>,
+    publickey: Option<&str>,
+    file: Option<&str>,
+    comment: Option<&str>,
+) -> Result<(), Error> {
+    if let Some(file) = file {
+        return block_file(pgclient, file, comment);
+    }
+
+    println!("Block public SSH key");
+    let query_string = r"INSERT INTO blocked_keys (fingerprint, comment, created_by)
+                          VALUES ($1, $2, $3)";
+
+    let newfingerprint = match publickey {
+        Some(publickey) => generate_fingerprint(&key_line(publickey).key()),
+        None => set_or_ask_for(fingerprint, "Fingerprint (SHA256:...)"),
+    };
+
+    if newfingerprint.is_empty() {
+        exit_with_message("Fingerprint cannot be empty.");
+    }
+
+    if !valid_fingerprint(&newfingerprint) {
+        exit_with_message("Invalid fingerprint (expected SHA256:...).");
+    }
+
+    if is_blocked(pgclient, &newfingerprint)? {
+        exit_with_message("Key already blocked.");
+    }
+
+    let newcomment = set_or_ask_for(comment, "Comment (e.g. incident reference)");
+    let newcomment = match &newcomment.trim().to_lowercase()[..] {
+        "" | "null" => None,
+        _ => Some(newcomment),
+    };
+
+    pgclient.query(
+        query_string,
+        &[&newfingerprint, &newcomment, &get_ssh_client()],
+    )?;
+
+    info!(
+        "({}) Blocked SSH key '{}'",
+        &get_ssh_client(),
+        &newfingerprint
+    );
+
+    // still attached keys stay in the database, but are no longer deployed
+    for row in pgclient.query(
+        r#"SELECT sshkeys.id, "user".email
+           FROM sshkeys, "user"
+           WHERE sshkeys.user_id = "user".id
+             AND sshkeys.fingerprint = $1
+           ORDER BY sshkeys.id"#,
+        &[&newfingerprint],
+    )? {
+        println!(
+            "{} Key is registered for user '{}' (ID {}), it won't be deployed anymore.",
+            "warning:".if_supports_color(Stdout, owo_colors::OwoColorize::yellow),
+            row.get::<&str, String>("email"),
+            row.get::<&str, i64>("id")
+        );
+    }
+
+    println!(
+        "{}",
+        "Done.".if_supports_color(Stdout, owo_colors::OwoColorize::green)
+    );
+
+    Ok(())
+}
+
+// one fingerprint per line ('#': comment), the first SHA256:... field counts,
+// so ssh-keygen -l output works, too
+fn block_file(pgclient: &mut Client, file: &str, comment: Option<&str>) -> Result<(), Error> {
+    let lines = if file.eq("-") {
+        io::stdin()
+            .lock()
+            .lines()
+            .collect::<Result<Vec<String>, _>>()
+    } else {
+        std::fs::File::open(file).and_then(|f| io::BufReader::new(f).lines().collect())
+    };
+
+    let Ok(lines) = lines else {
+        exit_with_message(&format!("Could not read '{file}'."))
+    };
+
+    let (mut blocked, mut skipped, mut invalid) = (0, 0, 0);
+
+    let mut transaction = pgclient.transaction()?;
+
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+
+        let fingerprint = line
+            .split_whitespace()
+            .find(|f| f.starts_with("SHA256:"))
+            .unwrap_or_default();
+
+        if !valid_fingerprint(fingerprint) {
+            eprintln!(
+                "{} line {}: invalid fingerprint",
+                "warning:".if_supports_color(Stdout, owo_colors::OwoColorize::yellow),
+                i + 1
+            );
+            invalid += 1;
+            continue;
+        }
+
+        let res = transaction.execute(
+            r"INSERT INTO blocked_keys (fingerprint, comment, created_by)
+              VALUES ($1, $2, $3)
+              ON CONFLICT (fingerprint) DO NOTHING",
+            &[&fingerprint, &comment, &get_ssh_client()],
+        )?;
+
+        if res == 0 {
+            skipped += 1;
+        } else {
+            info!("({}) Blocked SSH key '{}'", &get_ssh_client(), &fingerprint);
+            blocked += 1;
+        }
+    }
+
+    transaction.commit()?;
+
+    println!("{blocked} blocked, {skipped} already blocked, {invalid} invalid");
+
+    if invalid > 0 {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+pub fn unblock(pgclient: &mut Client, fingerprint: Option<&str>, force: bool) -> Result<(), Error> {
+    println!("Unblock public SSH key");
+    let query_string = r"DELETE FROM blocked_keys WHERE fingerprint = $1";
+
+    let oldfingerprint = set_or_ask_for(fingerprint, "Fingerprint (SHA256:...)");
+
+    if oldfingerprint.is_empty() {
+        exit_with_message("Fingerprint cannot be empty.");
+    }
+
+    if !is_blocked(pgclient, &oldfingerprint)? {
+        exit_with_message("Key not blocked.");
+    }
+
+    if !force {
+        println!();
+        print!("Do you really want to unblock key '{oldfingerprint}'? [y/N]: ");
+        let mut userinput = String::new();
+        io::stdout().flush().unwrap();
+        io::stdin().read_line(&mut userinput).unwrap();
+        if !userinput.trim().to_lowercase().eq("y") {
+            println!("Operation cancelled.");
+            std::process::exit(1);
+        }
+    }
+
+    pgclient.query(query_string, &[&oldfingerprint])?;
+
+    info!(
+        "({}) Unblocked SSH key '{}'",
+        &get_ssh_client(),
+        &oldfingerprint
+    );
+
+    println!(
+        "{}",
+        "Done.".if_supports_color(Stdout, owo_colors::OwoColorize::green)
+    );
+
+    Ok(())
+}
+
+pub fn list(pgclient: &mut Client, fingerprint: Option<&str>, json: bool) -> Result<(), Error> {
+    let query_string = r"SELECT blocked_keys.fingerprint,
+                                 blocked_keys.comment,
+                                 ARRAY_REMOVE(ARRAY_AGG(sshkeys.id ORDER BY sshkeys.id), NULL) AS keys,
+                                 blocked_keys.created_at::TIMESTAMP(0)::VARCHAR AS created_at,
+                                 blocked_keys.created_by
+                          FROM blocked_keys
+                          LEFT JOIN sshkeys ON sshkeys.fingerprint = blocked_keys.fingerprint
+                          GROUP BY blocked_keys.id
+                          ORDER BY blocked_keys.created_at, blocked_keys.fingerprint";
+
+    let mut res = Vec::new();
+
+    for row in pgclient.query(query_string, &[])? {
+        res.push(BlockedKeyQuery {
+            fingerprint: row.get("fingerprint"),
+            comment: row.get("comment"),
+            keys: row.get("keys"),
+            metadata: Metadata {
+                created_at: row.get("created_at"),
+                created_by: row.get("created_by"),
+                modified_at: None,
+                modified_by: None,
+            },
+        });
+    }
+
+    if let Some(fingerprint) = fingerprint {
+        res.retain(|x| x.fingerprint.contains(fingerprint));
+    }
+
+    if res.is_empty() {
+        return Ok(());
+    }
+
+    if json {
+        println!("{}", serde_json::to_string(&res).unwrap_or_default());
+    } else {
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+        table.set_titles(row!["fingerprint", "registered keys", "comment", "added"]);
+
+        for r in res {
+            let keys = if r.keys.is_empty() {
+                "-".to_string()
+            } else {
+                r.keys
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            };
+            table.add_row(row![
+                r.fingerprint,
+                keys,
+                r.comment.unwrap_or_else(|| "-".to_string()),
+                r.metadata.added(),
+            ]);
+        }
+
+        table.printstd();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_fingerprint_expects_sha256() {
+        assert!(valid_fingerprint(
+            "SHA256:dS8pN5W+O+OGFdkCTH27BxkcwWeK5agmJ24oeh0ktZ0"
+        ));
+        assert!(!valid_fingerprint(
+            "dS8pN5W+O+OGFdkCTH27BxkcwWeK5agmJ24oeh0ktZ0"
+        ));
+        assert!(!valid_fingerprint("SHA256:dS8pN5W+O+OGFdkCTH27Bxkc"));
+        assert!(!valid_fingerprint(
+            "MD5:16:27:ac:a5:76:28:2d:36:63:1b:56:4d:eb:df:a6:48"
+        ));
+    }
+}
@@ -0,0 +1,828 @@
+use log::info;
+use owo_colors::{OwoColorize, Stream::Stdout};
+use postgres::{Client, Error};
+use prettytable::{format, Table};
+use serde_derive::Serialize;
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::access::{expand, load};
+use crate::blocklist::is_blocked;
+use crate::key::{generate_fingerprint, key_line};
+use crate::logging::get_ssh_client;
+use crate::metadata::Metadata;
+use crate::serveraccess::key_policy;
+use crate::serverauth::{merge_options, parse_cidr, split_options, KeyOptions};
+use crate::{ask_for, exit_with_message, parse_duration, set_or_ask_for, ListObject};
+
+#[derive(Debug, Serialize)]
+struct CertAuthorityQuery {
+    pub name: String,
+    pub sshkey: String,
+    pub fingerprint: String,
+    pub serveraccess: Vec<String>,
+    pub comment: Option<String>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
+}
+
+pub fn add(
+    pgclient: &mut Client,
+    name: Option<&str>,
+    publickey: Option<&str>,
+    comment: Option<&str>,
+) -> Result<(), Error> {
+    println!("Add certificate authority");
+    let query_string = r"INSERT INTO certauthority (name, sshkey, fingerprint, comment, created_by)
+                          VALUES ($1, $2, $3, $4, $5)";
+
+    let newname = ask_for(&ListObject::CertAuthority, name, None, pgclient);
+
+    if newname.is_empty() {
+        exit_with_message("Certificate authority name cannot be empty.");
+    }
+
+    if !pgclient
+        .query(
+            r"SELECT id FROM certauthority WHERE name = $1 LIMIT 1",
+            &[&newname],
+        )?
+        .is_empty()
+    {
+        exit_with_message("Certificate authority name already in use.");
+    }
+
+    let newkey = set_or_ask_for(publickey, "Public SSH key (of the CA)");
+
+    if newkey.is_empty() {
+        exit_with_message("Key cannot be empty.");
+    }
+
+    let keyline = key_line(&newkey);
+    let newkey = keyline.key();
+    let fingerprint = generate_fingerprint(&newkey);
+
+    if is_blocked(pgclient, &fingerprint)? {
+        exit_with_message("Key is blocked (known-compromised).");
+    }
+
+    let res = pgclient.query(
+        r"SELECT name FROM certauthority WHERE fingerprint = $1 LIMIT 1",
+        &[&fingerprint],
+    )?;
+
+    if !res.is_empty() {
+        exit_with_message(&format!(
+            "Key already in use for certificate authority '{}'.",
+            res[0].get::<&str, String>("name")
+        ));
+    }
+
+    let newcomment = set_or_ask_for(comment, "Comment");
+    let newcomment = match &newcomment.trim().to_lowercase()[..] {
+        "" | "null" => None,
+        _ => Some(newcomment),
+    };
+
+    pgclient.query(
+        query_string,
+        &[
+            &newname,
+            &newkey,
+            &fingerprint,
+            &newcomment,
+            &get_ssh_client(),
+        ],
+    )?;
+
+    info!(
+        "({}) Added certificate authority '{}' ({})",
+        &get_ssh_client(),
+        &newname,
+        &fingerprint
+    );
+
+    println!(
+        "{}",
+        "Done.".if_supports_color(Stdout, owo_colors::OwoColorize::green)
+    );
+
+    if name.is_none() && publickey.is_none() && comment.is_none() {
+        loop {
+            println!();
+            print!("Do you want to add this certificate authority to a server access? [Y/n]: ");
+            let mut userinput = String::new();
+            io::stdout().flush().unwrap();
+            io::stdin().read_line(&mut userinput).unwrap();
+            if userinput.trim().to_lowercase().eq("n") {
+                break;
+            }
+            add_to_serveraccess(pgclient, Some(&newname), None)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn add_to_serveraccess(
+    pgclient: &mut Client,
+    name: Option<&str>,
+    serveraccess: Option<&str>,
+) -> Result<(), Error> {
+    println!("Add certificate authority to server access");
+    let query_string = r"INSERT INTO serveraccess_certauthority (serveraccess_id, certauthority_id)
+                          SELECT serveraccess.id,
+                            (SELECT certauthority.id
+                             FROM certauthority
+                             WHERE certauthority.name = $1)
+                          FROM serveraccess
+                          WHERE serveraccess.name = $2";
+
+    let newname = ask_for(&ListObject::CertAuthority, name, None, pgclient);
+
+    if newname.is_empty() {
+        exit_with_message("Certificate authority name cannot be empty.");
+    }
+
+    if pgclient
+        .query(
+            r"SELECT id FROM certauthority WHERE name = $1 LIMIT 1",
+            &[&newname],
+        )?
+        .is_empty()
+    {
+        exit_with_message("Certificate authority not found.");
+    }
+
+    let newserveraccess = ask_for(&ListObject::ServerAccess, serveraccess, None, pgclient);
+
+    if newserveraccess.is_empty() {
+        exit_with_message("Server access name cannot be empty.");
+    }
+
+    if pgclient
+        .query(
+            r"SELECT id FROM serveraccess WHERE name = $1 LIMIT 1",
+            &[&newserveraccess],
+        )?
+        .is_empty()
+    {
+        exit_with_message("Server access not found.");
+    }
+
+    let res = pgclient.query(
+        r"SELECT serveraccess_id
+           FROM serveraccess_certauthority
+           JOIN certauthority ON certauthority.id = certauthority_id
+           JOIN serveraccess ON serveraccess.id = serveraccess_id
+           WHERE certauthority.name = $1
+             AND serveraccess.name = $2",
+        &[&newname, &newserveraccess],
+    )?;
+
+    if res.is_empty() {
+        pgclient.query(query_string, &[&newname, &newserveraccess])?;
+    } else {
+        println!();
+        println!(
+            "{} Certificate authority already in server access.",
+            "warning:".if_supports_color(Stdout, owo_colors::OwoColorize::yellow)
+        );
+    }
+
+    info!(
+        "({}) Added certificate authority '{}' to server access '{}'",
+        &get_ssh_client(),
+        &newname,
+        &newserveraccess
+    );
+
+    println!(
+        "{}",
+        "Done.".if_supports_color(Stdout, owo_colors::OwoColorize::green)
+    );
+
+    Ok(())
+}
+
+pub fn delete(pgclient: &mut Client, name: Option<&str>, force: bool) -> Result<(), Error> {
+    println!("Delete certificate authority");
+    let query_string = r"DELETE FROM certauthority WHERE name = $1";
+
+    let oldname = ask_for(&ListObject::CertAuthority, name, None, pgclient);
+
+    if oldname.is_empty() {
+        exit_with_message("Certificate authority name cannot be empty.");
+    }
+
+    if pgclient
+        .query(
+            r"SELECT id FROM certauthority WHERE name = $1 LIMIT 1",
+            &[&oldname],
+        )?
+        .is_empty()
+    {
+        exit_with_message("Certificate authority not found.");
+    }
+
+    if !force {
+        println!();
+        print!("Do you really want to delete certificate authority '{oldname}'? [y/N]: ");
+        let mut userinput = String::new();
+        io::stdout().flush().unwrap();
+        io::stdin().read_line(&mut userinput).unwrap();
+        if !userinput.trim().to_lowercase().eq("y") {
+            println!("Operation cancelled.");
+            std::process::exit(1);
+        }
+    }
+
+    pgclient.query(query_string, &[&oldname])?;
+
+    info!(
+        "({}) Deleted certificate authority '{}'",
+        &get_ssh_client(),
+        &oldname
+    );
+
+    println!(
+        "{}",
+        "Done.".if_supports_color(Stdout, owo_colors::OwoColorize::green)
+    );
+
+    Ok(())
+}
+
+pub fn delete_from_serveraccess(
+    pgclient: &mut Client,
+    name: Option<&str>,
+    serveraccess: Option<&str>,
+    force: bool,
+) -> Result<(), Error> {
+    println!("Delete certificate authority from server access");
+    let query_string = r"DELETE
+                          FROM serveraccess_certauthority
+                          WHERE (serveraccess_id,
+                                 certauthority_id) =
+                            (SELECT serveraccess_id,
+                                    certauthority_id
+                             FROM serveraccess_certauthority
+                             JOIN certauthority ON certauthority.id = certauthority_id
+                             JOIN serveraccess ON serveraccess.id = serveraccess_id
+                             WHERE certauthority.name = $1
+                               AND serveraccess.name = $2)";
+
+    let oldname = ask_for(&ListObject::CertAuthority, name, None, pgclient);
+
+    if oldname.is_empty() {
+        exit_with_message("Certificate authority name cannot be empty.");
+    }
+
+    let oldserveraccess = ask_for(&ListObject::ServerAccess, serveraccess, None, pgclient);
+
+    if oldserveraccess.is_empty() {
+        exit_with_message("Server access name cannot be empty.");
+    }
+
+    if pgclient
+        .query(
+            r"SELECT serveraccess_id
+               FROM serveraccess_certauthority
+               JOIN certauthority ON certauthority.id = certauthority_id
+               JOIN serveraccess ON serveraccess.id = serveraccess_id
+               WHERE certauthority.name = $1
+                 AND serveraccess.name = $2",
+            &[&oldname, &oldserveraccess],
+        )?
+        .is_empty()
+    {
+        exit_with_message("Certificate authority not in server access.");
+    }
+
+    if !force {
+        println!();
+        print!(
+            "Do you really want to remove certificate authority '{oldname}' from server access '{oldserveraccess}'? [y/N]: "
+        );
+        let mut userinput = String::new();
+        io::stdout().flush().unwrap();
+        io::stdin().read_line(&mut userinput).unwrap();
+        if !userinput.trim().to_lowercase().eq("y") {
+            println!("Operation cancelled.");
+            std::process::exit(1);
+        }
+    }
+
+    pgclient.query(query_string, &[&oldname, &oldserveraccess])?;
+
+    info!(
+        "({}) Deleted certificate authority '{}' from server access '{}'",
+        &get_ssh_client(),
+        &oldname,
+        &oldserveraccess
+    );
+
+    println!(
+        "{}",
+        "Done.".if_supports_color(Stdout, owo_colors::OwoColorize::green)
+    );
+
+    Ok(())
+}
+
+pub fn list(
+    pgclient: &mut Client,
+    name: Option<&str>,
+    serveraccess: Option<&str>,
+    exact: bool,
+    json: bool,
+) -> Result<(), Error> {
+    let query_string = r"SELECT certauthority.name,
+                                 certauthority.sshkey,
+                                 certauthority.fingerprint,
+                                 ARRAY_REMOVE(ARRAY_AGG(serveraccess.name ORDER BY serveraccess.name), NULL) AS serveraccess,
+                                 certauthority.comment,
+                                 certauthority.created_at::TIMESTAMP(0)::VARCHAR AS created_at,
+                                 certauthority.created_by,
+                                 certauthority.modified_at::TIMESTAMP(0)::VARCHAR AS modified_at,
+                                 certauthority.modified_by
+                          FROM certauthority
+                          LEFT JOIN serveraccess_certauthority ON certauthority.id = certauthority_id
+                          LEFT JOIN serveraccess ON serveraccess.id = serveraccess_id
+                          GROUP BY certauthority.id
+                          ORDER BY certauthority.name";
+
+    let mut res = Vec::new();
+
+    for row in pgclient.query(query_string, &[])? {
+        res.push(CertAuthorityQuery {
+            name: row.get("name"),
+            sshkey: row.get("sshkey"),
+            fingerprint: row.get("fingerprint"),
+            serveraccess: row.get("serveraccess"),
+            comment: row.get("comment"),
+            metadata: Metadata::from_row(&row),
+        });
+    }
+
+    if let Some(name) = name {
+        if exact {
+            res.retain(|x| x.name.eq(&name));
+        } else {
+            res.retain(|x| x.name.to_lowercase().contains(&name.to_lowercase()));
+        }
+    }
+
+    if let Some(serveraccess) = serveraccess {
+        if exact {
+            res.retain(|x| x.serveraccess.iter().any(|s| s.eq(&serveraccess)));
+        } else {
+            res.retain(|x| {
+                x.serveraccess
+                    .iter()
+                    .any(|s| s.to_lowercase().contains(&serveraccess.to_lowercase()))
+            });
+        }
+    }
+
+    if res.is_empty() {
+        return Ok(());
+    }
+
+    if json {
+        println!("{}", serde_json::to_string(&res).unwrap_or_default());
+    } else {
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+        table.set_titles(row![
+            "name",
+            "fingerprint",
+            "serveraccess",
+            "comment",
+            "added"
+        ]);
+
+        for r in res {
+            let serveraccess = if r.serveraccess.is_empty() {
+                "-".to_string()
+            } else {
+                r.serveraccess.join(", ")
+            };
+            table.add_row(row![
+                r.name,
+                r.fingerprint,
+                serveraccess,
+                r.comment.unwrap_or_else(|| "-".to_string()),
+                r.metadata.added(),
+            ]);
+        }
+
+        table.printstd();
+    }
+
+    Ok(())
+}
+
+// serveraccess & key restrictions as ssh-keygen -O options ('clear' first, it resets the extensions),
+// the key's best_before caps the validity instead
+fn cert_options(key_options: &KeyOptions) -> Result<Vec<String>, String> {
+    let mut options = Vec::new();
+
+    if let Some(option) = &key_options.sshoption {
+        for o in split_options(option) {
+            match &o.to_lowercase()[..] {
+                "restrict" => options.insert(0, "clear".to_string()),
+                o @ ("no-pty"
+                | "no-port-forwarding"
+                | "no-agent-forwarding"
+                | "no-x11-forwarding"
+                | "no-user-rc"
+                | "no-touch-required"
+                | "verify-required") => options.push(o.to_string()),
+                _ => return Err(format!("sshoption '{o}' has no certificate equivalent")),
+            }
+        }
+    }
+
+    // source-address only takes addresses & CIDR ranges (no host name patterns)
+    if let Some(from) = &key_options.sshfrom {
+        let from = from.split(',').map(str::trim).collect::<Vec<&str>>();
+
+        if !from.iter().all(|f| parse_cidr(f).is_some()) {
+            return Err(format!(
+                "sshfrom '{}' is not a list of addresses",
+                from.join(",")
+            ));
+        }
+
+        options.push(format!("source-address={}", from.join(",")));
+    }
+
+    if let Some(command) = &key_options.sshcommand {
+        options.push(format!("force-command={command}"));
+    }
+
+    Ok(options)
+}
+
+// fresh directory only we can access (fails if the path exists), the key to sign must not be
+// replaceable by other local users before ssh-keygen reads it
+fn private_temp_dir() -> io::Result<PathBuf> {
+    use std::collections::hash_map::RandomState;
+    use std::hash::BuildHasher;
+    use std::os::unix::fs::DirBuilderExt;
+
+    let mut error = io::Error::from(io::ErrorKind::AlreadyExists);
+
+    for _ in 0..8 {
+        let random = RandomState::new().hash_one(std::process::id());
+        let dir = std::env::temp_dir().join(format!("oerec-cert-{random:016x}"));
+
+        match fs::DirBuilder::new().mode(0o700).create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => error = e,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(error)
+}
+
+fn ssh_keygen(args: &[&OsStr]) -> Result<String, String> {
+    let output = Command::new("ssh-keygen")
+        .args(args)
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+pub fn issue(
+    pgclient: &mut Client,
+    email: Option<&str>,
+    serveraccess: Option<&str>,
+    keyid: Option<&str>,
+    validity: &str,
+    cakey: &OsStr,
+    output: Option<&OsStr>,
+) -> Result<(), Error> {
+    let Some(validity) = parse_duration(validity).filter(|v| v.is_positive()) else {
+        exit_with_message("Could not parse validity (e.g. 30m, 8h, 7d).")
+    };
+
+    let email = ask_for(&ListObject::UserEmail, email, None, pgclient);
+
+    if email.is_empty() {
+        exit_with_message("User email cannot be empty.");
+    }
+
+    let serveraccess = ask_for(&ListObject::ServerAccess, serveraccess, None, pgclient);
+
+    if serveraccess.is_empty() {
+        exit_with_message("Server access name cannot be empty.");
+    }
+
+    let graph = load(pgclient)?;
+
+    let Some((userid, user)) = graph.users.iter().find(|(_, u)| u.email.eq(&email)) else {
+        exit_with_message("User not found.")
+    };
+
+    if user.disabled {
+        exit_with_message("User is disabled.");
+    }
+
+    let Some((said, sa)) = graph
+        .serveraccess
+        .iter()
+        .find(|(_, sa)| sa.name.eq(&serveraccess))
+    else {
+        exit_with_message("Server access not found.")
+    };
+
+    // direct grants & grants to (parents of) the user's groups
+    let mut memo = std::collections::HashMap::new();
+    let usergroups = graph
+        .user_usergroups
+        .get(userid)
+        .into_iter()
+        .flatten()
+        .flat_map(|g| expand(*g, &graph.usergroup_parents, &mut memo))
+        .collect::<BTreeSet<i64>>();
+
+    let useraccess = graph
+        .useraccess
+        .iter()
+        .filter(|ua| ua.serveraccess_id == *said && !ua.expired)
+        .filter(|ua| {
+            ua.user_id == Some(*userid) || ua.usergroup_id.is_some_and(|g| usergroups.contains(&g))
+        })
+        .map(|ua| ua.id)
+        .collect::<Vec<i64>>();
+
+    if useraccess.is_empty() {
+        exit_with_message(&format!(
+            "User '{email}' has no access via server access '{serveraccess}'."
+        ));
+    }
+
+    let keys = pgclient.query(
+        r"SELECT id,
+                  sshkey,
+                  fingerprint,
+                  EXTRACT(EPOCH FROM best_before - NOW())::BIGINT AS remaining
+           FROM sshkeys
+           WHERE user_id = $1
+             AND (best_before IS NULL
+                  OR best_before > NOW())
+             AND fingerprint NOT IN (SELECT fingerprint FROM blocked_keys)
+           ORDER BY id",
+        &[userid],
+    )?;
+
+    let keyid = keyid.map(|keyid| {
+        let Ok(keyid) = keyid.parse::<i64>() else {
+            exit_with_message("Key ID must be a number.")
+        };
+        keyid
+    });
+
+    // w/ the key restrictions (of the access graph loaded above)
+    let mut rejected = None;
+    let keys = keys
+        .into_iter()
+        .filter(|k| keyid.is_none_or(|id| k.get::<&str, i64>("id") == id))
+        .filter_map(|k| {
+            let restrictions = graph
+                .keys
+                .get(userid)?
+                .iter()
+                .find(|r| r.id == k.get::<&str, i64>("id"))?;
+
+            match key_policy(sa, k.get("sshkey")).and_then(|()| merge_options(sa, restrictions)) {
+                Ok(key_options) => Some((k, key_options)),
+                Err(e) => {
+                    rejected.get_or_insert(e);
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let (key, key_options) = match &keys[..] {
+        [] => match rejected {
+            Some(e) => exit_with_message(&format!("Cannot issue certificate: {e}.")),
+            None => exit_with_message("No (unexpired, unblocked) SSH key found."),
+        },
+        [(key, key_options)] => (key, key_options),
+        _ => exit_with_message(&format!(
+            "User has {} SSH keys, choose one w/ --id.",
+            keys.len()
+        )),
+    };
+
+    let options = match cert_options(key_options) {
+        Ok(options) => options,
+        Err(e) => exit_with_message(&format!("Cannot issue certificate: {e}.")),
+    };
+
+    // capped by the (latest) useraccess expiry and the key expiry
+    let remaining: i64 = pgclient
+        .query_one(
+            r"SELECT MAX(EXTRACT(EPOCH FROM best_before - NOW()))::BIGINT AS remaining
+               FROM useraccess
+               WHERE id = ANY($1)",
+            &[&useraccess],
+        )?
+        .get("remaining");
+
+    let mut seconds = validity.whole_seconds().min(remaining);
+    if let Some(key_remaining) = key.get::<&str, Option<i64>>("remaining") {
+        seconds = seconds.min(key_remaining);
+    }
+
+    if seconds < validity.whole_seconds() {
+        eprintln!(
+            "{} Validity capped to {}s (access / key expiry).",
+            "warning:".if_supports_color(Stdout, owo_colors::OwoColorize::yellow),
+            seconds
+        );
+    }
+
+    let principals = [sa.sshuser.clone(), email.clone()]
+        .into_iter()
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect::<Vec<String>>()
+        .join(",");
+
+    let Ok(workdir) = private_temp_dir() else {
+        exit_with_message("Could not create temporary directory.")
+    };
+    let keyfile = workdir.join("key.pub");
+    let certfile = workdir.join("key-cert.pub");
+
+    if fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&keyfile)
+        .and_then(|mut f| writeln!(f, "{}", key.get::<&str, String>("sshkey")))
+        .is_err()
+    {
+        _ = fs::remove_dir_all(&workdir);
+        exit_with_message("Could not write temporary key file.");
+    }
+
+    let interval = format!("-5m:+{seconds}s");
+    let mut args: Vec<&OsStr> = vec![
+        OsStr::new("-q"),
+        OsStr::new("-s"),
+        cakey,
+        OsStr::new("-I"),
+        OsStr::new(&email),
+        OsStr::new("-n"),
+        OsStr::new(&principals),
+        OsStr::new("-V"),
+        OsStr::new(&interval),
+    ];
+    for option in &options {
+        args.push(OsStr::new("-O"));
+        args.push(OsStr::new(option));
+    }
+    args.push(keyfile.as_os_str());
+
+    let signed = ssh_keygen(&args).and_then(|_| {
+        let cert = fs::read_to_string(&certfile).map_err(|e| e.to_string())?;
+        let details = ssh_keygen(&[OsStr::new("-L"), OsStr::new("-f"), certfile.as_os_str()])?;
+        Ok((cert, details))
+    });
+
+    _ = fs::remove_dir_all(&workdir);
+
+    let (cert, details) = match signed {
+        Ok(signed) => signed,
+        Err(e) => exit_with_message(&format!("Could not sign key: {e}")),
+    };
+
+    // the CA key may be unknown to oerec (e.g. TrustedUserCAKeys managed elsewhere)
+    let signing_ca = details
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("Signing CA:"))
+        .and_then(|l| l.split_whitespace().find(|f| f.starts_with("SHA256:")))
+        .unwrap_or_default()
+        .to_string();
+
+    let linked = graph
+        .serveraccess_certauthorities
+        .get(said)
+        .into_iter()
+        .flatten()
+        .filter_map(|id| graph.certauthorities.get(id))
+        .any(|ca| ca.fingerprint.eq(&signing_ca));
+
+    if !linked {
+        eprintln!(
+            "{} CA '{}' is not linked to server access '{}' (see add-certauthority-to-serveraccess).",
+            "warning:".if_supports_color(Stdout, owo_colors::OwoColorize::yellow),
+            signing_ca,
+            serveraccess
+        );
+    }
+
+    info!(
+        "({}) Issued certificate for SSH key '{}' of user '{}' (server access '{}', principals '{}', CA '{}', {}s)",
+        &get_ssh_client(),
+        &key.get::<&str, String>("fingerprint"),
+        &email,
+        &serveraccess,
+        &principals,
+        &signing_ca,
+        seconds
+    );
+
+    if let Some(output) = output {
+        if fs::write(output, &cert).is_err() {
+            exit_with_message("Could not write certificate.");
+        }
+
+        println!(
+            "{}",
+            "Done.".if_supports_color(Stdout, owo_colors::OwoColorize::green)
+        );
+    } else {
+        print!("{cert}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::{Key, ServerAccess};
+
+    // serveraccess options w/o key restrictions
+    fn serveraccess(
+        sshfrom: Option<&str>,
+        sshcommand: Option<&str>,
+        sshoption: Option<&str>,
+    ) -> KeyOptions {
+        let sa = ServerAccess {
+            name: "web01-root".to_string(),
+            sshuser: "root".to_string(),
+            sshfrom: sshfrom.map(str::to_string),
+            sshcommand: sshcommand.map(str::to_string),
+            sshoption: sshoption.map(str::to_string),
+            server_id: Some(1),
+            servergroup_id: None,
+            keytypes: None,
+            verify_required: false,
+        };
+
+        let key = Key {
+            id: 1,
+            sshkey: "ssh-ed25519 AAAAkey1".to_string(),
+            fingerprint: "SHA256:key1".to_string(),
+            comment: None,
+            sshfrom: None,
+            sshcommand: None,
+            sshoption: None,
+            expiry_time: None,
+            expired: false,
+            blocked: false,
+        };
+
+        merge_options(&sa, &key).unwrap()
+    }
+
+    #[test]
+    fn cert_options_maps_serveraccess_options() {
+        assert_eq!(
+            cert_options(&serveraccess(
+                Some("10.0.0.0/8, 2001:db8::1"),
+                Some("/usr/bin/backup"),
+                Some("no-pty,restrict"),
+            )),
+            Ok(vec![
+                "clear".to_string(),
+                "no-pty".to_string(),
+                "source-address=10.0.0.0/8,2001:db8::1".to_string(),
+                "force-command=/usr/bin/backup".to_string(),
+            ])
+        );
+
+        assert!(cert_options(&serveraccess(Some("*.example.com"), None, None)).is_err());
+        assert!(cert_options(&serveraccess(Some("10.0.0.0/33"), None, None)).is_err());
+        assert!(cert_options(&serveraccess(None, None, Some(r#"environment="A=1""#))).is_err());
+    }
+}
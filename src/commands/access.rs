@@ -0,0 +1,101 @@
+use crate::exit_with_message;
+
+pub fn explain(
+    con: &mut postgres::Client,
+    parser: &mut lexopt::Parser,
+) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut email: Option<String> = None;
+    let mut server: Option<String> = None;
+    let mut sshuser: Option<String> = None;
+    let mut json: bool = false;
+
+    let help = "oerec-explain-access
+Explain why a user has access to a server (every grant chain)
+
+Usage: oerec explain-access [OPTIONS]
+
+Options:
+        --email <EMAIL>          User email
+        --server <SERVER>        Server name or IP
+        --sshuser <SSHUSER>      Show only grants for SSHUSER
+
+    -j, --json                   Set output mode to JSON
+
+    -h, --help                   Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("email") => {
+                email = Some(parser.value()?.string()?);
+            }
+            Long("server") => {
+                server = Some(parser.value()?.string()?);
+            }
+            Long("sshuser") => {
+                sshuser = Some(parser.value()?.string()?);
+            }
+            Long("json") | Short('j') => {
+                json = true;
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if crate::access::explain(
+        con,
+        email.as_deref(),
+        server.as_deref(),
+        sshuser.as_deref(),
+        json,
+    )
+    .is_err()
+    {
+        exit_with_message("Could not explain access.");
+    };
+
+    Ok(())
+}
+
+pub fn check_groups(
+    con: &mut postgres::Client,
+    parser: &mut lexopt::Parser,
+) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut json: bool = false;
+
+    let help = "oerec-check-groups
+Check nested user groups and server groups for cycles (exits with 1 if any are found)
+
+Usage: oerec check-groups [OPTIONS]
+
+Options:
+    -j, --json                   Set output mode to JSON
+
+    -h, --help                   Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("json") | Short('j') => {
+                json = true;
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if crate::access::check_groups(con, json).is_err() {
+        exit_with_message("Could not check groups.");
+    };
+
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use crate::exit_with_message;
+
+pub fn block(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut fingerprint: Option<String> = None;
+    let mut sshkey: Option<String> = None;
+    let mut file: Option<String> = None;
+    let mut comment: Option<String> = None;
+
+    let help = "oerec-block-key
+Block public SSH key (known-compromised), blocked keys are never deployed
+
+Usage: oerec block-key [OPTIONS]
+
+Options:
+        --fingerprint <FINGERPRINT>    e.g. SHA256:dS8pN5W+O+OGFdkCTH27BxkcwWeK5agmJ24oeh0ktZ0
+        --sshkey <KEY>                 Block KEY (instead of --fingerprint)
+        --file <FILE>                  Read fingerprints from FILE, one per line ('-': stdin)
+        --comment <COMMENT>            e.g. incident reference
+
+    -h, --help                         Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("fingerprint") => {
+                fingerprint = Some(parser.value()?.string()?);
+            }
+            Long("sshkey") => {
+                sshkey = Some(parser.value()?.string()?);
+            }
+            Long("file") => {
+                file = Some(parser.value()?.string()?);
+            }
+            Long("comment") => {
+                comment = Some(parser.value()?.string()?);
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if [&fingerprint, &sshkey, &file]
+        .iter()
+        .filter(|o| o.is_some())
+        .count()
+        > 1
+    {
+        exit_with_message("--fingerprint, --sshkey and --file are mutually exclusive.");
+    }
+
+    if crate::blocklist::block(
+        con,
+        fingerprint.as_deref(),
+        sshkey.as_deref(),
+        file.as_deref(),
+        comment.as_deref(),
+    )
+    .is_err()
+    {
+        exit_with_message("Could not block key.");
+    };
+
+    Ok(())
+}
+
+pub fn unblock(
+    con: &mut postgres::Client,
+    parser: &mut lexopt::Parser,
+) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut fingerprint: Option<String> = None;
+    let mut confirm: bool = false;
+
+    let help = "oerec-unblock-key
+Unblock public SSH key
+
+Usage: oerec unblock-key [OPTIONS]
+
+Options:
+        --fingerprint <FINGERPRINT>
+        --confirm                      Skip confirmation dialog
+
+    -h, --help                         Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("fingerprint") => {
+                fingerprint = Some(parser.value()?.string()?);
+            }
+            Long("confirm") => {
+                confirm = true;
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if crate::blocklist::unblock(con, fingerprint.as_deref(), confirm).is_err() {
+        exit_with_message("Could not unblock key.");
+    };
+
+    Ok(())
+}
+
+pub fn list(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut fingerprint: Option<String> = None;
+    let mut json: bool = false;
+
+    let help = "oerec-list-blocked-keys
+List blocked public SSH keys (w/ IDs of keys still registered)
+
+Usage: oerec list-blocked-keys [OPTIONS]
+
+Options:
+    -j, --json                         Set output mode to JSON
+
+    -h, --help                         Print this message
+
+Filter:
+        --fingerprint <FINGERPRINT>    Filter output by fingerprint";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("fingerprint") => {
+                fingerprint = Some(parser.value()?.string()?);
+            }
+            Long("json") | Short('j') => {
+                json = true;
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if crate::blocklist::list(con, fingerprint.as_deref(), json).is_err() {
+        exit_with_message("Could not list blocked keys.");
+    };
+
+    Ok(())
+}
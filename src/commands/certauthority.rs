@@ -0,0 +1,334 @@
+use std::ffi::OsString;
+
+use crate::exit_with_message;
+
+pub fn add(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut name: Option<String> = None;
+    let mut sshkey: Option<String> = None;
+    let mut comment: Option<String> = None;
+
+    let help = "oerec-add-certauthority
+Add SSH user certificate authority (CA)
+
+Usage: oerec add-certauthority [OPTIONS]
+
+Options:
+        --certauthority <NAME>    CA name [alias: --ca, --name]
+        --sshkey <KEY>            Public key of the CA
+        --comment <COMMENT>
+
+    -h, --help                    Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("certauthority" | "ca" | "name") => {
+                name = Some(parser.value()?.string()?);
+            }
+            Long("sshkey") => {
+                sshkey = Some(parser.value()?.string()?);
+            }
+            Long("comment") => {
+                comment = Some(parser.value()?.string()?);
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if crate::certauthority::add(con, name.as_deref(), sshkey.as_deref(), comment.as_deref())
+        .is_err()
+    {
+        exit_with_message("Could not add certificate authority.");
+    };
+
+    Ok(())
+}
+
+pub fn add_to_serveraccess(
+    con: &mut postgres::Client,
+    parser: &mut lexopt::Parser,
+) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut name: Option<String> = None;
+    let mut serveraccess: Option<String> = None;
+
+    let help = "oerec-add-certauthority-to-serveraccess
+Add certificate authority to server access
+
+Users w/ access via SERVERACCESS may log in w/ certificates signed by the CA
+(principals: user email & user group names, see list-serverauth).
+
+Usage: oerec add-certauthority-to-serveraccess [OPTIONS]
+
+Options:
+        --certauthority <NAME>            CA name [alias: --ca]
+        --serveraccess <SERVERACCESS>
+
+    -h, --help                            Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("certauthority" | "ca") => {
+                name = Some(parser.value()?.string()?);
+            }
+            Long("serveraccess") => {
+                serveraccess = Some(parser.value()?.string()?);
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if crate::certauthority::add_to_serveraccess(con, name.as_deref(), serveraccess.as_deref())
+        .is_err()
+    {
+        exit_with_message("Could not add certificate authority to server access.");
+    };
+
+    Ok(())
+}
+
+pub fn delete(
+    con: &mut postgres::Client,
+    parser: &mut lexopt::Parser,
+) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut name: Option<String> = None;
+    let mut confirm: bool = false;
+
+    let help = "oerec-delete-certauthority
+Delete certificate authority
+
+Usage: oerec delete-certauthority [OPTIONS]
+
+Options:
+        --certauthority <NAME>    CA name [alias: --ca, --name]
+        --confirm                 Skip confirmation dialog
+
+    -h, --help                    Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("certauthority" | "ca" | "name") => {
+                name = Some(parser.value()?.string()?);
+            }
+            Long("confirm") => {
+                confirm = true;
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if crate::certauthority::delete(con, name.as_deref(), confirm).is_err() {
+        exit_with_message("Could not delete certificate authority.");
+    };
+
+    Ok(())
+}
+
+pub fn delete_from_serveraccess(
+    con: &mut postgres::Client,
+    parser: &mut lexopt::Parser,
+) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut name: Option<String> = None;
+    let mut serveraccess: Option<String> = None;
+    let mut confirm: bool = false;
+
+    let help = "oerec-delete-certauthority-from-serveraccess
+Delete certificate authority from server access
+
+Usage: oerec delete-certauthority-from-serveraccess [OPTIONS]
+
+Options:
+        --certauthority <NAME>            CA name [alias: --ca]
+        --serveraccess <SERVERACCESS>
+        --confirm                         Skip confirmation dialog
+
+    -h, --help                            Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("certauthority" | "ca") => {
+                name = Some(parser.value()?.string()?);
+            }
+            Long("serveraccess") => {
+                serveraccess = Some(parser.value()?.string()?);
+            }
+            Long("confirm") => {
+                confirm = true;
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if crate::certauthority::delete_from_serveraccess(
+        con,
+        name.as_deref(),
+        serveraccess.as_deref(),
+        confirm,
+    )
+    .is_err()
+    {
+        exit_with_message("Could not delete certificate authority from server access.");
+    };
+
+    Ok(())
+}
+
+pub fn list(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut name: Option<String> = None;
+    let mut serveraccess: Option<String> = None;
+    let mut exact: bool = false;
+    let mut json: bool = false;
+
+    let help = "oerec-list-certauthority
+List certificate authorities
+
+Usage: oerec list-certauthority [OPTIONS]
+
+Options:
+        --serveraccess <SERVERACCESS>    List CAs of SERVERACCESS
+
+    -e, --exact                          Only list exact matches
+    -j, --json                           Set output mode to JSON
+
+    -h, --help                           Print this message
+
+Filter:
+        --certauthority <NAME>           Filter output by CA name [alias: --ca, --name]";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("certauthority" | "ca" | "name") => {
+                name = Some(parser.value()?.string()?);
+            }
+            Long("serveraccess") => {
+                serveraccess = Some(parser.value()?.string()?);
+            }
+            Long("exact") | Short('e') => {
+                exact = true;
+            }
+            Long("json") | Short('j') => {
+                json = true;
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if crate::certauthority::list(con, name.as_deref(), serveraccess.as_deref(), exact, json)
+        .is_err()
+    {
+        exit_with_message("Could not list certificate authorities.");
+    };
+
+    Ok(())
+}
+
+pub fn issue(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut email: Option<String> = None;
+    let mut serveraccess: Option<String> = None;
+    let mut keyid: Option<String> = None;
+    let mut validity: Option<String> = None;
+    let mut cakey: Option<OsString> = None;
+    let mut output: Option<OsString> = None;
+
+    let help = "oerec-issue-cert
+Sign a user's SSH key w/ a CA private key (OpenSSH user certificate)
+
+Principals: the sshuser of SERVERACCESS & the user email. Validity is capped by the
+useraccess and key expiry, the serveraccess options (combined w/ the key restrictions)
+become certificate options.
+The certificate is printed to stdout (w/o --output).
+
+Usage: oerec issue-cert [OPTIONS] --validity <VALIDITY> --ca-key <FILE>
+
+Options:
+        --email <EMAIL>
+        --serveraccess <SERVERACCESS>
+        --id <KEYID>                     Key to sign (required if the user has more than one)
+        --validity <VALIDITY>            e.g. 30m, 8h, 7d
+        --ca-key <FILE>                  CA private key (ssh-keygen -s)
+        --output <FILE>                  Write certificate to FILE
+
+    -h, --help                           Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("email") => {
+                email = Some(parser.value()?.string()?);
+            }
+            Long("serveraccess") => {
+                serveraccess = Some(parser.value()?.string()?);
+            }
+            Long("id") => {
+                keyid = Some(parser.value()?.string()?);
+            }
+            Long("validity") => {
+                validity = Some(parser.value()?.string()?);
+            }
+            Long("ca-key") => {
+                cakey = Some(parser.value()?.parse()?);
+            }
+            Long("output") => {
+                output = Some(parser.value()?.parse()?);
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    let Some(validity) = validity else {
+        exit_with_message("--validity is required.");
+    };
+
+    let Some(cakey) = cakey else {
+        exit_with_message("--ca-key is required.");
+    };
+
+    if crate::certauthority::issue(
+        con,
+        email.as_deref(),
+        serveraccess.as_deref(),
+        keyid.as_deref(),
+        &validity,
+        &cakey,
+        output.as_deref(),
+    )
+    .is_err()
+    {
+        exit_with_message("Could not issue certificate.");
+    };
+
+    Ok(())
+}
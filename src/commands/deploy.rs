@@ -0,0 +1,127 @@
+use std::path::PathBuf;
+
+use crate::deploy::Transport;
+use crate::exit_with_message;
+
+pub fn deploy(parser: &mut lexopt::Parser) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut workdir: Option<PathBuf> = None;
+    let mut transport = "ssh".to_string();
+    let mut login = "root".to_string();
+    let mut target: Option<PathBuf> = None;
+    let mut jobs: usize = 8;
+    let mut all: bool = false;
+    let mut server: Option<String> = None;
+    let mut root_key: Option<PathBuf> = None;
+    let mut create_user: bool = false;
+    let mut min_size: usize = 127;
+    let mut dry_run: bool = false;
+    let mut json: bool = false;
+
+    let help = "oerec-deploy
+Deploy authorized_keys from workdir (see write-serverauth) to the servers
+
+Only files changed since the last deploy are copied (state is kept in
+<WORKDIR>/.oerec-deployed). Existing files are backed up to <FILE>~.
+
+Files of server accesses w/ certificate authorities are deployed, too. sshd_config needs:
+    TrustedUserCAKeys /etc/ssh/trusted_user_ca_keys
+    AuthorizedPrincipalsFile %h/.ssh/authorized_principals
+
+Usage: oerec deploy [OPTIONS] --workdir <WORKDIR>
+
+Options:
+        --workdir <WORKDIR>      [alias: --dir]
+        --transport <TYPE>       ssh (default) or local
+        --login <USER>           SSH login for the ssh transport (default: root)
+        --target <DIR>           Target directory for the local transport
+        --jobs <N>               Deploy to N servers in parallel (default: 8)
+
+        --all                    Deploy all files (even unchanged ones)
+        --server <IP>            Deploy all files of server IP only
+        --root-key <FILE>        Install FILE when root's keys are removed (instead of failing)
+        --create-users           Create missing OS users (and add them to AllowUsers / AllowGroups)
+        --min-size <BYTES>       Skip files smaller than BYTES, e.g. truncated ones (default: 127)
+
+        --dry-run                Show what would be deployed
+    -j, --json                   Set output mode to JSON
+
+    -h, --help                   Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("workdir" | "dir") => {
+                workdir = Some(parser.value()?.parse()?);
+            }
+            Long("transport") => {
+                transport = parser.value()?.string()?;
+            }
+            Long("login") => {
+                login = parser.value()?.string()?;
+            }
+            Long("target") => {
+                target = Some(parser.value()?.parse()?);
+            }
+            Long("jobs") => {
+                jobs = parser.value()?.parse()?;
+            }
+            Long("all") => {
+                all = true;
+            }
+            Long("server") => {
+                server = Some(parser.value()?.string()?);
+            }
+            Long("root-key") => {
+                root_key = Some(parser.value()?.parse()?);
+            }
+            Long("create-users") => {
+                create_user = true;
+            }
+            Long("min-size") => {
+                min_size = parser.value()?.parse()?;
+            }
+            Long("dry-run") => {
+                dry_run = true;
+            }
+            Long("json") | Short('j') => {
+                json = true;
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    let Some(workdir) = workdir else {
+        exit_with_message("Workdir cannot be empty.");
+    };
+
+    let transport = match (transport.as_str(), target) {
+        ("ssh", _) => Transport::Ssh { login },
+        ("local", Some(root)) => Transport::Local { root },
+        ("local", None) => exit_with_message("The local transport needs --target."),
+        (t, _) => exit_with_message(&format!("Unknown transport '{t}'.")),
+    };
+
+    match crate::deploy::deploy(
+        &workdir,
+        &transport,
+        jobs,
+        all,
+        server.as_deref(),
+        root_key.as_deref(),
+        create_user,
+        min_size,
+        dry_run,
+        json,
+    ) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => exit_with_message(&e),
+    }
+
+    Ok(())
+}
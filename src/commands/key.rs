@@ -6,18 +6,31 @@ pub fn add(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<()
     let mut email: Option<String> = None;
     let mut sshkey: Option<String> = None;
     let mut comment: Option<String> = None;
+    let mut until: Option<String> = None;
+    let mut sshfrom: Option<String> = None;
+    let mut sshcommand: Option<String> = None;
+    let mut sshoption: Option<String> = None;
 
     let help = "oerec-add-key
 Add public SSH key
 
+Key options of the key line (e.g. from=\"10.1.0.0/16\",no-pty ssh-ed25519 ...)
+are stored as per-key restrictions and combined w/ the serveraccess options,
+expiry-time= is the default for --until (set as expiry-time= on the servers).
+
 Usage: oerec add-key [OPTIONS]
 
 Options:
         --email <EMAIL>
         --sshkey <KEY>
         --comment <COMMENT>
+        --until <UNTIL>              Key expiry, format: YYYY-MM-DD, optional w/ HH:MI:SS
+
+        --sshfrom <SSHFROM>          from= pattern-list (intersected w/ the serveraccess)
+        --sshcommand <SSHCOMMAND>    command= pattern
+        --sshoption <SSHOPTION>      Additional key options (`man 8 sshd`)
 
-    -h, --help                 Print this message";
+    -h, --help                       Print this message";
 
     while let Some(arg) = parser.next()? {
         match arg {
@@ -30,6 +43,18 @@ Options:
             Long("comment") => {
                 comment = Some(parser.value()?.string()?);
             }
+            Long("until") => {
+                until = Some(parser.value()?.string()?);
+            }
+            Long("sshfrom") => {
+                sshfrom = Some(parser.value()?.string()?);
+            }
+            Long("sshcommand") => {
+                sshcommand = Some(parser.value()?.string()?);
+            }
+            Long("sshoption") => {
+                sshoption = Some(parser.value()?.string()?);
+            }
             Long("help") | Short('h') => {
                 println!("{help}");
                 std::process::exit(0);
@@ -38,13 +63,77 @@ Options:
         }
     }
 
-    if crate::key::add(con, email.as_deref(), sshkey.as_deref(), comment.as_deref()).is_err() {
+    if crate::key::add(
+        con,
+        email.as_deref(),
+        sshkey.as_deref(),
+        comment.as_deref(),
+        until.as_deref(),
+        sshfrom.as_deref(),
+        sshcommand.as_deref(),
+        sshoption.as_deref(),
+    )
+    .is_err()
+    {
         exit_with_message("Could not add key.");
     };
 
     Ok(())
 }
 
+pub fn import(
+    con: &mut postgres::Client,
+    parser: &mut lexopt::Parser,
+) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut email: Option<String> = None;
+    let mut file: Option<String> = None;
+    let mut json: bool = false;
+
+    let help = "oerec-import-keys
+Import public SSH keys from an authorized_keys or .pub file
+
+Usage: oerec import-keys [OPTIONS] --file <FILE>
+
+Options:
+        --email <EMAIL>
+        --file <FILE>    Read keys from FILE ('-': stdin)
+
+    -j, --json           Set output mode to JSON
+
+    -h, --help           Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("email") => {
+                email = Some(parser.value()?.string()?);
+            }
+            Long("file") => {
+                file = Some(parser.value()?.string()?);
+            }
+            Long("json") | Short('j') => {
+                json = true;
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    let Some(file) = file else {
+        exit_with_message("--file is required.");
+    };
+
+    if crate::key::import(con, email.as_deref(), &file, json).is_err() {
+        exit_with_message("Could not import keys.");
+    };
+
+    Ok(())
+}
+
 pub fn delete(
     con: &mut postgres::Client,
     parser: &mut lexopt::Parser,
@@ -95,7 +184,12 @@ pub fn list(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<(
     let mut email: Option<String> = None;
     let mut fingerprint: Option<String> = None;
     let mut keyid: Option<String> = None;
+    let mut expiring_within: Option<String> = None;
+    let mut added_by: Option<String> = None;
+    let mut added_after: Option<String> = None;
     let mut with_key: bool = false;
+    let mut fingerprint_format: Option<String> = None;
+    let mut visual: bool = false;
     let mut json: bool = false;
 
     let help = "oerec-list-key
@@ -105,10 +199,15 @@ Usage: oerec list-key [OPTIONS]
 
 Options:
         --email <EMAIL>                List keys by user EMAIL
-        --fingerprint <FINGERPRINT>    List keys by (SHA256) FINGERPRINT
+        --fingerprint <FINGERPRINT>    List keys by (SHA256 or MD5) FINGERPRINT
         --id <ID>                      List key w/ ID
+        --expiring-within <DURATION>   List keys expiring within DURATION (e.g. 30d)
+        --added-by <OPERATOR>          List keys added by OPERATOR
+        --added-after <DATETIME>       List keys added after DATETIME (YYYY-MM-DD [HH:MI:SS])
 
         --with-key                     Display public SSH keys [alias: --long]
+        --fingerprint-format <FORMAT>  md5 or sha256 [default: sha256]
+        --visual                       Display randomart (like ssh-keygen -lv)
     -j, --json                         Set output mode to JSON
 
     -h, --help                         Print this message";
@@ -118,12 +217,27 @@ Options:
             Long("email") => {
                 email = Some(parser.value()?.string()?);
             }
+            Long("fingerprint-format") => {
+                fingerprint_format = Some(parser.value()?.string()?);
+            }
+            Long("visual") => {
+                visual = true;
+            }
             Long("fingerprint") => {
                 fingerprint = Some(parser.value()?.string()?);
             }
             Long("id" | "keyid") => {
                 keyid = Some(parser.value()?.string()?);
             }
+            Long("expiring-within") => {
+                expiring_within = Some(parser.value()?.string()?);
+            }
+            Long("added-by") => {
+                added_by = Some(parser.value()?.string()?);
+            }
+            Long("added-after") => {
+                added_after = Some(parser.value()?.string()?);
+            }
             Long("with-key" | "long") => {
                 with_key = true;
             }
@@ -144,6 +258,11 @@ Options:
         fingerprint.as_deref(),
         with_key,
         keyid.as_deref(),
+        expiring_within.as_deref(),
+        added_by.as_deref(),
+        added_after.as_deref(),
+        fingerprint_format.as_deref(),
+        visual,
         json,
     )
     .is_err()
@@ -163,18 +282,31 @@ pub fn update(
     let mut keyid: Option<String> = None;
     let mut sshkey: Option<String> = None;
     let mut comment: Option<String> = None;
+    let mut until: Option<String> = None;
+    let mut sshfrom: Option<String> = None;
+    let mut sshcommand: Option<String> = None;
+    let mut sshoption: Option<String> = None;
 
     let help = "oerec-update-key
 Update public SSH key
 
+Key options of a new key line replace the stored per-key restrictions,
+its expiry-time= replaces the key expiry (unless --until is given).
+
 Usage: oerec update-key [OPTIONS]
 
 Options:
-        --id <KEYID>           [alias: --keyid]
+        --id <KEYID>                 [alias: --keyid]
         --sshkey <KEY>
         --comment <COMMENT>
+        --until <UNTIL>              Key expiry, format: YYYY-MM-DD, optional w/ HH:MI:SS
+                                     ('null': never)
+
+        --sshfrom <SSHFROM>          from= pattern-list ('null' to clear)
+        --sshcommand <SSHCOMMAND>    command= pattern ('null' to clear)
+        --sshoption <SSHOPTION>      Additional key options ('null' to clear)
 
-    -h, --help                 Print this message";
+    -h, --help                       Print this message";
 
     while let Some(arg) = parser.next()? {
         match arg {
@@ -187,6 +319,18 @@ Options:
             Long("comment") => {
                 comment = Some(parser.value()?.string()?);
             }
+            Long("until") => {
+                until = Some(parser.value()?.string()?);
+            }
+            Long("sshfrom") => {
+                sshfrom = Some(parser.value()?.string()?);
+            }
+            Long("sshcommand") => {
+                sshcommand = Some(parser.value()?.string()?);
+            }
+            Long("sshoption") => {
+                sshoption = Some(parser.value()?.string()?);
+            }
             Long("help") | Short('h') => {
                 println!("{help}");
                 std::process::exit(0);
@@ -195,9 +339,194 @@ Options:
         }
     }
 
-    if crate::key::update(con, keyid.as_deref(), sshkey.as_deref(), comment.as_deref()).is_err() {
+    if crate::key::update(
+        con,
+        keyid.as_deref(),
+        sshkey.as_deref(),
+        comment.as_deref(),
+        until.as_deref(),
+        sshfrom.as_deref(),
+        sshcommand.as_deref(),
+        sshoption.as_deref(),
+    )
+    .is_err()
+    {
         exit_with_message("Could not update key.");
     };
 
     Ok(())
 }
+
+pub fn rotate(
+    con: &mut postgres::Client,
+    parser: &mut lexopt::Parser,
+) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut keyid: Option<String> = None;
+    let mut sshkey: Option<String> = None;
+    let mut overlap: Option<String> = None;
+
+    let help = "oerec-rotate-key
+Replace public SSH key w/ a new one (w/ the per-key restrictions of the old one)
+
+Usage: oerec rotate-key [OPTIONS]
+
+Options:
+        --id <KEYID>              [alias: --keyid]
+        --sshkey <KEY>            New public SSH key
+        --overlap <DURATION>      Keep the old key valid for DURATION (e.g. 7d),
+                                  otherwise it is deleted
+
+    -h, --help                    Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("id" | "keyid") => {
+                keyid = Some(parser.value()?.string()?);
+            }
+            Long("sshkey") => {
+                sshkey = Some(parser.value()?.string()?);
+            }
+            Long("overlap") => {
+                overlap = Some(parser.value()?.string()?);
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if crate::key::rotate(con, keyid.as_deref(), sshkey.as_deref(), overlap.as_deref()).is_err() {
+        exit_with_message("Could not rotate key.");
+    };
+
+    Ok(())
+}
+
+pub fn check(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut json: bool = false;
+
+    let help = "oerec-check-keys
+List public SSH keys registered more than once (exit code 1 if any), keys shared
+across users and keys registered twice for the same user are listed separately
+
+Usage: oerec check-keys [OPTIONS]
+
+Options:
+    -j, --json    Set output mode to JSON
+
+    -h, --help    Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("json") | Short('j') => {
+                json = true;
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if crate::key::check(con, json).is_err() {
+        exit_with_message("Could not check keys.");
+    };
+
+    Ok(())
+}
+
+pub fn audit(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut threshold: Option<usize> = None;
+    let mut json: bool = false;
+
+    let help = "oerec-audit-keys
+Audit all public SSH keys (exit code 1 if findings exceed the threshold)
+
+Findings: blocked, weak-rsa (< 3072 bits), dsa, duplicate, user-disabled, no-access
+
+Usage: oerec audit-keys [OPTIONS]
+
+Options:
+        --threshold <N>    Max. number of findings [default: keys.audit_threshold or 0]
+
+    -j, --json             Set output mode to JSON
+
+    -h, --help             Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("threshold") => {
+                threshold = Some(parser.value()?.parse()?);
+            }
+            Long("json") | Short('j') => {
+                json = true;
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if crate::key::audit(con, threshold, json).is_err() {
+        exit_with_message("Could not audit keys.");
+    };
+
+    Ok(())
+}
+
+pub fn verify(
+    con: &mut postgres::Client,
+    parser: &mut lexopt::Parser,
+) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut fix: bool = false;
+    let mut json: bool = false;
+
+    let help = "oerec-verify-keys
+Recompute and verify stored fingerprints (exit code 1 if unfixed issues remain)
+
+Issues: fingerprint-mismatch, non-canonical, unparsable
+
+Usage: oerec verify-keys [OPTIONS]
+
+Options:
+        --fix     Rewrite keys & fingerprints (unparsable keys are reported only)
+
+    -j, --json    Set output mode to JSON
+
+    -h, --help    Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("fix") => {
+                fix = true;
+            }
+            Long("json") | Short('j') => {
+                json = true;
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if crate::key::verify(con, fix, json).is_err() {
+        exit_with_message("Could not verify keys.");
+    };
+
+    Ok(())
+}
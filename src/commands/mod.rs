@@ -1,6 +1,11 @@
 use crate::commands;
 
+mod access;
+mod blocklist;
+mod certauthority;
+mod deploy;
 mod key;
+mod serve;
 mod server;
 mod serveraccess;
 mod serverauth;
@@ -28,7 +33,8 @@ Commands:
 
     add-user, list-user, update-user, delete-user
 
-    add-key, list-key, update-key, delete-key
+    add-key, list-key, update-key, delete-key, import-keys, rotate-key
+    block-key, unblock-key, list-blocked-keys
 
     add-usergroup, list-usergroup, update-usergroup, delete-usergroup
 
@@ -38,11 +44,20 @@ Commands:
     add-serveraccess, list-serveraccess, update-serveraccess, delete-serveraccess
     add-useraccess, list-useraccess, delete-useraccess
 
+    add-certauthority, list-certauthority, delete-certauthority
+    add-certauthority-to-serveraccess, delete-certauthority-from-serveraccess
+    issue-cert
+
     enable-dns, disable-dns
     enable-server, disable-server
     enable-user, disable-user
 
-    write-serverauth";
+    add-server-token, delete-server-token
+
+    explain-access, check-groups, check-keys, audit-keys, verify-keys
+
+    write-serverauth, serverauth-history, deploy
+    serve, authorized-keys-command";
 
 #[allow(clippy::too_many_lines)]
 pub fn parse_subcommands(con: &mut postgres::Client) -> Result<(), lexopt::Error> {
@@ -62,6 +77,9 @@ pub fn parse_subcommands(con: &mut postgres::Client) -> Result<(), lexopt::Error
             Value(value) => {
                 let value = value.string()?;
                 match value.as_str() {
+                    "list-certauthority" | "list-certauthorities" => {
+                        commands::certauthority::list(con, &mut parser)?;
+                    }
                     "list-key" | "list-keys" => {
                         commands::key::list(con, &mut parser)?;
                     }
@@ -86,6 +104,12 @@ pub fn parse_subcommands(con: &mut postgres::Client) -> Result<(), lexopt::Error
                     "list-usergroup" | "list-usergroups" => {
                         commands::usergroup::list(con, &mut parser)?;
                     }
+                    "add-certauthority" => {
+                        commands::certauthority::add(con, &mut parser)?;
+                    }
+                    "add-certauthority-to-serveraccess" => {
+                        commands::certauthority::add_to_serveraccess(con, &mut parser)?;
+                    }
                     "add-key" => {
                         commands::key::add(con, &mut parser)?;
                     }
@@ -95,6 +119,9 @@ pub fn parse_subcommands(con: &mut postgres::Client) -> Result<(), lexopt::Error
                     "add-server-to-servergroup" => {
                         commands::server::add_to_servergroup(con, &mut parser)?;
                     }
+                    "add-server-token" => {
+                        commands::server::add_token(con, &mut parser)?;
+                    }
                     "add-serveraccess" => {
                         commands::serveraccess::add(con, &mut parser)?;
                     }
@@ -119,6 +146,39 @@ pub fn parse_subcommands(con: &mut postgres::Client) -> Result<(), lexopt::Error
                     "add-usergroup-to-usergroup" => {
                         commands::usergroup::add_to_usergroup(con, &mut parser)?;
                     }
+                    "check-keys" => {
+                        commands::key::check(con, &mut parser)?;
+                    }
+                    "audit-keys" => {
+                        commands::key::audit(con, &mut parser)?;
+                    }
+                    "verify-keys" => {
+                        commands::key::verify(con, &mut parser)?;
+                    }
+                    "rotate-key" => {
+                        commands::key::rotate(con, &mut parser)?;
+                    }
+                    "block-key" => {
+                        commands::blocklist::block(con, &mut parser)?;
+                    }
+                    "unblock-key" => {
+                        commands::blocklist::unblock(con, &mut parser)?;
+                    }
+                    "list-blocked-keys" | "list-blocked-key" => {
+                        commands::blocklist::list(con, &mut parser)?;
+                    }
+                    "import-keys" | "import-key" => {
+                        commands::key::import(con, &mut parser)?;
+                    }
+                    "issue-cert" => {
+                        commands::certauthority::issue(con, &mut parser)?;
+                    }
+                    "delete-certauthority" => {
+                        commands::certauthority::delete(con, &mut parser)?;
+                    }
+                    "delete-certauthority-from-serveraccess" => {
+                        commands::certauthority::delete_from_serveraccess(con, &mut parser)?;
+                    }
                     "delete-key" => {
                         commands::key::delete(con, &mut parser)?;
                     }
@@ -128,6 +188,9 @@ pub fn parse_subcommands(con: &mut postgres::Client) -> Result<(), lexopt::Error
                     "delete-server-from-servergroup" => {
                         commands::server::delete_from_servergroup(con, &mut parser)?;
                     }
+                    "delete-server-token" => {
+                        commands::server::delete_token(con, &mut parser)?;
+                    }
                     "delete-serveraccess" => {
                         commands::serveraccess::delete(con, &mut parser)?;
                     }
@@ -188,9 +251,24 @@ pub fn parse_subcommands(con: &mut postgres::Client) -> Result<(), lexopt::Error
                     "disable-user" => {
                         commands::user::disable(con, &mut parser)?;
                     }
+                    "explain-access" => {
+                        commands::access::explain(con, &mut parser)?;
+                    }
+                    "check-groups" => {
+                        commands::access::check_groups(con, &mut parser)?;
+                    }
                     "write-serverauth" => {
                         commands::serverauth::write(con, &mut parser)?;
                     }
+                    "authorized-keys-command" => {
+                        commands::serverauth::authorized_keys_command(con, &mut parser)?;
+                    }
+                    "serverauth-history" => {
+                        commands::serverauth::history(con, &mut parser)?;
+                    }
+                    "serve" => {
+                        commands::serve::serve(con, &mut parser)?;
+                    }
                     value => {
                         return Err(format!("Unknown subcommand '{value}'").into());
                     }
@@ -202,3 +280,13 @@ pub fn parse_subcommands(con: &mut postgres::Client) -> Result<(), lexopt::Error
 
     Ok(())
 }
+
+// deploy w/o database connection (see main)
+pub fn parse_deploy() -> Result<(), lexopt::Error> {
+    let mut parser = lexopt::Parser::from_env();
+
+    // the subcommand itself
+    parser.next()?;
+
+    commands::deploy::deploy(&mut parser)
+}
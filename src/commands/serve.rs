@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use crate::exit_with_message;
+
+pub fn serve(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut listen = "127.0.0.1:8080".to_string();
+    let mut ttl: u64 = 60;
+
+    let help = "oerec-serve
+Serve authorized_keys over HTTP
+
+Every host gets its own files only. Hosts are identified by their IP address, or by
+a token ('Authorization: Bearer <TOKEN>' header, see add-server-token).
+
+Endpoints:
+    GET /authorized_keys/<SSHUSER>    authorized_keys file of SSHUSER (text)
+    GET /serverauth                   all ssh users and keys of the host (JSON)
+
+The auth list is regenerated in the background. If that fails (e.g. the database is
+down), the last one is served and the connection is retried. Requests are answered
+w/ 503 only until the first successful run.
+
+Usage: oerec serve [OPTIONS]
+
+Options:
+        --listen <ADDRESS>       Listen on ADDRESS (default: 127.0.0.1:8080)
+        --cache-ttl <SECONDS>    Regenerate auth list every SECONDS (default: 60, min: 1)
+
+    -h, --help                   Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("listen") => {
+                listen = parser.value()?.string()?;
+            }
+            Long("cache-ttl") => {
+                ttl = parser.value()?.parse()?;
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if ttl == 0 {
+        exit_with_message("Cache TTL must be at least 1 second.");
+    }
+
+    crate::serve::serve(con, &listen, Duration::from_secs(ttl));
+
+    Ok(())
+}
@@ -110,6 +110,49 @@ Options:
     Ok(())
 }
 
+pub fn add_token(
+    con: &mut postgres::Client,
+    parser: &mut lexopt::Parser,
+) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut servername: Option<String> = None;
+    let mut confirm: bool = false;
+
+    let help = "oerec-add-server-token
+Generate a new token for 'oerec serve' (replaces an existing one)
+
+Usage: oerec add-server-token [OPTIONS]
+
+Options:
+        --server <NAME>    [alias: --servername]
+        --confirm          Skip confirmation dialog
+
+    -h, --help             Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("server" | "servername") => {
+                servername = Some(parser.value()?.string()?);
+            }
+            Long("confirm") => {
+                confirm = true;
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if crate::server::add_token(con, servername.as_deref(), confirm).is_err() {
+        exit_with_message("Could not add server token.");
+    };
+
+    Ok(())
+}
+
 pub fn delete(
     con: &mut postgres::Client,
     parser: &mut lexopt::Parser,
@@ -153,6 +196,49 @@ Options:
     Ok(())
 }
 
+pub fn delete_token(
+    con: &mut postgres::Client,
+    parser: &mut lexopt::Parser,
+) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut servername: Option<String> = None;
+    let mut confirm: bool = false;
+
+    let help = "oerec-delete-server-token
+Delete the token for 'oerec serve'
+
+Usage: oerec delete-server-token [OPTIONS]
+
+Options:
+        --server <NAME>    [alias: --servername]
+        --confirm          Skip confirmation dialog
+
+    -h, --help             Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("server" | "servername") => {
+                servername = Some(parser.value()?.string()?);
+            }
+            Long("confirm") => {
+                confirm = true;
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if crate::server::delete_token(con, servername.as_deref(), confirm).is_err() {
+        exit_with_message("Could not delete server token.");
+    };
+
+    Ok(())
+}
+
 pub fn disable(
     con: &mut postgres::Client,
     parser: &mut lexopt::Parser,
@@ -386,6 +472,8 @@ pub fn list(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<(
     let mut server: Option<String> = None;
     let mut ip: Option<String> = None;
     let mut serverid: Option<String> = None;
+    let mut added_by: Option<String> = None;
+    let mut added_after: Option<String> = None;
     let mut exact: bool = false;
     let mut json: bool = false;
 
@@ -395,14 +483,16 @@ List managed servers
 Usage: oerec list-server [OPTIONS]
 
 Options:
-        --server <SERVERNAME>    List server by SERVERNAME [alias: --name]
-        --ip <IP>                List server by IP
-        --id <ID>                List server w/ ID
+        --server <SERVERNAME>       List server by SERVERNAME [alias: --name]
+        --ip <IP>                   List server by IP
+        --id <ID>                   List server w/ ID
+        --added-by <OPERATOR>       List server added by OPERATOR
+        --added-after <DATETIME>    List server added after DATETIME (YYYY-MM-DD [HH:MI:SS])
 
-    -e, --exact                  Only list exact matches
-    -j, --json                   Set output mode to JSON
+    -e, --exact                     Only list exact matches
+    -j, --json                      Set output mode to JSON
 
-    -h, --help                   Print this message";
+    -h, --help                      Print this message";
 
     while let Some(arg) = parser.next()? {
         match arg {
@@ -415,6 +505,12 @@ Options:
             Long("id" | "serverid") => {
                 serverid = Some(parser.value()?.string()?);
             }
+            Long("added-by") => {
+                added_by = Some(parser.value()?.string()?);
+            }
+            Long("added-after") => {
+                added_after = Some(parser.value()?.string()?);
+            }
             Long("exact") | Short('e') => {
                 exact = true;
             }
@@ -434,6 +530,8 @@ Options:
         server.as_deref(),
         ip.as_deref(),
         serverid.as_deref(),
+        added_by.as_deref(),
+        added_after.as_deref(),
         exact,
         json,
     )
@@ -11,6 +11,8 @@ pub fn add(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<()
     let mut servername: Option<String> = None;
     let mut servergroup: Option<String> = None;
     let mut comment: Option<String> = None;
+    let mut keytypes: Option<String> = None;
+    let mut verify_required: bool = false;
 
     let help = "oerec-add-serveraccess
 Add server access
@@ -22,12 +24,16 @@ Usage: oerec add-serveraccess [OPTIONS] [--server <SERVER> | --servergroup <SERV
 
 Options:
         --serveraccess <NAME>          Server access name [alias: --name]
-        --sshuser <SSHUSER>               SSH / OS user [aliases: --user, --osuser]
+        --sshuser <SSHUSER>            SSH / OS user [aliases: --user, --osuser]
 
         --sshfrom <SSHFROM>            from= pattern-list
         --sshcommand <SSHCOMMAND>      command= pattern
         --sshoption <SSHOPTION>        Additional key options (`man 8 sshd`)
 
+        --keytypes <KEYTYPES>          Allowed key types, e.g. `sk` (security keys only)
+                                       or `sk,ssh-ed25519` [default: any]
+        --verify-required              Require user verification (security key PIN)
+
         --server <SERVER>              Server name
         --servergroup <SERVERGROUP>    Server group name
         --comment <COMMENT>
@@ -60,6 +66,12 @@ Options:
             Long("comment") => {
                 comment = Some(parser.value()?.string()?);
             }
+            Long("keytypes") => {
+                keytypes = Some(parser.value()?.string()?);
+            }
+            Long("verify-required") => {
+                verify_required = true;
+            }
             Long("help") | Short('h') => {
                 println!("{help}");
                 std::process::exit(0);
@@ -78,6 +90,8 @@ Options:
         servername.as_deref(),
         servergroup.as_deref(),
         comment.as_deref(),
+        keytypes.as_deref(),
+        verify_required,
     )
     .is_err()
     {
@@ -138,6 +152,8 @@ pub fn list(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<(
     let mut sshuser: Option<String> = None;
     let mut serveraccess: Option<String> = None;
     let mut servergroup: Option<String> = None;
+    let mut added_by: Option<String> = None;
+    let mut added_after: Option<String> = None;
     let mut exact: bool = false;
     let mut json: bool = false;
 
@@ -158,7 +174,9 @@ Options:
 
 Filter:
         --serveraccess <NAME>          Filter output by NAME [alias: --name]
-        --servergroup <SERVERGROUP>    Filter output by SERVERGROUP";
+        --servergroup <SERVERGROUP>    Filter output by SERVERGROUP
+        --added-by <OPERATOR>          Filter output by OPERATOR who added the server access
+        --added-after <DATETIME>       Filter output by server access added after DATETIME (YYYY-MM-DD [HH:MI:SS])";
 
     while let Some(arg) = parser.next()? {
         match arg {
@@ -177,6 +195,12 @@ Filter:
             Long("servergroup") => {
                 servergroup = Some(parser.value()?.string()?);
             }
+            Long("added-by") => {
+                added_by = Some(parser.value()?.string()?);
+            }
+            Long("added-after") => {
+                added_after = Some(parser.value()?.string()?);
+            }
             Long("exact") | Short('e') => {
                 exact = true;
             }
@@ -198,6 +222,8 @@ Filter:
         ip.as_deref(),
         sshuser.as_deref(),
         servergroup.as_deref(),
+        added_by.as_deref(),
+        added_after.as_deref(),
         exact,
         json,
     )
@@ -224,6 +250,8 @@ pub fn update(
     let mut servername: Option<String> = None;
     let mut servergroup: Option<String> = None;
     let mut comment: Option<String> = None;
+    let mut keytypes: Option<String> = None;
+    let mut verify_required: Option<bool> = None;
 
     let help = "oerec-update-serveraccess
 Update server access
@@ -239,6 +267,10 @@ Options:
         --sshcommand <SSHCOMMAND>      command= pattern
         --sshoption <SSHOPTION>        additional options, e.g `no-pty`
 
+        --keytypes <KEYTYPES>          Allowed key types, e.g. `sk` ('null': any)
+        --verify-required              Require user verification (security key PIN)
+        --no-verify-required
+
         --server <SERVER>              Server name
         --servergroup <SERVERGROUP>    Server group name
         --comment <COMMENT>
@@ -274,6 +306,15 @@ Options:
             Long("comment") => {
                 comment = Some(parser.value()?.string()?);
             }
+            Long("keytypes") => {
+                keytypes = Some(parser.value()?.string()?);
+            }
+            Long("verify-required") => {
+                verify_required = Some(true);
+            }
+            Long("no-verify-required") => {
+                verify_required = Some(false);
+            }
             Long("help") | Short('h') => {
                 println!("{help}");
                 std::process::exit(0);
@@ -293,6 +334,8 @@ Options:
         servername.as_deref(),
         servergroup.as_deref(),
         comment.as_deref(),
+        keytypes.as_deref(),
+        verify_required,
     )
     .is_err()
     {
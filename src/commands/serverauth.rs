@@ -7,6 +7,9 @@ pub fn list(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<(
 
     let mut server: Option<String> = None;
     let mut ip: Option<String> = None;
+    let mut json: bool = false;
+    let mut ndjson: bool = false;
+    let mut filtered: bool = false;
 
     let help = "oerec-list-serverauth
 List server auth
@@ -16,6 +19,10 @@ Usage: oerec list-serverauth [OPTIONS]
 Options:
         --server <SERVERNAME>    List server auth by SERVERNAME (only exact matches)
         --ip <IP>                List server auth by IP
+        --filtered               List keys filtered out by the key policy of a server access (w/ reason)
+
+    -j, --json                   Set output mode to JSON
+        --ndjson                 Set output mode to NDJSON (one key record per line)
 
     -h, --help                   Print this message";
 
@@ -27,6 +34,15 @@ Options:
             Long("ip") => {
                 ip = Some(parser.value()?.string()?);
             }
+            Long("json") | Short('j') => {
+                json = true;
+            }
+            Long("ndjson") => {
+                ndjson = true;
+            }
+            Long("filtered") => {
+                filtered = true;
+            }
             Long("help") | Short('h') => {
                 println!("{help}");
                 std::process::exit(0);
@@ -35,7 +51,16 @@ Options:
         }
     }
 
-    if crate::serverauth::list(con, ip.as_deref(), server.as_deref()).is_err() {
+    if crate::serverauth::list(
+        con,
+        ip.as_deref(),
+        server.as_deref(),
+        json,
+        ndjson,
+        filtered,
+    )
+    .is_err()
+    {
         exit_with_message("Could not list server auth.");
     };
 
@@ -47,15 +72,25 @@ pub fn write(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<
 
     let mut workdir: Option<OsString> = None;
     let mut force: bool = false;
+    let mut dry_run: bool = false;
+    let mut git: bool = false;
 
     let help = "oerec-write-serverauth
 Write authorized_keys to workdir
 
+New and changed files are written next to their target and renamed over it, stale files are
+removed last. Unchanged files and hidden entries (e.g. '.git') are left alone.
+
+Server accesses w/ certificate authorities also get '<IP>/<SSHUSER>/authorized_principals'
+and '<IP>/trusted_user_ca_keys' (for AuthorizedPrincipalsFile & TrustedUserCAKeys, see deploy).
+
 Usage: oerec write-serverauth [OPTIONS] --workdir <WORKDIR>
 
 Options:
         --workdir <WORKDIR>    [alias: --dir]
         --force                Overwrite workdir contents (USE WITH CAUTION)
+        --dry-run              Show changes to the workdir, write nothing [alias: --diff]
+        --git                  Commit changes to git (repository in workdir, created if missing)
 
     -h, --help                 Print this message";
 
@@ -67,6 +102,121 @@ Options:
             Long("force") => {
                 force = true;
             }
+            Long("dry-run" | "diff") => {
+                dry_run = true;
+            }
+            Long("git") => {
+                git = true;
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if dry_run {
+        crate::serverauth::diff(con, workdir.as_deref());
+    } else {
+        crate::serverauth::write(con, workdir.as_deref(), force, git);
+    }
+
+    Ok(())
+}
+
+pub fn authorized_keys_command(
+    con: &mut postgres::Client,
+    parser: &mut lexopt::Parser,
+) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut server: Option<String> = None;
+    let mut user: Option<String> = None;
+    let mut fingerprint: Option<String> = None;
+
+    let help = "oerec-authorized-keys-command
+Print authorized_keys lines for sshd (AuthorizedKeysCommand)
+
+Prints nothing & exits with 1 on errors (e.g. database not reachable).
+
+Usage: oerec authorized-keys-command [OPTIONS]
+
+Options:
+        --server <SERVER>              Server name or IP
+        --user <SSHUSER>               SSH user (%u)
+        --fingerprint <FINGERPRINT>    Only print the key w/ FINGERPRINT (%f)
+
+    -h, --help                         Print this message
+
+Example (sshd_config):
+    AuthorizedKeysCommand /usr/local/bin/oerec authorized-keys-command --server web01 --user %u --fingerprint %f
+    AuthorizedKeysCommandUser oerec";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("server") => {
+                server = Some(parser.value()?.string()?);
+            }
+            Long("user") => {
+                user = Some(parser.value()?.string()?);
+            }
+            Long("fingerprint") => {
+                fingerprint = Some(parser.value()?.string()?);
+            }
+            Long("help") | Short('h') => {
+                println!("{help}");
+                std::process::exit(0);
+            }
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    // never ask, sshd can't answer
+    let (Some(server), Some(user)) = (server, user) else {
+        exit_with_message("--server and --user are required.");
+    };
+
+    if crate::serverauth::authorized_keys(con, &server, &user, fingerprint.as_deref()).is_err() {
+        exit_with_message("Could not generate authorized keys.");
+    };
+
+    Ok(())
+}
+
+pub fn history(
+    con: &mut postgres::Client,
+    parser: &mut lexopt::Parser,
+) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut workdir: Option<OsString> = None;
+    let mut server: Option<String> = None;
+    let mut sshuser: Option<String> = None;
+
+    let help = "oerec-serverauth-history
+Show how the authorized_keys of a server changed (see write-serverauth --git)
+
+Usage: oerec serverauth-history [OPTIONS] --workdir <WORKDIR> --server <SERVER>
+
+Options:
+        --workdir <WORKDIR>    [alias: --dir]
+        --server <SERVER>      Server name or IP
+        --sshuser <SSHUSER>    Show changes for SSHUSER only
+
+    -h, --help                 Print this message";
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("workdir" | "dir") => {
+                workdir = Some(parser.value()?.parse()?);
+            }
+            Long("server") => {
+                server = Some(parser.value()?.string()?);
+            }
+            Long("sshuser") => {
+                sshuser = Some(parser.value()?.string()?);
+            }
             Long("help") | Short('h') => {
                 println!("{help}");
                 std::process::exit(0);
@@ -75,7 +225,16 @@ Options:
         }
     }
 
-    crate::serverauth::write(con, workdir.as_deref(), force);
+    if crate::serverauth::history(
+        con,
+        workdir.as_deref(),
+        server.as_deref(),
+        sshuser.as_deref(),
+    )
+    .is_err()
+    {
+        exit_with_message("Could not show server auth history.");
+    };
 
     Ok(())
 }
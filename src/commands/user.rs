@@ -286,6 +286,8 @@ pub fn list(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<(
     let mut email: Option<String> = None;
     let mut userid: Option<String> = None;
     let mut name: Option<String> = None;
+    let mut added_by: Option<String> = None;
+    let mut added_after: Option<String> = None;
     let mut exact: bool = false;
     let mut json: bool = false;
 
@@ -295,14 +297,16 @@ List user accounts
 Usage: oerec list-user [OPTIONS]
 
 Options:
-        --email <EMAIL>    List user by EMAIL
-        --name <NAME>      List user by NAME
-        --id <ID>          List user w/ ID
+        --email <EMAIL>             List user by EMAIL
+        --name <NAME>               List user by NAME
+        --id <ID>                   List user w/ ID
+        --added-by <OPERATOR>       List user added by OPERATOR
+        --added-after <DATETIME>    List user added after DATETIME (YYYY-MM-DD [HH:MI:SS])
 
-    -e, --exact            Only list exact matches
-    -j, --json             Set output mode to JSON
+    -e, --exact                     Only list exact matches
+    -j, --json                      Set output mode to JSON
 
-    -h, --help             Print this message";
+    -h, --help                      Print this message";
 
     while let Some(arg) = parser.next()? {
         match arg {
@@ -315,6 +319,12 @@ Options:
             Long("id" | "userid") => {
                 userid = Some(parser.value()?.string()?);
             }
+            Long("added-by") => {
+                added_by = Some(parser.value()?.string()?);
+            }
+            Long("added-after") => {
+                added_after = Some(parser.value()?.string()?);
+            }
             Long("exact") | Short('e') => {
                 exact = true;
             }
@@ -334,6 +344,8 @@ Options:
         email.as_deref(),
         name.as_deref(),
         userid.as_deref(),
+        added_by.as_deref(),
+        added_after.as_deref(),
         exact,
         json,
     )
@@ -21,6 +21,7 @@ Options:
         --usergroup <USERGROUP>          [alias: --groupname]
         --serveraccess <SERVERACCESS>
         --until <UNTIL>                  Format: YYYY-MM-DD, optional w/ HH:MI:SS
+                                         (also set as expiry-time= on the servers)
         --comment <COMMENT>
 
     -h, --help                           Print this message";
@@ -137,6 +138,8 @@ pub fn list(con: &mut postgres::Client, parser: &mut lexopt::Parser) -> Result<(
     let mut serveraccess: Option<String> = None;
     let mut servergroup: Option<String> = None;
     let mut usergroup: Option<String> = None;
+    let mut added_by: Option<String> = None;
+    let mut added_after: Option<String> = None;
     let mut show_expired: bool = false;
     let mut show_disabled: bool = false;
     let mut exact: bool = false;
@@ -163,7 +166,9 @@ Options:
 
 Filter:
         --servergroup <SERVERGROUP>     Filter output by server group
-        --usergroup <USERGROUP>         Filter output by user group";
+        --usergroup <USERGROUP>         Filter output by user group
+        --added-by <OPERATOR>           Filter output by OPERATOR who added the user access
+        --added-after <DATETIME>        Filter output by user access added after DATETIME (YYYY-MM-DD [HH:MI:SS])";
 
     while let Some(arg) = parser.next()? {
         match arg {
@@ -188,6 +193,12 @@ Filter:
             Long("usergroup") => {
                 usergroup = Some(parser.value()?.string()?);
             }
+            Long("added-by") => {
+                added_by = Some(parser.value()?.string()?);
+            }
+            Long("added-after") => {
+                added_after = Some(parser.value()?.string()?);
+            }
             Long("expired") => {
                 show_expired = true;
             }
@@ -217,6 +228,8 @@ Filter:
         serveraccess.as_deref(),
         servergroup.as_deref(),
         usergroup.as_deref(),
+        added_by.as_deref(),
+        added_after.as_deref(),
         exact,
         show_expired,
         show_disabled,
@@ -0,0 +1,886 @@
+use log::{error, info};
+use owo_colors::{OwoColorize, Stream::Stdout};
+use prettytable::{format, Table};
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::logging::get_ssh_client;
+use crate::serverauth::read_tree;
+
+const DEPLOYED_DIR: &str = ".oerec-deployed";
+const LOCK_FILE: &str = ".oerec-deploy.lock";
+
+pub enum Transport {
+    // run commands as LOGIN@host
+    Ssh { login: String },
+    // hosts are directories below root (for testing): <root>/<ip>/home/<user>, <root>/<ip>/etc/ssh/sshd_config
+    Local { root: PathBuf },
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    Install,
+    Remove,
+    // host is gone (deleted / disabled), only drop the deployed state
+    Forget,
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Action::Install => write!(f, "install"),
+            Action::Remove => write!(f, "remove"),
+            Action::Forget => write!(f, "forget"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Job {
+    path: PathBuf,
+    host: String,
+    // None: host wide file (in /etc/ssh)
+    user: Option<String>,
+    file: String,
+    action: Action,
+    // file to install (for 'remove' only set for root: the fallback key)
+    content: Option<String>,
+}
+
+// removes the lock file when the deploy ends (also on errors)
+struct Lock(PathBuf);
+
+impl Lock {
+    fn acquire(path: PathBuf) -> Result<Lock, String> {
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => Ok(Lock(path)),
+            Err(_) => Err(format!(
+                "Could not acquire lock '{}' (another deploy running?).",
+                path.display()
+            )),
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if fs::remove_file(&self.0).is_err() {
+            eprintln!("Could not remove lock '{}'.", self.0.display());
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DeployResult {
+    pub host: String,
+    pub user: Option<String>,
+    pub file: String,
+    pub action: Action,
+    pub created_user: bool,
+    pub ok: bool,
+    pub message: Option<String>,
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+}
+
+// <ip>/<user>/authorized_keys & <ip>/<user>/authorized_principals (to ~/.ssh),
+// <ip>/trusted_user_ca_keys (to /etc/ssh): (host, user, file)
+fn split_path(path: &Path) -> Option<(String, Option<String>, String)> {
+    let parts = path
+        .iter()
+        .map(|p| p.to_string_lossy().to_string())
+        .collect::<Vec<String>>();
+
+    if parts.first()?.parse::<IpAddr>().is_err() {
+        return None;
+    }
+
+    match parts.as_slice() {
+        [host, user, file] if file == "authorized_keys" || file == "authorized_principals" => {
+            Some((host.clone(), Some(user.clone()), file.clone()))
+        }
+        [host, file] if file == "trusted_user_ca_keys" => Some((host.clone(), None, file.clone())),
+        _ => None,
+    }
+}
+
+// truncated / empty files would lock users out (like deploy_keys.sh: < 127 bytes)
+fn check_size(job: &Job, min_size: usize) -> Result<(), String> {
+    match (job.action, &job.content) {
+        (Action::Install, Some(content))
+            if job.file == "authorized_keys" && content.len() < min_size =>
+        {
+            Err(format!(
+                "file too small ({} bytes, see --min-size)",
+                content.len()
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn plan(
+    desired: &BTreeMap<PathBuf, Vec<String>>,
+    deployed: &BTreeMap<PathBuf, Vec<String>>,
+    all: bool,
+    server: Option<&str>,
+    root_key: Option<&str>,
+) -> Vec<Job> {
+    let mut jobs = Vec::new();
+
+    let hosts = desired
+        .keys()
+        .filter_map(|p| split_path(p).map(|(host, _, _)| host))
+        .collect::<BTreeSet<String>>();
+
+    for (path, lines) in desired {
+        let Some((host, user, file)) = split_path(path) else {
+            continue;
+        };
+
+        if server.is_some_and(|s| s != host) {
+            continue;
+        }
+
+        if all || server.is_some() || deployed.get(path) != Some(lines) {
+            jobs.push(Job {
+                path: path.clone(),
+                host,
+                user,
+                file,
+                action: Action::Install,
+                content: Some(lines.join("\n") + "\n"),
+            });
+        }
+    }
+
+    for path in deployed.keys().filter(|p| !desired.contains_key(*p)) {
+        let Some((host, user, file)) = split_path(path) else {
+            continue;
+        };
+
+        if server.is_some_and(|s| s != host) {
+            continue;
+        }
+
+        let action = if hosts.contains(&host) {
+            Action::Remove
+        } else {
+            Action::Forget
+        };
+
+        // never lock out root, replace w/ the fallback key instead
+        let content = if file == "authorized_keys" && user.as_deref() == Some("root") {
+            root_key.map(str::to_string)
+        } else {
+            None
+        };
+
+        jobs.push(Job {
+            path: path.clone(),
+            host,
+            user,
+            file,
+            action,
+            content,
+        });
+    }
+
+    jobs
+}
+
+fn ssh(login: &str, host: &str, script: &str, stdin: &str) -> Result<String, String> {
+    let mut child = Command::new("ssh")
+        .args([
+            "-T",
+            "-o",
+            "BatchMode=yes",
+            "-o",
+            "ConnectTimeout=10",
+            &format!("{login}@{host}"),
+            script,
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not run ssh: {e}"))?;
+
+    if let Some(mut input) = child.stdin.take() {
+        input
+            .write_all(stdin.as_bytes())
+            .map_err(|e| format!("could not send file: {e}"))?;
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("could not run ssh: {e}"))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+// (directory, owner, mode) of a deployed file on the host, shell syntax
+fn ssh_target(user: Option<&str>) -> (String, &str, &str) {
+    match user {
+        Some(user) => (r#""$home/.ssh""#.to_string(), user, "0640"),
+        None => ("/etc/ssh".to_string(), "root", "0644"),
+    }
+}
+
+fn ssh_install_script(user: Option<&str>, file: &str, create_user: bool) -> String {
+    let mut script = "set -e\n".to_string();
+
+    if let Some(user) = user {
+        let create = if create_user {
+            format!(
+                r#"useradd --create-home --user-group --comment {user} {user}
+  for opt in AllowUsers AllowGroups; do
+    if grep -q "^$opt" /etc/ssh/sshd_config && ! grep -qE "^$opt.*[[:space:]]{user}([[:space:]]|\$)" /etc/ssh/sshd_config; then
+      sed -i "/^$opt/s/\$/ {user}/" /etc/ssh/sshd_config
+    fi
+  done
+  systemctl reload sshd >/dev/null 2>&1 || systemctl reload ssh >/dev/null 2>&1 || true
+  echo created"#
+            )
+        } else {
+            r#"echo "OS user not found" >&2
+  exit 3"#
+                .to_string()
+        };
+
+        script.push_str(&format!(
+            r#"if ! id {user} >/dev/null 2>&1; then
+  {create}
+fi
+home=$(getent passwd {user} | cut -d: -f6)
+mkdir -p "$home/.ssh"
+chown {user}: "$home/.ssh"
+chmod 0700 "$home/.ssh"
+"#
+        ));
+    }
+
+    let (dir, owner, mode) = ssh_target(user);
+
+    script.push_str(&format!(
+        r#"cat >{dir}/{file}.new
+chown {owner}: {dir}/{file}.new
+chmod {mode} {dir}/{file}.new
+if [ -f {dir}/{file} ]; then cp -p {dir}/{file} {dir}/{file}~; fi
+mv {dir}/{file}.new {dir}/{file}"#
+    ));
+
+    script
+}
+
+fn ssh_remove_script(user: Option<&str>, file: &str) -> String {
+    let mut script = "set -e\n".to_string();
+
+    if let Some(user) = user {
+        script.push_str(&format!(
+            r#"home=$(getent passwd {user} | cut -d: -f6)
+[ -n "$home" ] || exit 0
+"#
+        ));
+    }
+
+    let (dir, _, _) = ssh_target(user);
+
+    script.push_str(&format!(
+        r#"if [ -f {dir}/{file} ]; then
+  cp -p {dir}/{file} {dir}/{file}~
+  rm -f {dir}/{file}
+fi"#
+    ));
+
+    script
+}
+
+fn local_allow(root: &Path, user: &str) -> Result<(), String> {
+    let config = root.join("etc/ssh/sshd_config");
+
+    let Ok(contents) = fs::read_to_string(&config) else {
+        return Ok(());
+    };
+
+    let mut lines = Vec::new();
+    for line in contents.lines() {
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+
+        if words
+            .first()
+            .is_some_and(|w| w.eq(&"AllowUsers") || w.eq(&"AllowGroups"))
+            && !words[1..].contains(&user)
+        {
+            lines.push(format!("{line} {user}"));
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+
+    fs::write(&config, lines.join("\n") + "\n").map_err(|e| e.to_string())
+}
+
+impl Transport {
+    // returns true if the OS user was created
+    fn install(
+        &self,
+        host: &str,
+        user: Option<&str>,
+        file: &str,
+        content: &str,
+        create_user: bool,
+    ) -> Result<bool, String> {
+        match self {
+            Transport::Ssh { login } => {
+                let script = ssh_install_script(user, file, create_user);
+                let out = ssh(login, host, &script, content)?;
+                Ok(out.lines().any(|l| l.eq("created")))
+            }
+            Transport::Local { root } => {
+                let root = root.join(host);
+                let mut created = false;
+
+                let (dir, mode) = match user {
+                    Some(user) => {
+                        let home = root.join("home").join(user);
+
+                        if !home.is_dir() {
+                            if !create_user {
+                                return Err("OS user not found".to_string());
+                            }
+
+                            fs::create_dir_all(&home).map_err(|e| e.to_string())?;
+                            local_allow(&root, user)?;
+                            created = true;
+                        }
+
+                        let ssh = home.join(".ssh");
+                        fs::create_dir_all(&ssh).map_err(|e| e.to_string())?;
+                        fs::set_permissions(&ssh, fs::Permissions::from_mode(0o700))
+                            .map_err(|e| e.to_string())?;
+
+                        (ssh, 0o640)
+                    }
+                    None => {
+                        let etc = root.join("etc/ssh");
+                        fs::create_dir_all(&etc).map_err(|e| e.to_string())?;
+
+                        (etc, 0o644)
+                    }
+                };
+
+                let new = dir.join(format!("{file}.new"));
+                fs::write(&new, content).map_err(|e| e.to_string())?;
+                fs::set_permissions(&new, fs::Permissions::from_mode(mode))
+                    .map_err(|e| e.to_string())?;
+
+                let target = dir.join(file);
+                if target.is_file() {
+                    fs::copy(&target, dir.join(format!("{file}~"))).map_err(|e| e.to_string())?;
+                }
+                fs::rename(&new, &target).map_err(|e| e.to_string())?;
+
+                Ok(created)
+            }
+        }
+    }
+
+    fn remove(&self, host: &str, user: Option<&str>, file: &str) -> Result<(), String> {
+        match self {
+            Transport::Ssh { login } => {
+                ssh(login, host, &ssh_remove_script(user, file), "").map(|_| ())
+            }
+            Transport::Local { root } => {
+                let dir = match user {
+                    Some(user) => root.join(host).join("home").join(user).join(".ssh"),
+                    None => root.join(host).join("etc/ssh"),
+                };
+                let target = dir.join(file);
+
+                if target.is_file() {
+                    fs::copy(&target, dir.join(format!("{file}~"))).map_err(|e| e.to_string())?;
+                    fs::remove_file(&target).map_err(|e| e.to_string())?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn run(&self, job: &Job, create_user: bool, min_size: usize) -> DeployResult {
+        let mut result = DeployResult {
+            host: job.host.clone(),
+            user: job.user.clone(),
+            file: job.file.clone(),
+            action: job.action,
+            created_user: false,
+            ok: false,
+            message: None,
+        };
+
+        if job.user.as_deref().is_some_and(|u| !valid_name(u)) {
+            result.message = Some("invalid user name".to_string());
+            return result;
+        }
+
+        let user = job.user.as_deref();
+
+        let res = check_size(job, min_size).and_then(|()| match (job.action, &job.content) {
+            (Action::Forget, _) => Ok(()),
+            (Action::Install, Some(content)) => self
+                .install(&job.host, user, &job.file, content, create_user)
+                .map(|created| result.created_user = created),
+            (Action::Remove, Some(content)) => self
+                .install(&job.host, user, &job.file, content, false)
+                .map(|_| ()),
+            (Action::Remove, None) if job.file == "authorized_keys" && user == Some("root") => {
+                Err("refusing to remove root's keys w/o --root-key".to_string())
+            }
+            (_, None) => self.remove(&job.host, user, &job.file),
+        });
+
+        match res {
+            Ok(()) => result.ok = true,
+            Err(e) => result.message = Some(e),
+        }
+
+        result
+    }
+}
+
+fn update_state(deployed: &Path, job: &Job) -> std::io::Result<()> {
+    let path = deployed.join(&job.path);
+
+    if let (Action::Install, Some(content)) = (job.action, &job.content) {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, content)?;
+    } else if path.exists() {
+        fs::remove_file(&path)?;
+
+        for dir in path.ancestors().skip(1) {
+            if dir == deployed || fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// user@host, host for host wide files
+fn target(result: &DeployResult) -> String {
+    match &result.user {
+        Some(user) => format!("{user}@{}", result.host),
+        None => result.host.clone(),
+    }
+}
+
+fn print_report(results: &[DeployResult]) {
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+    table.set_titles(row!["host", "user", "file", "action", "result", "message"]);
+
+    for r in results {
+        let status = if r.ok {
+            "ok".if_supports_color(Stdout, owo_colors::OwoColorize::green)
+                .to_string()
+        } else {
+            "failed"
+                .if_supports_color(Stdout, owo_colors::OwoColorize::red)
+                .to_string()
+        };
+
+        let mut message = r.message.clone().unwrap_or_default();
+        if r.created_user {
+            message = "created OS user".to_string();
+        }
+
+        table.add_row(row![
+            r.host,
+            r.user.as_deref().unwrap_or_default(),
+            r.file,
+            r.action,
+            status,
+            message
+        ]);
+    }
+
+    table.printstd();
+}
+
+// Ok(false): some jobs failed
+#[allow(clippy::too_many_arguments, clippy::fn_params_excessive_bools)]
+pub fn deploy(
+    dir: &Path,
+    transport: &Transport,
+    jobs: usize,
+    all: bool,
+    server: Option<&str>,
+    root_key: Option<&Path>,
+    create_user: bool,
+    min_size: usize,
+    dry_run: bool,
+    json: bool,
+) -> Result<bool, String> {
+    let root_key = match root_key {
+        Some(f) => {
+            Some(fs::read_to_string(f).map_err(|_| format!("Could not read '{}'.", f.display()))?)
+        }
+        None => None,
+    };
+
+    if !dir.is_dir() {
+        return Err("Workdir not found (run write-serverauth first).".to_string());
+    }
+
+    let _lock = Lock::acquire(dir.join(LOCK_FILE))?;
+
+    let deployed = dir.join(DEPLOYED_DIR);
+    let planned = plan(
+        &read_tree(dir)?,
+        &read_tree(&deployed)?,
+        all,
+        server,
+        root_key.as_deref(),
+    );
+
+    let results = if dry_run {
+        planned
+            .iter()
+            .map(|job| {
+                let size = check_size(job, min_size);
+                DeployResult {
+                    host: job.host.clone(),
+                    user: job.user.clone(),
+                    file: job.file.clone(),
+                    action: job.action,
+                    created_user: false,
+                    ok: size.is_ok(),
+                    message: Some(size.err().unwrap_or_else(|| "dry run".to_string())),
+                }
+            })
+            .collect::<Vec<DeployResult>>()
+    } else {
+        // jobs for the same host run one after another (sshd_config, user creation)
+        let mut hosts: BTreeMap<&str, Vec<&Job>> = BTreeMap::new();
+        for job in &planned {
+            hosts.entry(&job.host).or_default().push(job);
+        }
+
+        let queue = Mutex::new(hosts.into_values().collect::<Vec<Vec<&Job>>>());
+        let done: Mutex<Vec<(&Job, DeployResult)>> = Mutex::new(Vec::new());
+
+        thread::scope(|s| {
+            for _ in 0..jobs.max(1) {
+                s.spawn(|| {
+                    while let Some(batch) = queue.lock().ok().and_then(|mut q| q.pop()) {
+                        for job in batch {
+                            let result = transport.run(job, create_user, min_size);
+                            if let Ok(mut done) = done.lock() {
+                                done.push((job, result));
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        let mut done = done.into_inner().unwrap_or_default();
+        done.sort_by(|a, b| {
+            (&a.1.host, &a.1.user, &a.1.file).cmp(&(&b.1.host, &b.1.user, &b.1.file))
+        });
+
+        let mut results = Vec::new();
+        for (job, result) in done {
+            if result.ok {
+                info!(
+                    "({}) Deployed {} {} {}",
+                    &get_ssh_client(),
+                    result.action,
+                    &result.file,
+                    target(&result)
+                );
+
+                if let Err(e) = update_state(&deployed, job) {
+                    error!(
+                        "Could not update deployed state for '{}': {}",
+                        job.path.display(),
+                        e
+                    );
+                }
+            } else {
+                error!(
+                    "({}) Could not deploy {} {} {}: {}",
+                    &get_ssh_client(),
+                    result.action,
+                    &result.file,
+                    target(&result),
+                    result.message.as_deref().unwrap_or_default()
+                );
+            }
+            results.push(result);
+        }
+
+        results
+    };
+
+    let failed = results.iter().filter(|r| !r.ok).count();
+
+    if json {
+        println!("{}", serde_json::to_string(&results).unwrap_or_default());
+    } else if results.is_empty() {
+        println!("Nothing to deploy.");
+    } else {
+        print_report(&results);
+        println!();
+        if dry_run {
+            println!("{} planned", results.len());
+        } else {
+            println!("{} deployed, {} failed", results.len() - failed, failed);
+        }
+    }
+
+    Ok(failed == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_path_accepts_deployed_files() {
+        assert_eq!(
+            split_path(Path::new("10.0.0.1/root/authorized_keys")),
+            Some((
+                "10.0.0.1".to_string(),
+                Some("root".to_string()),
+                "authorized_keys".to_string()
+            ))
+        );
+        assert_eq!(
+            split_path(Path::new("10.0.0.1/deploy/authorized_principals")),
+            Some((
+                "10.0.0.1".to_string(),
+                Some("deploy".to_string()),
+                "authorized_principals".to_string()
+            ))
+        );
+        assert_eq!(
+            split_path(Path::new("10.0.0.1/trusted_user_ca_keys")),
+            Some((
+                "10.0.0.1".to_string(),
+                None,
+                "trusted_user_ca_keys".to_string()
+            ))
+        );
+
+        assert_eq!(split_path(Path::new("10.0.0.1/root/other")), None);
+        assert_eq!(split_path(Path::new("web01/root/authorized_keys")), None);
+        assert_eq!(split_path(Path::new("10.0.0.1/root")), None);
+    }
+
+    #[test]
+    fn plan_keeps_root_key_for_authorized_keys_only() {
+        let desired = BTreeMap::from([(
+            PathBuf::from("10.0.0.1/deploy/authorized_keys"),
+            vec!["key".to_string()],
+        )]);
+        let deployed = BTreeMap::from([
+            (
+                PathBuf::from("10.0.0.1/root/authorized_keys"),
+                vec!["old".to_string()],
+            ),
+            (
+                PathBuf::from("10.0.0.1/root/authorized_principals"),
+                vec!["alice@x".to_string()],
+            ),
+            (
+                PathBuf::from("10.0.0.1/trusted_user_ca_keys"),
+                vec!["ca".to_string()],
+            ),
+        ]);
+
+        let jobs = plan(&desired, &deployed, false, None, Some("fallback\n"));
+        let jobs = jobs
+            .iter()
+            .map(|j| (j.file.as_str(), j.action, j.content.as_deref()))
+            .map(|(f, a, c)| format!("{f} {a} {c:?}"))
+            .collect::<Vec<String>>();
+
+        assert_eq!(
+            jobs,
+            vec![
+                r#"authorized_keys install Some("key\n")"#,
+                r#"authorized_keys remove Some("fallback\n")"#,
+                "authorized_principals remove None",
+                "trusted_user_ca_keys remove None",
+            ]
+        );
+    }
+
+    // fresh <tmp>/oerec-deploy-<name>-<pid>/{workdir,hosts}
+    fn scratch(name: &str) -> (PathBuf, PathBuf) {
+        let base = std::env::temp_dir().join(format!("oerec-deploy-{name}-{}", std::process::id()));
+        _ = fs::remove_dir_all(&base);
+
+        let (workdir, hosts) = (base.join("workdir"), base.join("hosts"));
+        fs::create_dir_all(&workdir).unwrap();
+        fs::create_dir_all(&hosts).unwrap();
+
+        (workdir, hosts)
+    }
+
+    fn put(path: &Path, contents: &[u8]) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn run_local(workdir: &Path, hosts: &Path, create_user: bool) -> Result<bool, String> {
+        let transport = Transport::Local {
+            root: hosts.to_path_buf(),
+        };
+
+        deploy(
+            workdir,
+            &transport,
+            2,
+            false,
+            None,
+            None,
+            create_user,
+            16,
+            false,
+            true,
+        )
+    }
+
+    #[test]
+    fn deploy_local_installs_changed_files() {
+        let (workdir, hosts) = scratch("install");
+        let keys = "ssh-ed25519 AAAAkey1 alice@x\n";
+
+        put(
+            &workdir.join("10.0.0.1/alice/authorized_keys"),
+            keys.as_bytes(),
+        );
+        // below --min-size (truncated)
+        put(
+            &workdir.join("10.0.0.1/bob/authorized_keys"),
+            b"ssh-ed25519\n",
+        );
+        put(
+            &hosts.join("10.0.0.1/home/alice/.ssh/authorized_keys"),
+            b"old\n",
+        );
+        fs::create_dir_all(hosts.join("10.0.0.1/home/bob")).unwrap();
+
+        assert_eq!(run_local(&workdir, &hosts, false), Ok(false));
+
+        let ssh = hosts.join("10.0.0.1/home/alice/.ssh");
+        assert_eq!(
+            fs::read_to_string(ssh.join("authorized_keys")).unwrap(),
+            keys
+        );
+        assert_eq!(
+            fs::read_to_string(ssh.join("authorized_keys~")).unwrap(),
+            "old\n"
+        );
+        assert!(!hosts
+            .join("10.0.0.1/home/bob/.ssh/authorized_keys")
+            .exists());
+
+        let deployed = workdir.join(DEPLOYED_DIR).join("10.0.0.1");
+        assert_eq!(
+            fs::read_to_string(deployed.join("alice/authorized_keys")).unwrap(),
+            keys
+        );
+        assert!(!deployed.join("bob/authorized_keys").exists());
+        assert!(!workdir.join(LOCK_FILE).exists());
+
+        // removed files are backed up & removed on the host
+        fs::remove_file(workdir.join("10.0.0.1/bob/authorized_keys")).unwrap();
+        put(&ssh.join("authorized_keys"), b"changed on the host\n");
+        fs::remove_dir_all(workdir.join("10.0.0.1/alice")).unwrap();
+        put(
+            &workdir.join("10.0.0.1/carol/authorized_keys"),
+            keys.as_bytes(),
+        );
+        fs::create_dir_all(hosts.join("10.0.0.1/home/carol")).unwrap();
+
+        assert_eq!(run_local(&workdir, &hosts, false), Ok(true));
+
+        assert!(!ssh.join("authorized_keys").exists());
+        assert_eq!(
+            fs::read_to_string(ssh.join("authorized_keys~")).unwrap(),
+            "changed on the host\n"
+        );
+        assert!(!deployed.join("alice").exists());
+        assert!(deployed.join("carol/authorized_keys").exists());
+    }
+
+    #[test]
+    fn deploy_local_creates_users() {
+        let (workdir, hosts) = scratch("users");
+        let config = hosts.join("10.0.0.1/etc/ssh/sshd_config");
+
+        put(
+            &workdir.join("10.0.0.1/carol/authorized_keys"),
+            b"ssh-ed25519 AAAAkey1 carol@x\n",
+        );
+        put(&config, b"PermitRootLogin no\nAllowUsers root\n");
+
+        assert_eq!(run_local(&workdir, &hosts, false), Ok(false));
+        assert!(!hosts.join("10.0.0.1/home/carol").exists());
+
+        assert_eq!(run_local(&workdir, &hosts, true), Ok(true));
+        assert!(hosts
+            .join("10.0.0.1/home/carol/.ssh/authorized_keys")
+            .is_file());
+        assert_eq!(
+            fs::read_to_string(&config).unwrap(),
+            "PermitRootLogin no\nAllowUsers root carol\n"
+        );
+    }
+
+    #[test]
+    fn deploy_releases_lock_on_errors() {
+        let (workdir, hosts) = scratch("lock");
+
+        put(&workdir.join(LOCK_FILE), b"");
+        assert!(run_local(&workdir, &hosts, false).is_err());
+        // somebody else's lock stays
+        assert!(workdir.join(LOCK_FILE).exists());
+        fs::remove_file(workdir.join(LOCK_FILE)).unwrap();
+
+        // not UTF-8, the workdir can't be read
+        put(
+            &workdir.join("10.0.0.1/alice/authorized_keys"),
+            b"\xff\xfe\n",
+        );
+        assert!(run_local(&workdir, &hosts, false).is_err());
+        assert!(!workdir.join(LOCK_FILE).exists());
+    }
+}
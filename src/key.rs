@@ -1,14 +1,23 @@
-use base64::{engine, Engine};
+use base64::{alphabet, engine, Engine};
 use log::info;
+use md5::Md5;
 use owo_colors::{OwoColorize, Stream::Stdout};
 use postgres::{Client, Error};
 use prettytable::{format, Table};
 use serde_derive::Serialize;
 use sha2::{Digest, Sha256};
-use std::io::{self, Write};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+use time::PrimitiveDateTime;
 
+use crate::access::{expand, load};
+use crate::blocklist::is_blocked;
 use crate::logging::get_ssh_client;
-use crate::{ask_for, exit_with_message, set_or_ask_for, ListObject};
+use crate::metadata::{self, Metadata};
+use crate::serverauth::{options, warn_filtered};
+use crate::{
+    ask_for, exit_with_message, parse_datetime, parse_duration, set_or_ask_for, ListObject,
+};
 
 #[derive(Debug, Serialize)]
 struct SshKeysQuery {
@@ -17,26 +26,52 @@ struct SshKeysQuery {
     pub sshkey: String,
     pub fingerprint: String,
     pub comment: Option<String>,
+    pub until: Option<String>,
+    #[serde(skip)]
+    pub expires_in: Option<i64>,
+    pub sshfrom: Option<String>,
+    pub sshcommand: Option<String>,
+    pub sshoption: Option<String>,
+    // YYYYMMDDHHMM
+    #[serde(skip)]
+    pub expiry: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub randomart: Option<String>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
 }
 
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::too_many_lines)]
 pub fn add(
     pgclient: &mut Client,
     email: Option<&str>,
     publickey: Option<&str>,
     comment: Option<&str>,
+    until: Option<&str>,
+    sshfrom: Option<&str>,
+    sshcommand: Option<&str>,
+    sshoption: Option<&str>,
 ) -> Result<(), Error> {
     println!("Add SSH key");
-    let query_string = r#"INSERT INTO sshkeys (user_id, sshkey, fingerprint, comment)
+    let query_string = r#"INSERT INTO sshkeys (user_id, sshkey, fingerprint, comment, best_before, created_by,
+                                               sshfrom, sshcommand, sshoption)
                           SELECT "user".id,
                                  $1,
                                  $2,
-                                 $3
+                                 $3,
+                                 $5,
+                                 $6,
+                                 $7,
+                                 $8,
+                                 $9
                           FROM "user"
-                          WHERE "user".email = $4"#;
+                          WHERE "user".email = $4
+                          RETURNING id"#;
 
     let newemail = ask_for(&ListObject::UserEmail, email, None, pgclient);
 
-    if newemail.eq("") {
+    if newemail.is_empty() {
         exit_with_message("User email cannot be empty.");
     }
 
@@ -49,28 +84,65 @@ pub fn add(
         exit_with_message("User not found.")
     }
 
-    let mut newkey = set_or_ask_for(publickey, "Public SSH key");
+    let newkey = set_or_ask_for(publickey, "Public SSH key");
 
-    if newkey.eq("") {
+    if newkey.is_empty() {
         exit_with_message("Key cannot be empty.");
     }
 
-    if newkey.split(' ').count() < 2 {
-        exit_with_message("Invalid key format.")
+    let keyline = key_line(&newkey);
+    let newkey = keyline.key();
+    let fingerprint = &generate_fingerprint(&newkey);
+
+    if is_blocked(pgclient, fingerprint)? {
+        exit_with_message("Key is blocked (known-compromised).");
     }
 
-    newkey = newkey.split(' ').collect::<Vec<&str>>()[..2].join(" ");
-    let fingerprint = &generate_fingerprint(&newkey);
+    if let Some((id, owner)) = key_owner(pgclient, fingerprint)? {
+        exit_with_message(&format!("Key already exists for user '{owner}' (ID {id})."));
+    }
 
-    let newcomment = set_or_ask_for(comment, "Comment");
+    // the key's own comment is the default
+    let newcomment = match &keyline.comment {
+        Some(keycomment) => {
+            let newcomment = set_or_ask_for(
+                comment,
+                &format!(
+                    "Comment [<Enter>: '{}']",
+                    keycomment.if_supports_color(Stdout, owo_colors::OwoColorize::green)
+                ),
+            );
+            if newcomment.is_empty() {
+                keycomment.clone()
+            } else {
+                newcomment
+            }
+        }
+        None => set_or_ask_for(comment, "Comment"),
+    };
     let newcomment = match &newcomment.trim().to_lowercase()[..] {
         "" | "null" => None,
         _ => Some(newcomment),
     };
 
-    pgclient.query(
+    // not asked for, the options of the key line are the default
+    let restrictions = key_restrictions(&keyline).with(sshfrom, sshcommand, sshoption);
+
+    let newuntil = until.map_or(restrictions.expiry_time, parse_until);
+
+    let res = pgclient.query(
         query_string,
-        &[&newkey, &fingerprint, &newcomment, &newemail],
+        &[
+            &newkey,
+            &fingerprint,
+            &newcomment,
+            &newemail,
+            &newuntil,
+            &get_ssh_client(),
+            &restrictions.sshfrom,
+            &restrictions.sshcommand,
+            &restrictions.sshoption,
+        ],
     )?;
 
     info!(
@@ -80,6 +152,10 @@ pub fn add(
         &newemail
     );
 
+    if let Some(row) = res.first() {
+        warn_filtered(pgclient, row.get("id"))?;
+    }
+
     println!(
         "{}",
         "Done.".if_supports_color(Stdout, owo_colors::OwoColorize::green)
@@ -88,6 +164,641 @@ pub fn add(
     Ok(())
 }
 
+// "" | "null": never expires
+fn parse_until(until: &str) -> Option<PrimitiveDateTime> {
+    match &until.trim().to_lowercase()[..] {
+        "" | "null" => None,
+        _ => match parse_datetime(until) {
+            Some(datetime) => Some(datetime),
+            None => exit_with_message("Could not parse datetime."),
+        },
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct SharedKey {
+    fingerprint: String,
+    ids: Vec<i64>,
+    emails: Vec<String>,
+    // false: registered more than once for the same user
+    shared: bool,
+}
+
+// (key id, email) of an already registered key
+fn key_owner(pgclient: &mut Client, fingerprint: &str) -> Result<Option<(i64, String)>, Error> {
+    let res = pgclient.query(
+        r#"SELECT sshkeys.id, "user".email
+           FROM sshkeys, "user"
+           WHERE sshkeys.user_id = "user".id
+             AND sshkeys.fingerprint = $1
+           ORDER BY sshkeys.id
+           LIMIT 1"#,
+        &[&fingerprint],
+    )?;
+
+    Ok(res.first().map(|row| (row.get("id"), row.get("email"))))
+}
+
+pub fn check(pgclient: &mut Client, json: bool) -> Result<(), Error> {
+    let mut found = Vec::new();
+
+    for row in pgclient.query(
+        r#"SELECT sshkeys.fingerprint,
+                  ARRAY_AGG(sshkeys.id ORDER BY sshkeys.id) AS ids,
+                  ARRAY_AGG(DISTINCT "user".email) AS emails,
+                  COUNT(DISTINCT sshkeys.user_id) > 1 AS shared
+           FROM sshkeys, "user"
+           WHERE sshkeys.user_id = "user".id
+           GROUP BY sshkeys.fingerprint
+           HAVING COUNT(*) > 1
+           ORDER BY sshkeys.fingerprint"#,
+        &[],
+    )? {
+        found.push(SharedKey {
+            fingerprint: row.get("fingerprint"),
+            ids: row.get("ids"),
+            emails: row.get("emails"),
+            shared: row.get("shared"),
+        });
+    }
+
+    let print_table = |keys: &[&SharedKey]| {
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+        table.set_titles(row!["fingerprint", "key ids", "emails"]);
+
+        for s in keys {
+            table.add_row(row![
+                s.fingerprint,
+                s.ids
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                s.emails.join(", ")
+            ]);
+        }
+
+        table.printstd();
+    };
+
+    if json {
+        println!("{}", serde_json::to_string(&found).unwrap_or_default());
+    } else {
+        let (shared, duplicate): (Vec<&SharedKey>, Vec<&SharedKey>) =
+            found.iter().partition(|s| s.shared);
+
+        if shared.is_empty() {
+            println!("No shared keys found.");
+        } else {
+            println!("Keys shared across users:");
+            print_table(&shared);
+        }
+
+        if !duplicate.is_empty() {
+            println!();
+            println!("Keys registered more than once for the same user:");
+            print_table(&duplicate);
+        }
+    }
+
+    if !found.is_empty() {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+// below the (configurable) minimum for new keys, but deprecated
+const AUDIT_MIN_RSA_BITS: usize = 3072;
+
+#[derive(Debug, Serialize)]
+struct AuditResult {
+    id: i64,
+    email: String,
+    keytype: String,
+    bits: Option<usize>,
+    fingerprint: String,
+    findings: Vec<&'static str>,
+}
+
+pub fn audit(pgclient: &mut Client, threshold: Option<usize>, json: bool) -> Result<(), Error> {
+    let graph = load(pgclient)?;
+
+    // users w/ (unexpired) access, directly or via (parents of) their groups
+    let mut memo = HashMap::new();
+    let mut with_access = HashSet::new();
+
+    for (userid, groups) in &graph.user_usergroups {
+        let groups = groups
+            .iter()
+            .flat_map(|g| expand(*g, &graph.usergroup_parents, &mut memo))
+            .collect::<HashSet<i64>>();
+
+        if graph
+            .useraccess
+            .iter()
+            .any(|ua| !ua.expired && ua.usergroup_id.is_some_and(|g| groups.contains(&g)))
+        {
+            with_access.insert(*userid);
+        }
+    }
+
+    with_access.extend(
+        graph
+            .useraccess
+            .iter()
+            .filter(|ua| !ua.expired)
+            .filter_map(|ua| ua.user_id),
+    );
+
+    let mut keys = graph
+        .keys
+        .iter()
+        .flat_map(|(userid, keys)| keys.iter().map(move |k| (*userid, k)))
+        .collect::<Vec<_>>();
+    keys.sort_by_key(|(_, k)| k.id);
+
+    // the same key w/ different fingerprints (e.g. stored w/o a recheck) counts, too
+    let mut results = Vec::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+
+    for (userid, key) in keys {
+        let (keytype, data) = key.sshkey.split_once(' ').unwrap_or((&key.sshkey, ""));
+        let parsed = parse_key(keytype, data.trim()).ok();
+
+        let mut findings = Vec::new();
+
+        if key.blocked {
+            findings.push("blocked");
+        }
+
+        if keytype.eq("ssh-dss") {
+            findings.push("dsa");
+        }
+
+        if let Some(parsed) = &parsed {
+            if parsed.keytype.eq("ssh-rsa") && parsed.bits < AUDIT_MIN_RSA_BITS {
+                findings.push("weak-rsa");
+            }
+        }
+
+        let identity = parsed
+            .as_ref()
+            .map_or_else(|| key.fingerprint.clone(), |p| fingerprint(&p.blob));
+
+        if let Some(first) = seen.get(&identity) {
+            findings.push("duplicate");
+            let first: &mut AuditResult = &mut results[*first];
+            if !first.findings.contains(&"duplicate") {
+                first.findings.push("duplicate");
+            }
+        }
+
+        let user = graph.users.get(&userid);
+
+        if user.is_some_and(|u| u.disabled) {
+            findings.push("user-disabled");
+        }
+
+        if !with_access.contains(&userid) {
+            findings.push("no-access");
+        }
+
+        seen.entry(identity).or_insert(results.len());
+
+        results.push(AuditResult {
+            id: key.id,
+            email: user.map(|u| u.email.clone()).unwrap_or_default(),
+            keytype: keytype.to_string(),
+            bits: parsed.as_ref().map(|p| p.bits),
+            fingerprint: key.fingerprint.clone(),
+            findings,
+        });
+    }
+
+    results.retain(|r| !r.findings.is_empty());
+
+    let count = results.iter().map(|r| r.findings.len()).sum::<usize>();
+    let threshold = threshold
+        .or_else(|| crate::KEY_CONFIG.get().and_then(|c| c.audit_threshold))
+        .unwrap_or_default();
+
+    if json {
+        println!("{}", serde_json::to_string(&results).unwrap_or_default());
+    } else if results.is_empty() {
+        println!("No findings.");
+    } else {
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+        table.set_titles(row![
+            "id",
+            "email",
+            "type",
+            "bits",
+            "fingerprint",
+            "findings"
+        ]);
+
+        for r in &results {
+            table.add_row(row![
+                r.id,
+                r.email,
+                r.keytype,
+                r.bits.map_or_else(|| "-".to_string(), |b| b.to_string()),
+                r.fingerprint,
+                r.findings.join(", ")
+            ]);
+        }
+
+        table.printstd();
+
+        println!();
+        println!(
+            "{} findings in {} keys (threshold: {})",
+            count,
+            results.len(),
+            threshold
+        );
+    }
+
+    if count > threshold {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct VerifyResult {
+    id: i64,
+    email: String,
+    issue: &'static str,
+    detail: String,
+    fixed: bool,
+}
+
+#[allow(clippy::too_many_lines)]
+pub fn verify(pgclient: &mut Client, fix: bool, json: bool) -> Result<(), Error> {
+    let mut transaction = pgclient.transaction()?;
+
+    let rows = transaction.query(
+        r#"SELECT sshkeys.id, "user".email, sshkeys.sshkey, sshkeys.fingerprint
+           FROM sshkeys, "user"
+           WHERE sshkeys.user_id = "user".id
+           ORDER BY sshkeys.id"#,
+        &[],
+    )?;
+
+    let mut results = Vec::new();
+
+    for row in rows {
+        let id: i64 = row.get("id");
+        let email: String = row.get("email");
+        let sshkey: String = row.get("sshkey");
+        let stored: String = row.get("fingerprint");
+
+        let (key, fingerprint) = match canonical_key(&sshkey) {
+            Ok(canonical) => canonical,
+            Err(e) => {
+                results.push(VerifyResult {
+                    id,
+                    email,
+                    issue: "unparsable",
+                    detail: e,
+                    fixed: false,
+                });
+                continue;
+            }
+        };
+
+        let mut issues = Vec::new();
+
+        if key.ne(&sshkey) {
+            issues.push(VerifyResult {
+                id,
+                email: email.clone(),
+                issue: "non-canonical",
+                detail: "key not in canonical form (base64 / whitespace)".to_string(),
+                fixed: false,
+            });
+        }
+
+        if fingerprint.ne(&stored) {
+            issues.push(VerifyResult {
+                id,
+                email: email.clone(),
+                issue: "fingerprint-mismatch",
+                detail: format!("stored: {stored}, computed: {fingerprint}"),
+                fixed: false,
+            });
+        }
+
+        if issues.is_empty() {
+            continue;
+        }
+
+        if fix {
+            // the same key w/ the correct fingerprint can't be registered twice
+            let owner = transaction.query(
+                r#"SELECT sshkeys.id, "user".email
+                   FROM sshkeys, "user"
+                   WHERE sshkeys.user_id = "user".id
+                     AND sshkeys.fingerprint = $1
+                     AND sshkeys.id <> $2
+                   LIMIT 1"#,
+                &[&fingerprint, &id],
+            )?;
+
+            if let Some(owner) = owner.first() {
+                for issue in &mut issues {
+                    issue.detail.push_str(&format!(
+                        " (not fixed: key already exists for user '{}' (ID {}))",
+                        owner.get::<&str, String>("email"),
+                        owner.get::<&str, i64>("id")
+                    ));
+                }
+            } else {
+                transaction.execute(
+                    r"UPDATE sshkeys
+                      SET sshkey = $1,
+                          fingerprint = $2,
+                          modified_at = NOW(),
+                          modified_by = $3
+                      WHERE id = $4",
+                    &[&key, &fingerprint, &get_ssh_client(), &id],
+                )?;
+
+                info!(
+                    "({}) Fixed SSH key {} ('{}' -> '{}')",
+                    &get_ssh_client(),
+                    &id,
+                    &stored,
+                    &fingerprint
+                );
+
+                for issue in &mut issues {
+                    issue.fixed = true;
+                }
+            }
+        }
+
+        results.append(&mut issues);
+    }
+
+    transaction.commit()?;
+
+    let unfixed = results.iter().filter(|r| !r.fixed).count();
+
+    if json {
+        println!("{}", serde_json::to_string(&results).unwrap_or_default());
+    } else if results.is_empty() {
+        println!("All keys verified.");
+    } else {
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+        table.set_titles(row!["id", "email", "issue", "detail", "fixed"]);
+
+        for r in &results {
+            table.add_row(row![
+                r.id,
+                r.email,
+                r.issue,
+                r.detail,
+                if r.fixed { "yes" } else { "no" }
+            ]);
+        }
+
+        table.printstd();
+
+        println!();
+        println!(
+            "{} issues, {} fixed",
+            results.len(),
+            results.len() - unfixed
+        );
+    }
+
+    if unfixed > 0 {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct ImportResult {
+    line: usize,
+    status: &'static str,
+    fingerprint: Option<String>,
+    comment: Option<String>,
+    reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ImportSummary {
+    email: String,
+    added: usize,
+    skipped: usize,
+    invalid: usize,
+    keys: Vec<ImportResult>,
+}
+
+#[allow(clippy::too_many_lines)]
+pub fn import(
+    pgclient: &mut Client,
+    email: Option<&str>,
+    file: &str,
+    json: bool,
+) -> Result<(), Error> {
+    let newemail = ask_for(&ListObject::UserEmail, email, None, pgclient);
+
+    if newemail.is_empty() {
+        exit_with_message("User email cannot be empty.");
+    }
+
+    let res = pgclient.query(
+        r#"SELECT id FROM "user" WHERE email = $1 LIMIT 1"#,
+        &[&newemail],
+    )?;
+
+    if res.is_empty() {
+        exit_with_message("User not found.")
+    }
+
+    let userid: i64 = res[0].get("id");
+
+    let lines = if file.eq("-") {
+        io::stdin()
+            .lock()
+            .lines()
+            .collect::<Result<Vec<String>, _>>()
+    } else {
+        std::fs::File::open(file).and_then(|f| io::BufReader::new(f).lines().collect())
+    };
+
+    let Ok(lines) = lines else {
+        exit_with_message(&format!("Could not read '{file}'."))
+    };
+
+    // fingerprint -> owner
+    let mut known = HashMap::new();
+    for row in pgclient.query(
+        r#"SELECT sshkeys.fingerprint, "user".email
+           FROM sshkeys, "user"
+           WHERE sshkeys.user_id = "user".id"#,
+        &[],
+    )? {
+        known.insert(
+            row.get::<&str, String>("fingerprint"),
+            row.get::<&str, String>("email"),
+        );
+    }
+
+    let blocked = pgclient
+        .query(r"SELECT fingerprint FROM blocked_keys", &[])?
+        .iter()
+        .map(|row| row.get::<&str, String>("fingerprint"))
+        .collect::<HashSet<String>>();
+
+    let mut results = Vec::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+
+        let mut result = ImportResult {
+            line: i + 1,
+            status: "invalid",
+            fingerprint: None,
+            comment: None,
+            reason: None,
+        };
+
+        let checked = parse_line(line)
+            .map_err(|e| format!("Invalid key format: {e}"))
+            .and_then(|keyline| {
+                parse_restrictions(&keyline.options)
+                    .map(|restrictions| (keyline, restrictions))
+                    .map_err(|e| format!("Invalid key options: {e}"))
+            });
+
+        let (keyline, restrictions) = match checked {
+            Ok(checked) => checked,
+            Err(e) => {
+                result.reason = Some(e);
+                results.push(result);
+                continue;
+            }
+        };
+
+        result.comment.clone_from(&keyline.comment);
+
+        let fingerprint = match check_key(&keyline.keytype, &keyline.data) {
+            Ok(fingerprint) => fingerprint,
+            Err(e) => {
+                result.reason = Some(e);
+                results.push(result);
+                continue;
+            }
+        };
+
+        result.fingerprint = Some(fingerprint.clone());
+
+        if blocked.contains(&fingerprint) {
+            result.reason = Some("Key is blocked (known-compromised)".to_string());
+            results.push(result);
+            continue;
+        }
+
+        if let Some(owner) = known.get(&fingerprint) {
+            result.status = "skipped";
+            result.reason = Some(format!("Key already exists for user '{owner}'"));
+            results.push(result);
+            continue;
+        }
+
+        pgclient.query(
+            r"INSERT INTO sshkeys (user_id, sshkey, fingerprint, comment, best_before, created_by,
+                                   sshfrom, sshcommand, sshoption)
+              VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            &[
+                &userid,
+                &keyline.key(),
+                &fingerprint,
+                &keyline.comment,
+                &restrictions.expiry_time,
+                &get_ssh_client(),
+                &restrictions.sshfrom,
+                &restrictions.sshcommand,
+                &restrictions.sshoption,
+            ],
+        )?;
+
+        info!(
+            "({}) Added SSH key '{}' for user '{}'",
+            &get_ssh_client(),
+            &fingerprint,
+            &newemail
+        );
+
+        known.insert(fingerprint, newemail.clone());
+        result.status = "added";
+        results.push(result);
+    }
+
+    let count = |status: &str| results.iter().filter(|r| r.status.eq(status)).count();
+
+    let (added, skipped, invalid) = (count("added"), count("skipped"), count("invalid"));
+
+    let summary = ImportSummary {
+        email: newemail,
+        added,
+        skipped,
+        invalid,
+        keys: results,
+    };
+
+    if json {
+        println!("{}", serde_json::to_string(&summary).unwrap_or_default());
+    } else {
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+        table.set_titles(row!["line", "status", "fingerprint", "comment", "reason"]);
+
+        for r in &summary.keys {
+            table.add_row(row![
+                r.line,
+                r.status,
+                r.fingerprint.as_deref().unwrap_or("-"),
+                r.comment.as_deref().unwrap_or("-"),
+                r.reason.as_deref().unwrap_or("-")
+            ]);
+        }
+
+        if !summary.keys.is_empty() {
+            table.printstd();
+            println!();
+        }
+
+        println!(
+            "{} added, {} skipped, {} invalid",
+            summary.added, summary.skipped, summary.invalid
+        );
+    }
+
+    if summary.invalid > 0 {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
 pub fn delete(pgclient: &mut Client, keyid: Option<&str>, force: bool) -> Result<(), Error> {
     println!("Delete public SSH key");
     let query_string = r"DELETE FROM sshkeys WHERE id = $1";
@@ -99,7 +810,7 @@ pub fn delete(pgclient: &mut Client, keyid: Option<&str>, force: bool) -> Result
         pgclient,
     );
 
-    if oldkeyid.eq("") {
+    if oldkeyid.is_empty() {
         exit_with_message("Key ID cannot be empty.");
     }
 
@@ -149,19 +860,37 @@ pub fn delete(pgclient: &mut Client, keyid: Option<&str>, force: bool) -> Result
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn list(
     pgclient: &mut Client,
     email: Option<&str>,
     fingerprint: Option<&str>,
     with_key: bool,
     id: Option<&str>,
+    expiring_within: Option<&str>,
+    added_by: Option<&str>,
+    added_after: Option<&str>,
+    fingerprint_format: Option<&str>,
+    visual: bool,
     json: bool,
 ) -> std::result::Result<(), Error> {
-    let query_string = r#"SELECT sshkeys.id, "user".email, sshkey, sshkeys.fingerprint, sshkeys.comment
+    let query_string = r#"SELECT sshkeys.id, "user".email, sshkey, sshkeys.fingerprint, sshkeys.comment,
+                                 sshkeys.best_before::VARCHAR AS until,
+                                 EXTRACT(EPOCH FROM sshkeys.best_before - NOW())::BIGINT AS expires_in,
+                                 sshkeys.sshfrom, sshkeys.sshcommand, sshkeys.sshoption,
+                                 TO_CHAR(sshkeys.best_before, 'YYYYMMDDHH24MI') AS expiry,
+                                 sshkeys.created_at::TIMESTAMP(0)::VARCHAR AS created_at, sshkeys.created_by,
+                                 sshkeys.modified_at::TIMESTAMP(0)::VARCHAR AS modified_at, sshkeys.modified_by
                           FROM sshkeys, "user"
                           WHERE sshkeys.user_id = "user".id
                           ORDER BY sshkeys.id"#;
 
+    let md5 = match fingerprint_format.map(str::to_lowercase).as_deref() {
+        None | Some("sha256") => false,
+        Some("md5") => true,
+        Some(_) => exit_with_message("Unknown fingerprint format (md5, sha256)."),
+    };
+
     let mut res = Vec::new();
 
     for row in pgclient.query(query_string, &[])? {
@@ -171,15 +900,44 @@ pub fn list(
             sshkey: row.get("sshkey"),
             fingerprint: row.get("fingerprint"),
             comment: row.get("comment"),
+            until: row.get("until"),
+            expires_in: row.get("expires_in"),
+            sshfrom: row.get("sshfrom"),
+            sshcommand: row.get("sshcommand"),
+            sshoption: row.get("sshoption"),
+            expiry: row.get("expiry"),
+            randomart: None,
+            metadata: Metadata::from_row(&row),
         });
     }
 
+    metadata::retain(&mut res, |x| &x.metadata, added_by, added_after);
+
+    if let Some(expiring_within) = expiring_within {
+        let Some(duration) = parse_duration(expiring_within) else {
+            exit_with_message("Wrong duration format.")
+        };
+        let seconds = duration.whole_seconds();
+        res.retain(|x| x.expires_in.is_some_and(|e| e > 0 && e <= seconds));
+    }
+
     if let Some(email) = email {
         res.retain(|x| x.email.to_lowercase().contains(&email.to_lowercase()));
     }
 
-    if let Some(fingerprint) = fingerprint {
-        res.retain(|x| x.fingerprint.contains(fingerprint));
+    // computed from the stored key, the stored fingerprint is the fallback
+    let fingerprints = |x: &SshKeysQuery| match key_blob(&x.sshkey) {
+        Some(blob) => (self::fingerprint(&blob), Some(fingerprint_md5(&blob))),
+        None => (x.fingerprint.clone(), None),
+    };
+
+    if let Some(search) = fingerprint {
+        res.retain(|x| {
+            let (sha256, md5) = fingerprints(x);
+            x.fingerprint.contains(search)
+                || sha256.contains(search)
+                || md5.is_some_and(|m| m.contains(&search.to_lowercase().replace("md5:", "MD5:")))
+        });
     }
 
     if let Some(id) = id {
@@ -193,31 +951,100 @@ pub fn list(
         return Ok(());
     }
 
-    #[allow(clippy::uninlined_format_args)]
-    if json {
-        println!("{}", serde_json::to_string(&res).unwrap_or_default());
-    } else if with_key {
-        for r in res {
-            let comment = r.comment.map_or_else(|| "-".to_string(), |comment| comment);
-            println!("id:          {}", r.id);
+    for x in &mut res {
+        let (sha256, md5sum) = fingerprints(x);
+
+        x.fingerprint = if md5 {
+            md5sum.unwrap_or_else(|| "-".to_string())
+        } else {
+            sha256
+        };
+
+        if visual {
+            x.randomart = key_blob(&x.sshkey).map(|blob| {
+                let keytype = x.sshkey.split_whitespace().next().unwrap_or_default();
+                let title = key_title(keytype, &blob);
+                if md5 {
+                    randomart(&title, "MD5", &Md5::digest(&blob))
+                } else {
+                    randomart(&title, "SHA256", &Sha256::digest(&blob))
+                }
+            });
+        }
+    }
+
+    // like in authorized_keys (before merging w/ the serveraccess options)
+    let restrictions = |x: &SshKeysQuery| {
+        let options = options(
+            x.sshfrom.as_deref(),
+            x.sshcommand.as_deref(),
+            x.sshoption.as_deref(),
+            x.expiry.as_deref(),
+        );
+
+        if options.is_empty() {
+            "-".to_string()
+        } else {
+            options.join(",")
+        }
+    };
+
+    #[allow(clippy::uninlined_format_args)]
+    if json {
+        println!("{}", serde_json::to_string(&res).unwrap_or_default());
+    } else if with_key {
+        for r in res {
+            let options = restrictions(&r);
+            let comment = r.comment.unwrap_or_else(|| "-".to_string());
+            let until = r.until.unwrap_or_else(|| "-".to_string());
+            println!("id:          {}", r.id);
             println!("email:       {}", r.email);
             println!("ssh key:     {}", r.sshkey);
             println!("fingerprint: {}", r.fingerprint);
             println!("comment:     {}", comment);
+            println!("options:     {}", options);
+            println!("until:       {}", until);
+            println!("added:       {}", r.metadata.added());
+            println!("modified:    {}", r.metadata.modified());
+            if let Some(randomart) = r.randomart {
+                println!("{randomart}");
+            }
             println!("---");
         }
+    } else if visual {
+        // like ssh-keygen -lv
+        for r in res {
+            let comment = r.comment.unwrap_or_else(|| "-".to_string());
+            println!("{} {} {} ({})", r.id, r.fingerprint, r.email, comment);
+            println!("{}", r.randomart.unwrap_or_default());
+            println!();
+        }
     } else {
         let mut table = Table::new();
         table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
 
-        table.set_titles(row!["id", "email", "fingerprint", "comment"]);
+        table.set_titles(row![
+            "id",
+            "email",
+            "fingerprint",
+            "comment",
+            "options",
+            "until",
+            "added",
+            "modified"
+        ]);
 
         for r in res {
+            let options = restrictions(&r);
             table.add_row(row![
                 r.id,
                 r.email,
                 r.fingerprint,
-                r.comment.unwrap_or_else(|| "-".to_string())
+                r.comment.unwrap_or_else(|| "-".to_string()),
+                options,
+                r.until.unwrap_or_else(|| "-".to_string()),
+                r.metadata.added(),
+                r.metadata.modified()
             ]);
         }
 
@@ -227,39 +1054,570 @@ pub fn list(
     Ok(())
 }
 
-fn generate_fingerprint(key: &str) -> String {
+// [options] keytype base64 [comment]
+#[derive(Debug)]
+pub struct KeyLine {
+    pub options: Vec<String>,
+    pub keytype: String,
+    pub data: String,
+    pub comment: Option<String>,
+}
+
+impl KeyLine {
+    pub fn key(&self) -> String {
+        format!("{} {}", self.keytype, self.data)
+    }
+}
+
+fn is_keytype(field: &str) -> bool {
+    field.starts_with("ssh-") || field.starts_with("ecdsa-") || field.starts_with("sk-")
+}
+
+// options are separated by commas and end at the first unquoted whitespace,
+// e.g. from="10.0.0.0/8",command="echo a, b",no-pty
+fn split_options(line: &str) -> Result<(Vec<String>, &str), String> {
+    let mut options = Vec::new();
+    let mut option = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (i, c) in line.char_indices() {
+        if escaped {
+            option.push(c);
+            escaped = false;
+            continue;
+        }
+
+        match c {
+            '\\' if quoted => {
+                option.push(c);
+                escaped = true;
+            }
+            '"' => {
+                option.push(c);
+                quoted = !quoted;
+            }
+            ',' if !quoted => options.push(std::mem::take(&mut option)),
+            c if c.is_whitespace() && !quoted => {
+                options.push(option);
+                if options.iter().any(String::is_empty) {
+                    return Err("empty key option".to_string());
+                }
+                return Ok((options, &line[i..]));
+            }
+            _ => option.push(c),
+        }
+    }
+
+    if quoted {
+        Err("unterminated quote in key options".to_string())
+    } else {
+        Err("missing key after options".to_string())
+    }
+}
+
+pub fn parse_line(line: &str) -> Result<KeyLine, String> {
+    let line = line.trim();
+
+    let (options, rest) = match line.split_whitespace().next() {
+        Some(field) if is_keytype(field) => (Vec::new(), line),
+        Some(_) => split_options(line)?,
+        None => return Err("empty line".to_string()),
+    };
+
+    let Some((keytype, rest)) = rest.trim_start().split_once(char::is_whitespace) else {
+        return Err("missing key data".to_string());
+    };
+
+    let rest = rest.trim_start();
+    let (data, comment) = rest
+        .split_once(char::is_whitespace)
+        .map_or((rest, None), |(data, comment)| (data, Some(comment.trim())));
+
+    if data.is_empty() {
+        return Err("missing key data".to_string());
+    }
+
+    let comment = comment.filter(|c| !c.is_empty()).map(str::to_string);
+
+    Ok(KeyLine {
+        options,
+        keytype: keytype.to_string(),
+        data: data.to_string(),
+        comment,
+    })
+}
+
+pub fn key_line(line: &str) -> KeyLine {
+    match parse_line(line) {
+        Ok(keyline) => keyline,
+        Err(e) => exit_with_message(&format!("Invalid key format: {e}.")),
+    }
+}
+
+// per-key restrictions, merged w/ the serveraccess options on generation
+#[derive(Debug, Default, PartialEq)]
+pub struct Restrictions {
+    pub sshfrom: Option<String>,
+    pub sshcommand: Option<String>,
+    pub sshoption: Option<String>,
+    // expiry-time= of a key line, stored as best_before
+    pub expiry_time: Option<PrimitiveDateTime>,
+}
+
+impl Restrictions {
+    fn from_row(row: &postgres::Row) -> Self {
+        Restrictions {
+            sshfrom: row.get("sshfrom"),
+            sshcommand: row.get("sshcommand"),
+            sshoption: row.get("sshoption"),
+            expiry_time: None,
+        }
+    }
+
+    // --sshfrom, --sshcommand & --sshoption ('null': clear)
+    fn with(
+        mut self,
+        sshfrom: Option<&str>,
+        sshcommand: Option<&str>,
+        sshoption: Option<&str>,
+    ) -> Self {
+        let value = |v: &str| match &v.trim().to_lowercase()[..] {
+            "" | "null" => None,
+            _ => Some(v.to_string()),
+        };
+
+        if let Some(from) = sshfrom {
+            self.sshfrom = value(from);
+        }
+        if let Some(command) = sshcommand {
+            self.sshcommand = value(command);
+        }
+        if let Some(option) = sshoption {
+            self.sshoption = value(option);
+        }
+
+        self
+    }
+}
+
+// YYYYMMDD[HHMM[SS]] (local time), like sshd's expiry-time=
+fn parse_expiry_time(value: &str) -> Option<PrimitiveDateTime> {
+    if !value.bytes().all(|b| b.is_ascii_digit()) || ![8, 12, 14].contains(&value.len()) {
+        return None;
+    }
+
+    let field = |i: usize| value.get(i..i + 2).unwrap_or("00");
+
+    parse_datetime(&format!(
+        "{}-{}-{} {}:{}:{}",
+        &value[..4],
+        &value[4..6],
+        &value[6..8],
+        field(8),
+        field(10),
+        field(12)
+    ))
+}
+
+// from=, command= & expiry-time= (best_before) have their own columns, other options are kept as is
+pub fn parse_restrictions(options: &[String]) -> Result<Restrictions, String> {
+    let mut restrictions = Restrictions::default();
+    let mut other = Vec::new();
+
+    for option in options {
+        let (name, value) = option.split_once('=').unwrap_or((option, ""));
+        let unquoted = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value)
+            .to_string();
+
+        let field = match &name.to_lowercase()[..] {
+            "from" => &mut restrictions.sshfrom,
+            "command" => &mut restrictions.sshcommand,
+            "expiry-time" => {
+                if restrictions.expiry_time.is_some() {
+                    return Err("duplicate key option 'expiry-time'".to_string());
+                }
+                restrictions.expiry_time = Some(
+                    parse_expiry_time(&unquoted)
+                        .ok_or_else(|| format!("invalid expiry-time '{unquoted}'"))?,
+                );
+                continue;
+            }
+            _ => {
+                other.push(option.clone());
+                continue;
+            }
+        };
+
+        if field.is_some() {
+            return Err(format!("duplicate key option '{}'", name.to_lowercase()));
+        }
+        *field = Some(unquoted);
+    }
+
+    if !other.is_empty() {
+        restrictions.sshoption = Some(other.join(","));
+    }
+
+    Ok(restrictions)
+}
+
+fn key_restrictions(keyline: &KeyLine) -> Restrictions {
+    match parse_restrictions(&keyline.options) {
+        Ok(restrictions) => restrictions,
+        Err(e) => exit_with_message(&format!("Invalid key options: {e}.")),
+    }
+}
+
+const DEFAULT_MIN_RSA_BITS: usize = 2048;
+
+// supported by `parse_key`
+pub const KEY_TYPES: [&str; 7] = [
+    "ssh-ed25519",
+    "sk-ssh-ed25519@openssh.com",
+    "ssh-rsa",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+];
+
+#[derive(Debug)]
+pub struct PublicKey {
+    pub keytype: String,
+    pub bits: usize,
+    pub blob: Vec<u8>,
+}
+
+// SSH wire format (RFC 4251): uint32 length + data
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn string(&mut self) -> Result<&'a [u8], String> {
+        if self.data.len() < 4 {
+            return Err("truncated key blob".to_string());
+        }
+
+        let (len, rest) = self.data.split_at(4);
+        let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+
+        if rest.len() < len {
+            return Err("truncated key blob".to_string());
+        }
+
+        let (value, rest) = rest.split_at(len);
+        self.data = rest;
+        Ok(value)
+    }
+
+    // positive mpint, returns the number of significant bits
+    fn mpint(&mut self) -> Result<usize, String> {
+        let value = self.string()?;
+
+        if value.first().is_some_and(|b| b & 0x80 != 0) {
+            return Err("negative integer in key blob".to_string());
+        }
+
+        let value = match value.iter().position(|b| *b != 0) {
+            Some(start) => &value[start..],
+            None => return Ok(0),
+        };
+
+        Ok(value.len() * 8 - value[0].leading_zeros() as usize)
+    }
+}
+
+fn ed25519(reader: &mut Reader) -> Result<usize, String> {
+    if reader.string()?.len() != 32 {
+        return Err("invalid ed25519 key length".to_string());
+    }
+
+    Ok(256)
+}
+
+fn ecdsa(reader: &mut Reader, curve: &str) -> Result<usize, String> {
+    let (bits, coord) = match curve {
+        "nistp256" => (256, 32),
+        "nistp384" => (384, 48),
+        "nistp521" => (521, 66),
+        _ => return Err(format!("unsupported curve '{curve}'")),
+    };
+
+    let identifier = reader.string()?;
+    if identifier != curve.as_bytes() {
+        return Err(format!(
+            "curve mismatch ('{}' vs. '{curve}')",
+            String::from_utf8_lossy(identifier)
+        ));
+    }
+
+    // uncompressed point: 0x04 || x || y
+    let point = reader.string()?;
+    if point.len() != 1 + 2 * coord || point[0] != 0x04 {
+        return Err(format!("invalid {curve} point"));
+    }
+
+    Ok(bits)
+}
+
+fn application(reader: &mut Reader) -> Result<(), String> {
+    if reader.string()?.is_empty() {
+        return Err("missing security key application".to_string());
+    }
+
+    Ok(())
+}
+
+pub fn parse_key(keytype: &str, data: &str) -> Result<PublicKey, String> {
+    let Ok(blob) = engine::general_purpose::STANDARD.decode(data) else {
+        return Err("invalid base64".to_string());
+    };
+
+    let mut reader = Reader { data: &blob };
+
+    let inner = reader.string()?;
+    if inner != keytype.as_bytes() {
+        return Err(format!(
+            "key type mismatch ('{keytype}' vs. '{}')",
+            String::from_utf8_lossy(inner)
+        ));
+    }
+
+    let bits = match keytype {
+        "ssh-ed25519" => ed25519(&mut reader)?,
+        "sk-ssh-ed25519@openssh.com" => {
+            let bits = ed25519(&mut reader)?;
+            application(&mut reader)?;
+            bits
+        }
+        "ssh-rsa" => {
+            let e = reader.mpint()?;
+            let n = reader.mpint()?;
+            if e < 2 || n == 0 {
+                return Err("invalid RSA key".to_string());
+            }
+            n
+        }
+        "ecdsa-sha2-nistp256" | "ecdsa-sha2-nistp384" | "ecdsa-sha2-nistp521" => {
+            ecdsa(&mut reader, &keytype["ecdsa-sha2-".len()..])?
+        }
+        "sk-ecdsa-sha2-nistp256@openssh.com" => {
+            let bits = ecdsa(&mut reader, "nistp256")?;
+            application(&mut reader)?;
+            bits
+        }
+        _ => return Err(format!("unsupported key type '{keytype}'")),
+    };
+
+    if !reader.data.is_empty() {
+        return Err("trailing data in key blob".to_string());
+    }
+
+    Ok(PublicKey {
+        keytype: keytype.to_string(),
+        bits,
+        blob,
+    })
+}
+
+fn min_rsa_bits() -> usize {
+    crate::KEY_CONFIG
+        .get()
+        .and_then(|c| c.min_rsa_bits)
+        .unwrap_or(DEFAULT_MIN_RSA_BITS)
+}
+
+fn fingerprint(blob: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(blob);
+    let result = hasher.finalize();
+    let mut fingerprint = String::from("SHA256:");
+    fingerprint.push_str(&engine::general_purpose::STANDARD_NO_PAD.encode(result));
+    fingerprint
+}
+
+// MD5:xx:xx:.. (legacy format, e.g. in old sshd logs)
+fn fingerprint_md5(blob: &[u8]) -> String {
+    let digest = Md5::digest(blob);
+    let hex = digest
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<String>>();
+    format!("MD5:{}", hex.join(":"))
+}
+
+// key blob of a stored key (w/o parsing it, so deprecated key types work, too)
+fn key_blob(sshkey: &str) -> Option<Vec<u8>> {
+    let data = sshkey.split_whitespace().nth(1)?;
+    engine::general_purpose::STANDARD.decode(data).ok()
+}
+
+// e.g. [ED25519 256], like ssh-keygen -lv
+fn key_title(keytype: &str, blob: &[u8]) -> String {
+    let name = match keytype {
+        "ssh-rsa" => "RSA",
+        "ssh-dss" => "DSA",
+        "ssh-ed25519" => "ED25519",
+        "sk-ssh-ed25519@openssh.com" => "ED25519-SK",
+        "sk-ecdsa-sha2-nistp256@openssh.com" => "ECDSA-SK",
+        t if t.starts_with("ecdsa-") => "ECDSA",
+        _ => return "[UNKNOWN]".to_string(),
+    };
+
+    let bits = if keytype.eq("ssh-dss") {
+        let mut reader = Reader { data: blob };
+        reader.string().and_then(|_| reader.mpint()).ok()
+    } else {
+        parse_key(keytype, &engine::general_purpose::STANDARD.encode(blob))
+            .ok()
+            .map(|k| k.bits)
+    };
+
+    match bits {
+        Some(bits) => format!("[{name} {bits}]"),
+        None => format!("[{name}]"),
+    }
+}
+
+// OpenSSH's "drunken bishop" visual host key (see sshkey.c)
+fn randomart(title: &str, algorithm: &str, digest: &[u8]) -> String {
+    const WIDTH: usize = 17;
+    const HEIGHT: usize = 9;
+    const SYMBOLS: &[u8] = b" .o+=*BOX@%&#/^SE";
+
+    let end = SYMBOLS.len() - 1;
+    let mut field = [[0usize; HEIGHT]; WIDTH];
+    let (mut x, mut y) = (WIDTH / 2, HEIGHT / 2);
+
+    for byte in digest {
+        let mut input = *byte;
+        for _ in 0..4 {
+            x = if input & 0x1 == 0 {
+                x.saturating_sub(1)
+            } else {
+                (x + 1).min(WIDTH - 1)
+            };
+            y = if input & 0x2 == 0 {
+                y.saturating_sub(1)
+            } else {
+                (y + 1).min(HEIGHT - 1)
+            };
+            if field[x][y] < end - 2 {
+                field[x][y] += 1;
+            }
+            input >>= 2;
+        }
+    }
+
+    field[WIDTH / 2][HEIGHT / 2] = end - 1;
+    field[x][y] = end;
+
+    let border = |label: &str| {
+        let pad = WIDTH.saturating_sub(label.len()) / 2;
+        format!(
+            "+{}{}{}+",
+            "-".repeat(pad),
+            label,
+            "-".repeat(WIDTH.saturating_sub(pad + label.len()))
+        )
+    };
+
+    let mut art = vec![border(title)];
+
+    for y in 0..HEIGHT {
+        let row = (0..WIDTH)
+            .map(|x| SYMBOLS[field[x][y].min(end)] as char)
+            .collect::<String>();
+        art.push(format!("|{row}|"));
+    }
+
+    art.push(border(&format!("[{algorithm}]")));
+
+    art.join("\n")
+}
+
+// parses the key and applies the key policy, returns the fingerprint
+fn check_key(keytype: &str, data: &str) -> Result<String, String> {
+    let publickey = parse_key(keytype, data).map_err(|e| format!("Wrong SSH key format: {e}"))?;
+
+    if publickey.keytype.eq("ssh-rsa") && publickey.bits < min_rsa_bits() {
+        return Err(format!(
+            "RSA key too small ({} bits, minimum: {})",
+            publickey.bits,
+            min_rsa_bits()
+        ));
+    }
+
+    Ok(fingerprint(&publickey.blob))
+}
+
+// (canonical key, fingerprint) of a stored key, w/ the parsing of `generate_fingerprint`
+// (w/o the key policy), tolerates missing padding & trailing bits in the base64 data
+fn canonical_key(sshkey: &str) -> Result<(String, String), String> {
+    let lenient = engine::GeneralPurpose::new(
+        &alphabet::STANDARD,
+        engine::GeneralPurposeConfig::new()
+            .with_decode_allow_trailing_bits(true)
+            .with_decode_padding_mode(engine::DecodePaddingMode::Indifferent),
+    );
+
+    let mut fields = sshkey.split_whitespace();
+
+    let (Some(keytype), Some(data)) = (fields.next(), fields.next()) else {
+        return Err("wrong SSH key format".to_string());
+    };
+
+    let Ok(blob) = lenient.decode(data) else {
+        return Err("invalid base64".to_string());
+    };
+
+    let data = engine::general_purpose::STANDARD.encode(&blob);
+    let publickey = parse_key(keytype, &data)?;
+
+    Ok((format!("{keytype} {data}"), fingerprint(&publickey.blob)))
+}
+
+pub fn generate_fingerprint(key: &str) -> String {
     let sshkey = key.split(' ').collect::<Vec<&str>>();
 
     if sshkey.len() < 2 {
         exit_with_message("Wrong SSH key format.");
     }
 
-    if sshkey[0].eq("ssh-ed25519") || sshkey[0].eq("ssh-rsa") || sshkey[0].eq("ecdsa-sha2-nistp256")
-    {
-        if let Ok(binkey) = engine::general_purpose::STANDARD.decode(sshkey[1]) {
-            let mut hasher = Sha256::new();
-            hasher.update(binkey);
-            let result = hasher.finalize();
-            let mut fingerprint = String::from("SHA256:");
-            fingerprint.push_str(&engine::general_purpose::STANDARD_NO_PAD.encode(result));
-            return fingerprint;
-        }
+    match check_key(sshkey[0], sshkey[1]) {
+        Ok(fingerprint) => fingerprint,
+        Err(e) => exit_with_message(&format!("{e}.")),
     }
-
-    exit_with_message("Wrong SSH key format.");
 }
 
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::too_many_lines)]
 pub fn update(
     pgclient: &mut Client,
     keyid: Option<&str>,
     publickey: Option<&str>,
     comment: Option<&str>,
+    until: Option<&str>,
+    sshfrom: Option<&str>,
+    sshcommand: Option<&str>,
+    sshoption: Option<&str>,
 ) -> Result<(), Error> {
     println!("Update SSH key");
     let query_string = r"UPDATE sshkeys
                           SET sshkey = $1,
                               fingerprint = $2,
-                              comment = $3
+                              comment = $3,
+                              best_before = $5,
+                              sshfrom = $7,
+                              sshcommand = $8,
+                              sshoption = $9,
+                              modified_at = NOW(),
+                              modified_by = $6
                           WHERE id = $4";
 
     let newkeyid = ask_for(
@@ -269,7 +1627,7 @@ pub fn update(
         pgclient,
     );
 
-    if newkeyid.eq("") {
+    if newkeyid.is_empty() {
         exit_with_message("Key ID cannot be empty.");
     }
 
@@ -278,7 +1636,10 @@ pub fn update(
     };
 
     let res = pgclient.query(
-        r"SELECT sshkey, comment FROM sshkeys WHERE id = $1 LIMIT 1",
+        r"SELECT sshkey, fingerprint, comment, best_before, sshfrom, sshcommand, sshoption
+          FROM sshkeys
+          WHERE id = $1
+          LIMIT 1",
         &[&newkeyidint],
     )?;
 
@@ -287,20 +1648,45 @@ pub fn update(
     }
 
     let oldkey: String = res[0].get("sshkey");
+    let oldfingerprint: String = res[0].get("fingerprint");
     let oldkeycomment: Option<String> = res[0].get("comment");
+    let olduntil: Option<PrimitiveDateTime> = res[0].get("best_before");
+    let oldrestrictions = Restrictions::from_row(&res[0]);
 
     let mut newkey = set_or_ask_for(publickey, "New public SSH key: [<Enter>: no change]");
 
-    if newkey.eq("") {
+    if newkey.is_empty() {
         newkey.clone_from(&oldkey);
     }
 
-    if newkey.split(' ').count() < 2 {
-        exit_with_message("Invalid key format.")
+    let keyline = key_line(&newkey);
+    let newkey = keyline.key();
+
+    // only a changed key is validated, legacy keys (DSA, short RSA) stay editable
+    let unchanged = parse_line(&oldkey).is_ok_and(|old| old.key().eq(&newkey));
+    let fingerprint = &if unchanged {
+        oldfingerprint.clone()
+    } else {
+        generate_fingerprint(&newkey)
+    };
+
+    // the options of a new key line replace the stored ones
+    let restrictions = if keyline.options.is_empty() {
+        oldrestrictions
+    } else {
+        key_restrictions(&keyline)
+    }
+    .with(sshfrom, sshcommand, sshoption);
+
+    if fingerprint.ne(&oldfingerprint) && is_blocked(pgclient, fingerprint)? {
+        exit_with_message("Key is blocked (known-compromised).");
     }
 
-    newkey = newkey.split(' ').collect::<Vec<&str>>()[..2].join(" ");
-    let fingerprint = &generate_fingerprint(&newkey);
+    if let Some((id, owner)) = key_owner(pgclient, fingerprint)? {
+        if id != newkeyidint {
+            exit_with_message(&format!("Key already exists for user '{owner}' (ID {id})."));
+        }
+    }
 
     let newcomment = set_or_ask_for(
         comment,
@@ -313,7 +1699,7 @@ pub fn update(
         ),
     );
 
-    let newcommentopt = if newcomment.to_lowercase().eq("") {
+    let newcommentopt = if newcomment.to_lowercase().is_empty() {
         oldkeycomment
     } else if newcomment.trim().to_lowercase().eq("null") {
         None
@@ -321,18 +1707,33 @@ pub fn update(
         Some(newcomment)
     };
 
+    // an expiry-time= of the new key line replaces the stored expiry
+    let newuntil = until.map_or(restrictions.expiry_time.or(olduntil), parse_until);
+
     pgclient.query(
         query_string,
-        &[&newkey, &fingerprint, &newcommentopt, &newkeyidint],
+        &[
+            &newkey,
+            &fingerprint,
+            &newcommentopt,
+            &newkeyidint,
+            &newuntil,
+            &get_ssh_client(),
+            &restrictions.sshfrom,
+            &restrictions.sshcommand,
+            &restrictions.sshoption,
+        ],
     )?;
 
     info!(
         "({}) Updated SSH key '{}' -> '{}'",
         &get_ssh_client(),
-        &generate_fingerprint(&oldkey),
+        &oldfingerprint,
         &fingerprint
     );
 
+    warn_filtered(pgclient, newkeyidint)?;
+
     println!(
         "{}",
         "Done.".if_supports_color(Stdout, |t| t
@@ -341,3 +1742,328 @@ pub fn update(
 
     Ok(())
 }
+
+// replaces a key w/ a new one for the same user, w/ an optional overlap
+// window during which both keys are valid
+pub fn rotate(
+    pgclient: &mut Client,
+    keyid: Option<&str>,
+    publickey: Option<&str>,
+    overlap: Option<&str>,
+) -> Result<(), Error> {
+    println!("Rotate SSH key");
+
+    let oldkeyid = ask_for(
+        &ListObject::KeyID,
+        keyid,
+        Some("Key ID ['?' list by email"),
+        pgclient,
+    );
+
+    if oldkeyid.is_empty() {
+        exit_with_message("Key ID cannot be empty.");
+    }
+
+    let Ok(oldkeyidint) = oldkeyid.parse::<i64>() else {
+        exit_with_message("Wrong key ID format.")
+    };
+
+    let overlap = match overlap {
+        Some(overlap) => match parse_duration(overlap) {
+            Some(duration) => duration.whole_seconds(),
+            None => exit_with_message("Wrong duration format."),
+        },
+        None => 0,
+    };
+
+    let res = pgclient.query(
+        r#"SELECT sshkeys.user_id, sshkeys.fingerprint, sshkeys.comment, "user".email,
+                  sshkeys.sshfrom, sshkeys.sshcommand, sshkeys.sshoption
+           FROM sshkeys, "user"
+           WHERE sshkeys.user_id = "user".id
+             AND sshkeys.id = $1
+           LIMIT 1"#,
+        &[&oldkeyidint],
+    )?;
+
+    if res.is_empty() {
+        exit_with_message("Key not found.");
+    }
+
+    let userid: i64 = res[0].get("user_id");
+    let email: String = res[0].get("email");
+    let oldfingerprint: String = res[0].get("fingerprint");
+    let oldkeycomment: Option<String> = res[0].get("comment");
+    let oldrestrictions = Restrictions::from_row(&res[0]);
+
+    let newkey = set_or_ask_for(publickey, "New public SSH key");
+
+    if newkey.is_empty() {
+        exit_with_message("Key cannot be empty.");
+    }
+
+    let keyline = key_line(&newkey);
+    let newkey = keyline.key();
+    let fingerprint = &generate_fingerprint(&newkey);
+
+    if is_blocked(pgclient, fingerprint)? {
+        exit_with_message("Key is blocked (known-compromised).");
+    }
+
+    if let Some((id, owner)) = key_owner(pgclient, fingerprint)? {
+        exit_with_message(&format!("Key already exists for user '{owner}' (ID {id})."));
+    }
+
+    // the new key keeps the restrictions of the old one (w/o options of its own)
+    let restrictions = if keyline.options.is_empty() {
+        oldrestrictions
+    } else {
+        key_restrictions(&keyline)
+    };
+
+    let newcomment = keyline.comment.or(oldkeycomment);
+
+    let mut transaction = pgclient.transaction()?;
+
+    let res = transaction.query(
+        r"INSERT INTO sshkeys (user_id, sshkey, fingerprint, comment, best_before, created_by,
+                               sshfrom, sshcommand, sshoption)
+          VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+          RETURNING id",
+        &[
+            &userid,
+            &newkey,
+            &fingerprint,
+            &newcomment,
+            &restrictions.expiry_time,
+            &get_ssh_client(),
+            &restrictions.sshfrom,
+            &restrictions.sshcommand,
+            &restrictions.sshoption,
+        ],
+    )?;
+    let newkeyid: i64 = res[0].get("id");
+
+    if overlap > 0 {
+        // never extend an earlier expiry
+        transaction.query(
+            r"UPDATE sshkeys
+              SET best_before = LEAST(COALESCE(best_before, 'infinity'), DATE_TRUNC('second', NOW() + $2::BIGINT * INTERVAL '1 second')),
+                  modified_at = NOW(),
+                  modified_by = $3
+              WHERE id = $1",
+            &[&oldkeyidint, &overlap, &get_ssh_client()],
+        )?;
+    } else {
+        transaction.query(r"DELETE FROM sshkeys WHERE id = $1", &[&oldkeyidint])?;
+    }
+
+    transaction.commit()?;
+
+    info!(
+        "({}) Rotated SSH key ID {} ({}) -> ID {} ({}) for user '{}', overlap: {}s",
+        &get_ssh_client(),
+        &oldkeyidint,
+        &oldfingerprint,
+        &newkeyid,
+        &fingerprint,
+        &email,
+        &overlap
+    );
+
+    println!(
+        "{}",
+        "Done.".if_supports_color(Stdout, owo_colors::OwoColorize::green)
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED25519: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIIvD0OM3RzHyme38enRAQsBGWxmKnITj2vC6p3dVBCru";
+    const ECDSA384: &str = "AAAAE2VjZHNhLXNoYTItbmlzdHAzODQAAAAIbmlzdHAzODQAAABhBAbg9CNXMnyya0uZYOLxxcfAAg0wTIRdkEtGC1lCjJnYGRWfuWJ82sOXm4Jbs5Ly8FzcqiEgyyTe4cS0TDDtx9GSY0kTc6CPpEnOsMUmXBRdm7pDVsuCaO7zs3vQeXtwng==";
+    const ECDSA521: &str = "AAAAE2VjZHNhLXNoYTItbmlzdHA1MjEAAAAIbmlzdHA1MjEAAACFBAA8hneIhUF6s8c6HPDkCw1oNsi973TlVCw7Hcpvs5rNFrmZKPdbiHphNO0BLGTnYX/uGN3QIzQ6RS9CcW+/y5Xh2QBcmlDbwl1ZQ1itjVcFbAb6XbWoiE8Em8T+qO9/NTJPSu1o8uNg0CH0XblK9AZQ/M3e3zFYPJvnBT2al9QEsNQyeA==";
+    const RSA1024: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAgQCcxvofdA+Pl/vXerevUK5SpjOgySf5JtYtsfXCpCpu63IwuvnwGrt46NwuKKo3JbJM69UbuC6fAgny2NwyQBkCm9ZPb4/Dblhm74kUfwTIjzzo8dFovVorF7PhslymB01/PCsTVAiLOjMPy79soCFXx05PO5BfZeWZSru/7qsFLw==";
+
+    fn encode(fields: &[&[u8]]) -> String {
+        let mut blob = Vec::new();
+        for field in fields {
+            blob.extend_from_slice(&u32::try_from(field.len()).unwrap().to_be_bytes());
+            blob.extend_from_slice(field);
+        }
+        engine::general_purpose::STANDARD.encode(blob)
+    }
+
+    #[test]
+    fn parse_key_accepts_valid_keys() {
+        let key = parse_key("ssh-ed25519", ED25519).unwrap();
+        assert_eq!(
+            fingerprint(&key.blob),
+            "SHA256:dS8pN5W+O+OGFdkCTH27BxkcwWeK5agmJ24oeh0ktZ0"
+        );
+        assert_eq!(
+            parse_key("ecdsa-sha2-nistp384", ECDSA384).unwrap().bits,
+            384
+        );
+
+        let key = parse_key("ecdsa-sha2-nistp521", ECDSA521).unwrap();
+        assert_eq!(key.bits, 521);
+        assert_eq!(
+            fingerprint(&key.blob),
+            "SHA256:7NUL/WwiWfP3qSYAhi3+pAsXtiTkpOfLoP9XaPOZBII"
+        );
+
+        assert_eq!(parse_key("ssh-rsa", RSA1024).unwrap().bits, 1024);
+    }
+
+    #[test]
+    fn parse_key_accepts_security_keys() {
+        let sk = encode(&[b"sk-ssh-ed25519@openssh.com", &[1; 32], b"ssh:"]);
+        assert!(parse_key("sk-ssh-ed25519@openssh.com", &sk).is_ok());
+
+        let mut point = vec![4];
+        point.extend_from_slice(&[1; 64]);
+        let sk = encode(&[
+            b"sk-ecdsa-sha2-nistp256@openssh.com",
+            b"nistp256",
+            &point,
+            b"ssh:",
+        ]);
+        assert!(parse_key("sk-ecdsa-sha2-nistp256@openssh.com", &sk).is_ok());
+
+        // application string is mandatory
+        let sk = encode(&[b"sk-ssh-ed25519@openssh.com", &[1; 32]]);
+        assert!(parse_key("sk-ssh-ed25519@openssh.com", &sk).is_err());
+    }
+
+    #[test]
+    fn parse_key_rejects_invalid_keys() {
+        // declared type does not match the blob
+        assert!(parse_key("ssh-rsa", ED25519).is_err());
+        assert!(parse_key("ecdsa-sha2-nistp256", ECDSA384).is_err());
+
+        assert!(parse_key("ssh-ed25519", "not base64!").is_err());
+        assert!(parse_key("ssh-ed25519", &ED25519[..40]).is_err());
+        assert!(parse_key("ssh-ed25519", &encode(&[b"ssh-ed25519", &[1; 31]])).is_err());
+        assert!(parse_key("ssh-ed25519", &encode(&[b"ssh-ed25519", &[1; 32], b"x"])).is_err());
+        assert!(parse_key("ssh-dss", &encode(&[b"ssh-dss"])).is_err());
+    }
+
+    #[test]
+    fn parse_line_splits_options_and_comment() {
+        let line = parse_line(&format!("ssh-ed25519 {ED25519}")).unwrap();
+        assert!(line.options.is_empty());
+        assert_eq!(line.comment, None);
+        assert_eq!(line.key(), format!("ssh-ed25519 {ED25519}"));
+
+        let line = parse_line(&format!("  ssh-ed25519  {ED25519}  alice @ laptop ")).unwrap();
+        assert_eq!(line.data, ED25519);
+        assert_eq!(line.comment.as_deref(), Some("alice @ laptop"));
+
+        let line = parse_line(&format!(
+            r#"from="10.0.0.0/8,::1",command="echo \"a, b\"",no-pty ssh-ed25519 {ED25519} x"#
+        ))
+        .unwrap();
+        assert_eq!(
+            line.options,
+            [
+                r#"from="10.0.0.0/8,::1""#,
+                r#"command="echo \"a, b\"""#,
+                "no-pty"
+            ]
+        );
+        assert_eq!(line.keytype, "ssh-ed25519");
+        assert_eq!(line.comment.as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn parse_line_rejects_broken_lines() {
+        assert!(parse_line("").is_err());
+        assert!(parse_line("ssh-ed25519").is_err());
+        assert!(parse_line("ssh-ed25519 ").is_err());
+        assert!(parse_line(r#"command="true ssh-ed25519 AAAA"#).is_err());
+        assert!(parse_line(&format!("no-pty,,x ssh-ed25519 {ED25519}")).is_err());
+        assert!(parse_line("no-pty").is_err());
+    }
+
+    #[test]
+    fn fingerprints_and_randomart_match_ssh_keygen() {
+        let blob = key_blob(&format!("ssh-ed25519 {ED25519} a@test")).unwrap();
+
+        assert_eq!(
+            fingerprint_md5(&blob),
+            "MD5:f4:04:bb:33:d3:2b:e1:9c:d7:f6:54:13:d7:96:63:bb"
+        );
+        assert_eq!(
+            randomart(
+                &key_title("ssh-ed25519", &blob),
+                "SHA256",
+                &Sha256::digest(&blob)
+            ),
+            [
+                "+--[ED25519 256]--+",
+                "|           o.+oo |",
+                "|      .     o *.+|",
+                "|     . o .. .*oX.|",
+                "|    . o E. .o=O..|",
+                "|     o  S ..= o+.|",
+                "|      . o +o o.o.|",
+                "|     . + =   o. .|",
+                "|    o o o   . +. |",
+                "|  .o . .     ooo |",
+                "+----[SHA256]-----+",
+            ]
+            .join("\n")
+        );
+
+        let blob = key_blob(&format!("ssh-rsa {RSA1024}")).unwrap();
+        assert_eq!(key_title("ssh-rsa", &blob), "[RSA 1024]");
+    }
+
+    #[test]
+    fn canonical_key_repairs_base64() {
+        let key = format!("ecdsa-sha2-nistp384 {ECDSA384}");
+        let (canonical, fingerprint) = canonical_key(&key).unwrap();
+        assert_eq!(canonical, key);
+
+        // w/o padding, w/ trailing bits set & extra whitespace
+        let sloppy = format!("ecdsa-sha2-nistp384  {}h", &ECDSA384[..ECDSA384.len() - 3]);
+        assert_eq!(canonical_key(&sloppy), Ok((key, fingerprint)));
+
+        assert!(canonical_key("ssh-ed25519").is_err());
+        assert!(canonical_key(&format!("ssh-rsa {ED25519}")).is_err());
+        assert!(canonical_key("ssh-dss AAAAB3NzaC1kc3M=").is_err());
+    }
+
+    #[test]
+    fn parse_restrictions_splits_key_options() {
+        let line = parse_line(&format!(
+            r#"from="10.1.0.0/16",command="echo a, b",no-pty,expiry-time="202701311230" ssh-ed25519 {ED25519}"#
+        ))
+        .unwrap();
+
+        assert_eq!(
+            parse_restrictions(&line.options),
+            Ok(Restrictions {
+                sshfrom: Some("10.1.0.0/16".to_string()),
+                sshcommand: Some("echo a, b".to_string()),
+                sshoption: Some("no-pty".to_string()),
+                expiry_time: parse_datetime("2027-01-31 12:30:00"),
+            })
+        );
+
+        assert_eq!(
+            parse_restrictions(&[r#"expiry-time="20270131""#.to_string()]).map(|r| r.expiry_time),
+            Ok(parse_datetime("2027-01-31"))
+        );
+        assert!(parse_restrictions(&[r#"expiry-time="2027-01-31""#.to_string()]).is_err());
+        assert!(parse_restrictions(&[
+            r#"from="10.0.0.0/8""#.to_string(),
+            r#"from="10.1.0.0/16""#.to_string()
+        ])
+        .is_err());
+    }
+}
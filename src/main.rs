@@ -6,15 +6,24 @@ use serde_derive::Deserialize;
 use std::fs;
 use std::io::{self, Write};
 use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+use time::{format_description, Date, PrimitiveDateTime, Time};
 
 const DEFAULT_LOGFILE: &str = "/var/log/oerec.log";
 
 #[macro_use]
 extern crate prettytable;
 
+mod access;
+mod blocklist;
+mod certauthority;
 mod commands;
+mod deploy;
 mod key;
 mod logging;
+mod metadata;
+mod serve;
 mod server;
 mod serveraccess;
 mod serverauth;
@@ -28,6 +37,7 @@ struct Config {
     client: ClientConfig,
     db: DBConfig,
     logging: Option<LogConfig>,
+    keys: Option<KeyConfig>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -41,6 +51,7 @@ struct DBConfig {
     path: Option<String>,
     host: Option<String>,
     dbname: Option<String>,
+    connect_timeout: Option<u64>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -48,6 +59,17 @@ struct LogConfig {
     file: Option<PathBuf>,
 }
 
+#[derive(Deserialize, Debug, Default)]
+struct KeyConfig {
+    min_rsa_bits: Option<usize>,
+    audit_threshold: Option<usize>,
+}
+
+static KEY_CONFIG: OnceLock<KeyConfig> = OnceLock::new();
+
+// for reconnects of long running commands (serve)
+static DB_CONFIG: OnceLock<postgres::config::Config> = OnceLock::new();
+
 #[derive(Copy, Clone, Debug)]
 pub enum ListObject {
     UserEmail,
@@ -57,6 +79,7 @@ pub enum ListObject {
     ServerGroup,
     ServerAccess,
     KeyID,
+    CertAuthority,
 }
 
 #[cfg(unix)]
@@ -97,19 +120,46 @@ pub fn ask_for(
         ListObject::UserEmail => (
             message.unwrap_or("User email ['?' for list]"),
             Box::new(|o: String| {
-                _ = &user::list(pgclient, o.strip_suffix('?'), None, None, false, false);
+                _ = &user::list(
+                    pgclient,
+                    o.strip_suffix('?'),
+                    None,
+                    None,
+                    None,
+                    None,
+                    false,
+                    false,
+                );
             }) as Box<dyn FnMut(_)>,
         ),
         ListObject::UserName => (
             message.unwrap_or("User name ['?' for list]"),
             Box::new(|o: String| {
-                _ = &user::list(pgclient, None, o.strip_suffix('?'), None, false, false);
+                _ = &user::list(
+                    pgclient,
+                    None,
+                    o.strip_suffix('?'),
+                    None,
+                    None,
+                    None,
+                    false,
+                    false,
+                );
             }) as Box<dyn FnMut(_)>,
         ),
         ListObject::ServerName => (
             message.unwrap_or("Server name ['?' for list]"),
             Box::new(|o: String| {
-                _ = &server::list(pgclient, o.strip_suffix('?'), None, None, false, false);
+                _ = &server::list(
+                    pgclient,
+                    o.strip_suffix('?'),
+                    None,
+                    None,
+                    None,
+                    None,
+                    false,
+                    false,
+                );
             }) as Box<dyn FnMut(_)>,
         ),
         ListObject::UserGroup => (
@@ -121,7 +171,19 @@ pub fn ask_for(
         ListObject::KeyID => (
             message.unwrap_or("Key ID ['?' for list]"),
             Box::new(|o: String| {
-                _ = &key::list(pgclient, o.strip_suffix('?'), None, false, None, false);
+                _ = &key::list(
+                    pgclient,
+                    o.strip_suffix('?'),
+                    None,
+                    false,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    false,
+                    false,
+                );
             }) as Box<dyn FnMut(_)>,
         ),
         ListObject::ServerGroup => (
@@ -149,11 +211,19 @@ pub fn ask_for(
                     None,
                     None,
                     None,
+                    None,
+                    None,
                     false,
                     false,
                 );
             }) as Box<dyn FnMut(_)>,
         ),
+        ListObject::CertAuthority => (
+            message.unwrap_or("Certificate authority name ['?' for list]"),
+            Box::new(|o: String| {
+                _ = &certauthority::list(pgclient, o.strip_suffix('?'), None, false, false);
+            }) as Box<dyn FnMut(_)>,
+        ),
     };
 
     #[allow(clippy::ignored_unit_patterns)]
@@ -167,6 +237,54 @@ pub fn ask_for(
     }
 }
 
+// e.g. 90s, 30m, 8h, 7d, 2w
+#[must_use]
+pub fn parse_duration(duration: &str) -> Option<time::Duration> {
+    let duration = duration.trim();
+    let unit = duration.chars().last()?;
+    let value = duration[..duration.len() - unit.len_utf8()]
+        .parse::<i64>()
+        .ok()
+        .filter(|v| *v >= 0)?;
+
+    match unit {
+        's' => Some(time::Duration::seconds(value)),
+        'm' => Some(time::Duration::minutes(value)),
+        'h' => Some(time::Duration::hours(value)),
+        'd' => Some(time::Duration::days(value)),
+        'w' => Some(time::Duration::weeks(value)),
+        _ => None,
+    }
+}
+
+// YYYY-MM-DD, optional w/ HH:MI:SS
+#[must_use]
+pub fn parse_datetime(datetime: &str) -> Option<PrimitiveDateTime> {
+    let mut fields = datetime.split_whitespace();
+
+    let date = Date::parse(
+        fields.next()?,
+        &format_description::parse("[year]-[month]-[day]").expect("BUG: DateTimeFormatDesc"),
+    )
+    .ok()?;
+
+    let time = match fields.next() {
+        Some(time) => Time::parse(
+            time,
+            &format_description::parse("[hour]:[minute]:[second]")
+                .expect("BUG: DateTimeFormatDesc"),
+        )
+        .ok()?,
+        None => Time::MIDNIGHT,
+    };
+
+    if fields.next().is_some() {
+        return None;
+    }
+
+    Some(PrimitiveDateTime::new(date, time))
+}
+
 fn exit_with_message(message: &str) -> ! {
     println!();
     eprintln!(
@@ -179,6 +297,12 @@ fn exit_with_message(message: &str) -> ! {
 
 fn main() {
     pipe_reset();
+
+    // sshd waits for AuthorizedKeysCommand, which usually runs as an
+    // unprivileged user (w/o access to the log file)
+    let subcommand = std::env::args().nth(1);
+    let keys_command = subcommand.as_deref() == Some("authorized-keys-command");
+
     let homedir = match home_dir() {
         Some(path) => path,
         _ => PathBuf::from("/root"),
@@ -208,6 +332,33 @@ fn main() {
         exit_with_message("Could not parse config file.");
     };
 
+    _ = KEY_CONFIG.set(config.keys.unwrap_or_default());
+
+    // logging
+    let logfile = if let Some(logconfig) = config.logging {
+        logconfig
+            .file
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_LOGFILE))
+    } else {
+        PathBuf::from(DEFAULT_LOGFILE)
+    };
+
+    if let Err(e) = logging::create_logger(&logfile) {
+        if !keys_command {
+            error!("Could create logger: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // deploy only reads the workdir, no database connection needed
+    if subcommand.as_deref() == Some("deploy") {
+        if let Err(e) = commands::parse_deploy() {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let mut pgconfig = postgres::config::Config::new();
     pgconfig
         .user(&config.client.user.unwrap_or_else(|| "oerec".to_string()))
@@ -224,24 +375,22 @@ fn main() {
         pgconfig.password(&password);
     }
 
+    match (config.db.connect_timeout, keys_command) {
+        (Some(timeout), _) => {
+            pgconfig.connect_timeout(Duration::from_secs(timeout));
+        }
+        (None, true) => {
+            pgconfig.connect_timeout(Duration::from_secs(3));
+        }
+        (None, false) => {}
+    }
+
+    _ = DB_CONFIG.set(pgconfig.clone());
+
     let Ok(mut con) = pgconfig.connect(NoTls) else {
         exit_with_message("Could not connect to database.");
     };
 
-    // logging
-    let logfile = if let Some(logconfig) = config.logging {
-        logconfig
-            .file
-            .unwrap_or_else(|| PathBuf::from(DEFAULT_LOGFILE))
-    } else {
-        PathBuf::from(DEFAULT_LOGFILE)
-    };
-
-    if let Err(e) = logging::create_logger(&logfile) {
-        error!("Could create logger: {}", e);
-        std::process::exit(1);
-    }
-
     if let Err(e) = commands::parse_subcommands(&mut con) {
         eprintln!("Error: {e}");
         std::process::exit(1);
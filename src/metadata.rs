@@ -0,0 +1,73 @@
+use postgres::Row;
+use serde_derive::Serialize;
+
+use crate::{exit_with_message, parse_datetime};
+
+// created/modified timestamps & operators (see `get_ssh_client`), expects
+// the columns created_at & modified_at as VARCHAR
+#[derive(Debug, Serialize)]
+pub struct Metadata {
+    pub created_at: Option<String>,
+    pub created_by: Option<String>,
+    pub modified_at: Option<String>,
+    pub modified_by: Option<String>,
+}
+
+impl Metadata {
+    pub fn from_row(row: &Row) -> Metadata {
+        Metadata {
+            created_at: row.get("created_at"),
+            created_by: row.get("created_by"),
+            modified_at: row.get("modified_at"),
+            modified_by: row.get("modified_by"),
+        }
+    }
+
+    pub fn added(&self) -> String {
+        display(self.created_at.as_ref(), self.created_by.as_ref())
+    }
+
+    pub fn modified(&self) -> String {
+        display(self.modified_at.as_ref(), self.modified_by.as_ref())
+    }
+}
+
+fn display(at: Option<&String>, by: Option<&String>) -> String {
+    match (at, by) {
+        (Some(at), Some(by)) => format!("{at} ({by})"),
+        (Some(at), None) => at.to_string(),
+        (None, Some(by)) => format!("({by})"),
+        (None, None) => "-".to_string(),
+    }
+}
+
+// --added-by (substring) & --added-after (YYYY-MM-DD, optional w/ HH:MI:SS)
+pub fn retain<T>(
+    res: &mut Vec<T>,
+    metadata: impl Fn(&T) -> &Metadata,
+    added_by: Option<&str>,
+    added_after: Option<&str>,
+) {
+    if let Some(added_by) = added_by {
+        res.retain(|x| {
+            metadata(x)
+                .created_by
+                .as_ref()
+                .is_some_and(|by| by.to_lowercase().contains(&added_by.to_lowercase()))
+        });
+    }
+
+    if let Some(added_after) = added_after {
+        let Some(after) = parse_datetime(added_after) else {
+            exit_with_message("Could not parse datetime.")
+        };
+
+        res.retain(|x| {
+            metadata(x)
+                .created_at
+                .as_deref()
+                .and_then(parse_datetime)
+                .is_some_and(|at| at > after)
+        });
+    }
+}
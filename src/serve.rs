@@ -0,0 +1,338 @@
+use log::{error, info, warn};
+use postgres::{Client, Error, NoTls};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+use tiny_http::{Header, Method, Response};
+
+use crate::exit_with_message;
+use crate::server::hash_token;
+use crate::serverauth::{generate_all, update_dns, ServerAuth};
+
+// retry delays while the database is unreachable (doubled after every failure)
+const RETRY_MIN: Duration = Duration::from_secs(1);
+const RETRY_MAX: Duration = Duration::from_secs(60);
+
+struct Cache {
+    serverauth: Vec<ServerAuth>,
+    servers: HashSet<IpAddr>,
+    tokens: HashMap<String, IpAddr>,
+}
+
+fn refresh(pgclient: &mut Client) -> Result<Cache, Error> {
+    update_dns(pgclient)?;
+
+    let mut servers = HashSet::new();
+    let mut tokens = HashMap::new();
+
+    for row in pgclient.query(r"SELECT ip, token_hash FROM server WHERE NOT disabled", &[])? {
+        let ip: IpAddr = row.get("ip");
+        servers.insert(ip);
+
+        if let Some(hash) = row.get::<&str, Option<String>>("token_hash") {
+            tokens.insert(hash, ip);
+        }
+    }
+
+    Ok(Cache {
+        serverauth: generate_all(pgclient)?,
+        servers,
+        tokens,
+    })
+}
+
+fn reconnect() -> Result<Client, String> {
+    let Some(config) = crate::DB_CONFIG.get() else {
+        return Err("no database config".to_string());
+    };
+
+    config.connect(NoTls).map_err(|e| e.to_string())
+}
+
+// peers connecting via IPv6 to a dual stack socket show up as ::ffff:a.b.c.d
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+// (status, content type, body)
+fn respond(
+    cache: &Cache,
+    peer: IpAddr,
+    token: Option<&str>,
+    url: &str,
+) -> (u16, &'static str, String) {
+    // a token overrides the peer address (NAT, proxies, ...)
+    let host = match token {
+        Some(token) => cache.tokens.get(&hash_token(token)).copied(),
+        None => Some(canonical(peer)).filter(|ip| cache.servers.contains(ip)),
+    };
+
+    let Some(host) = host else {
+        return (403, "text/plain", "Forbidden\n".to_string());
+    };
+
+    let path = url.split('?').next().unwrap_or_default();
+    let serverauth = cache
+        .serverauth
+        .iter()
+        .filter(|a| a.serverip.eq(&host.to_string()));
+
+    if path.eq("/serverauth") {
+        let serverauth = serverauth.collect::<Vec<&ServerAuth>>();
+        return (
+            200,
+            "application/json",
+            serde_json::to_string(&serverauth).unwrap_or_default(),
+        );
+    }
+
+    let Some(sshuser) = path
+        .strip_prefix("/authorized_keys/")
+        .filter(|u| !u.is_empty() && !u.contains('/'))
+    else {
+        return (404, "text/plain", "Not Found\n".to_string());
+    };
+
+    let mut body = String::new();
+
+    for auth in serverauth.filter(|a| a.sshuser.user.eq(sshuser)) {
+        for line in auth.sshuser.authorized_keys.lines() {
+            body.push_str(line);
+            body.push('\n');
+        }
+    }
+
+    (200, "text/plain", body)
+}
+
+pub fn serve(pgclient: &mut Client, listen: &str, ttl: Duration) {
+    // None: no auth list yet (the database was unreachable from the start)
+    let cache = match refresh(pgclient) {
+        Ok(c) => RwLock::new(Some(Arc::new(c))),
+        Err(e) => {
+            error!("(serve) Could not generate serverauth: {e}");
+            RwLock::new(None)
+        }
+    };
+
+    let Ok(server) = tiny_http::Server::http(listen) else {
+        exit_with_message(&format!("Could not listen on '{listen}'."))
+    };
+
+    info!("(serve) Listening on '{listen}'");
+    println!("Listening on '{listen}'");
+
+    thread::scope(|s| {
+        // refresh in the background, requests are answered from the last good copy meanwhile
+        // (also while the database is down, hosts must not lose their keys)
+        s.spawn(|| {
+            let mut delay = if cache.read().is_ok_and(|c| c.is_some()) {
+                ttl
+            } else {
+                RETRY_MIN
+            };
+
+            let mut connected = true;
+
+            loop {
+                thread::sleep(delay);
+
+                if !connected {
+                    match reconnect() {
+                        Ok(client) => {
+                            info!("(serve) Reconnected to database");
+                            *pgclient = client;
+                            connected = true;
+                        }
+                        Err(e) => {
+                            error!("(serve) Could not reconnect to database: {e}");
+                            delay = (delay * 2).clamp(RETRY_MIN, RETRY_MAX);
+                            continue;
+                        }
+                    }
+                }
+
+                match refresh(pgclient) {
+                    Ok(c) => {
+                        if let Ok(mut cache) = cache.write() {
+                            *cache = Some(Arc::new(c));
+                        }
+                        delay = ttl;
+                    }
+                    Err(e) => {
+                        error!("(serve) Could not refresh serverauth (keeping the last one): {e}");
+
+                        // database restart, network issues, ...
+                        if pgclient.is_closed()
+                            || pgclient.is_valid(Duration::from_secs(5)).is_err()
+                        {
+                            connected = false;
+                            delay = RETRY_MIN;
+                        }
+                    }
+                }
+            }
+        });
+
+        for request in server.incoming_requests() {
+            let Some(peer) = request.remote_addr().map(std::net::SocketAddr::ip) else {
+                continue;
+            };
+
+            let Some(current) = cache.read().ok().and_then(|c| c.clone()) else {
+                warn!("(serve) {} 503 {}", peer, request.url());
+                let _ = request
+                    .respond(Response::from_string("Service Unavailable\n").with_status_code(503));
+                continue;
+            };
+
+            let (status, content_type, body) = if request.method().eq(&Method::Get) {
+                let token = request
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv("Authorization"))
+                    .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+                    .map(str::trim);
+
+                respond(&current, peer, token, request.url())
+            } else {
+                (405, "text/plain", "Method Not Allowed\n".to_string())
+            };
+
+            if status == 200 {
+                info!("(serve) {} {} {}", peer, status, request.url());
+            } else {
+                warn!("(serve) {} {} {}", peer, status, request.url());
+            }
+
+            let mut response = Response::from_string(body).with_status_code(status);
+            if let Ok(header) = Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()) {
+                response = response.with_header(header);
+            }
+
+            if let Err(e) = request.respond(response) {
+                warn!("(serve) Could not send response to {peer}: {e}");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serverauth::{AuthorizedKey, AuthorizedKeys, AuthorizedUser};
+
+    fn serverauth(ip: &str, user: &str, line: &str) -> ServerAuth {
+        ServerAuth {
+            servername: ip.to_string(),
+            serverip: ip.to_string(),
+            sshuser: AuthorizedUser {
+                user: user.to_string(),
+                authorized_keys: AuthorizedKeys {
+                    keys: vec![AuthorizedKey {
+                        keyid: 1,
+                        fingerprint: String::new(),
+                        email: String::new(),
+                        options: Vec::new(),
+                        granted_by: Vec::new(),
+                        line: line.to_string(),
+                    }],
+                    cert_authorities: Vec::new(),
+                },
+                authorized_principals: Vec::new(),
+            },
+        }
+    }
+
+    fn cache() -> Cache {
+        let web01: IpAddr = "10.0.0.1".parse().unwrap();
+        let web02: IpAddr = "10.0.0.2".parse().unwrap();
+
+        Cache {
+            serverauth: vec![
+                serverauth("10.0.0.1", "root", "key-web01"),
+                serverauth("10.0.0.2", "root", "key-web02"),
+            ],
+            servers: HashSet::from([web01, web02]),
+            tokens: HashMap::from([(hash_token("secret"), web02)]),
+        }
+    }
+
+    #[test]
+    fn respond_by_peer_address() {
+        let cache = cache();
+
+        let (status, _, body) = respond(
+            &cache,
+            "10.0.0.1".parse().unwrap(),
+            None,
+            "/authorized_keys/root",
+        );
+        assert_eq!((status, body.as_str()), (200, "key-web01\n"));
+
+        let (status, _, body) = respond(
+            &cache,
+            "::ffff:10.0.0.1".parse().unwrap(),
+            None,
+            "/authorized_keys/root",
+        );
+        assert_eq!((status, body.as_str()), (200, "key-web01\n"));
+
+        let (status, _, body) = respond(
+            &cache,
+            "10.0.0.1".parse().unwrap(),
+            None,
+            "/authorized_keys/deploy",
+        );
+        assert_eq!((status, body.as_str()), (200, ""));
+
+        let (status, _, _) = respond(
+            &cache,
+            "10.0.0.3".parse().unwrap(),
+            None,
+            "/authorized_keys/root",
+        );
+        assert_eq!(status, 403);
+    }
+
+    #[test]
+    fn respond_by_token() {
+        let cache = cache();
+
+        let (status, _, body) = respond(
+            &cache,
+            "192.0.2.1".parse().unwrap(),
+            Some("secret"),
+            "/authorized_keys/root",
+        );
+        assert_eq!((status, body.as_str()), (200, "key-web02\n"));
+
+        // a wrong token must not fall back to the peer address
+        let (status, _, _) = respond(
+            &cache,
+            "10.0.0.1".parse().unwrap(),
+            Some("wrong"),
+            "/authorized_keys/root",
+        );
+        assert_eq!(status, 403);
+    }
+
+    #[test]
+    fn respond_unknown_path() {
+        let cache = cache();
+        let peer = "10.0.0.1".parse().unwrap();
+
+        assert_eq!(respond(&cache, peer, None, "/authorized_keys/").0, 404);
+        assert_eq!(
+            respond(&cache, peer, None, "/authorized_keys/root/x").0,
+            404
+        );
+        assert_eq!(respond(&cache, peer, None, "/").0, 404);
+        assert_eq!(respond(&cache, peer, None, "/serverauth?x=1").0, 200);
+    }
+}
@@ -1,12 +1,16 @@
+use base64::{engine, Engine};
 use log::info;
 use owo_colors::{OwoColorize, Stream::Stdout};
 use postgres::{Client, Error};
 use prettytable::{format, Table};
 use serde_derive::Serialize;
-use std::io::{self, Write};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read, Write};
 use std::net::IpAddr;
 
 use crate::logging::get_ssh_client;
+use crate::metadata::{self, Metadata};
 use crate::{ask_for, exit_with_message, server, set_or_ask_for, ListObject};
 
 #[allow(clippy::module_name_repetitions)]
@@ -18,6 +22,8 @@ pub struct ServerQuery {
     pub disabled: String,
     pub use_dns: String,
     pub comment: Option<String>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
 }
 
 pub fn add(
@@ -29,12 +35,12 @@ pub fn add(
     comment: Option<&str>,
 ) -> Result<(), Error> {
     println!("Add server");
-    let query_string = r"INSERT INTO server (name, ip, disabled, use_dns, comment)
-                          VALUES ($1, $2, $3, $4, $5)";
+    let query_string = r"INSERT INTO server (name, ip, disabled, use_dns, comment, created_by)
+                          VALUES ($1, $2, $3, $4, $5, $6)";
 
     let newservername = ask_for(&ListObject::ServerName, servername, None, pgclient);
 
-    if newservername.eq("") {
+    if newservername.is_empty() {
         exit_with_message("Server name cannot be empty.");
     }
 
@@ -67,7 +73,14 @@ pub fn add(
 
     pgclient.query(
         query_string,
-        &[&newservername, &newip, &disabled, &use_dns, &newcomment],
+        &[
+            &newservername,
+            &newip,
+            &disabled,
+            &use_dns,
+            &newcomment,
+            &get_ssh_client(),
+        ],
     )?;
 
     info!(
@@ -100,7 +113,7 @@ pub fn add_to_servergroup(
 
     let newgroupname = ask_for(&ListObject::ServerGroup, servergroup, None, pgclient);
 
-    if newgroupname.eq("") {
+    if newgroupname.is_empty() {
         exit_with_message("Name cannot be empty.");
     }
 
@@ -115,7 +128,7 @@ pub fn add_to_servergroup(
 
     let newservername = ask_for(&ListObject::ServerName, servername, None, pgclient);
 
-    if newservername.eq("") {
+    if newservername.is_empty() {
         exit_with_message("Server name cannot be empty.");
     }
 
@@ -176,13 +189,87 @@ pub fn add_to_servergroup(
     Ok(())
 }
 
+pub fn add_token(
+    pgclient: &mut Client,
+    servername: Option<&str>,
+    force: bool,
+) -> Result<(), Error> {
+    println!("Add server token");
+    let query_string = r"UPDATE server
+                          SET token_hash = $1, modified_at = NOW(), modified_by = $3
+                          WHERE name = $2";
+
+    let oldname = ask_for(&ListObject::ServerName, servername, None, pgclient);
+
+    if oldname.is_empty() {
+        exit_with_message("Server name cannot be empty.");
+    }
+
+    let res = pgclient.query(
+        r"SELECT token_hash FROM server WHERE name = $1 LIMIT 1",
+        &[&oldname],
+    )?;
+
+    if res.is_empty() {
+        exit_with_message("Server not found.");
+    }
+
+    if !force && res[0].get::<&str, Option<String>>("token_hash").is_some() {
+        println!();
+        print!(
+            "Do you really want to replace the token of server '{}'? [y/N]: ",
+            &oldname
+        );
+        let mut userinput = String::new();
+        io::stdout().flush().unwrap();
+        io::stdin().read_line(&mut userinput).unwrap();
+        if !userinput.trim().to_lowercase().eq("y") {
+            println!("Operation cancelled.");
+            std::process::exit(1);
+        }
+    }
+
+    let mut random = [0u8; 32];
+    if File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut random))
+        .is_err()
+    {
+        exit_with_message("Could not generate token.");
+    }
+
+    let token = engine::general_purpose::URL_SAFE_NO_PAD.encode(random);
+
+    _ = pgclient.execute(
+        query_string,
+        &[&hash_token(&token), &oldname, &get_ssh_client()],
+    )?;
+
+    info!(
+        "({}) Added token for server '{}'",
+        &get_ssh_client(),
+        &oldname
+    );
+
+    // only the hash is stored, so this is the only chance to see the token
+    println!();
+    println!("Token: {token}");
+    println!();
+
+    println!(
+        "{}",
+        "Done.".if_supports_color(Stdout, owo_colors::OwoColorize::green)
+    );
+
+    Ok(())
+}
+
 pub fn delete(pgclient: &mut Client, servername: Option<&str>, force: bool) -> Result<(), Error> {
     println!("Delete server");
     let query_string = r"DELETE FROM server WHERE name = $1";
 
     let oldservername = ask_for(&ListObject::ServerName, servername, None, pgclient);
 
-    if oldservername.eq("") {
+    if oldservername.is_empty() {
         exit_with_message("Server name cannot be empty.");
     }
 
@@ -247,7 +334,7 @@ pub fn delete_from_servergroup(
 
     let oldservername = ask_for(&ListObject::ServerName, servername, None, pgclient);
 
-    if oldservername.eq("") {
+    if oldservername.is_empty() {
         exit_with_message("Server name cannot be empty.");
     }
 
@@ -263,7 +350,7 @@ pub fn delete_from_servergroup(
 
     let oldservergroup = ask_for(&ListObject::ServerGroup, servergroup, None, pgclient);
 
-    if oldservergroup.eq("") {
+    if oldservergroup.is_empty() {
         exit_with_message("Group name cannot be empty.");
     }
 
@@ -360,13 +447,71 @@ pub fn delete_from_servergroup(
     Ok(())
 }
 
+pub fn delete_token(
+    pgclient: &mut Client,
+    servername: Option<&str>,
+    force: bool,
+) -> Result<(), Error> {
+    println!("Delete server token");
+    let query_string = r"UPDATE server
+                          SET token_hash = NULL, modified_at = NOW(), modified_by = $2
+                          WHERE name = $1";
+
+    let oldname = ask_for(&ListObject::ServerName, servername, None, pgclient);
+
+    if oldname.is_empty() {
+        exit_with_message("Server name cannot be empty.");
+    }
+
+    let res = pgclient.query(
+        r"SELECT name FROM server WHERE name = $1 LIMIT 1",
+        &[&oldname],
+    )?;
+
+    if res.is_empty() {
+        exit_with_message("Server not found.");
+    }
+
+    if !force {
+        println!();
+        print!(
+            "Do you really want to delete the token of server '{}'? [y/N]: ",
+            &oldname
+        );
+        let mut userinput = String::new();
+        io::stdout().flush().unwrap();
+        io::stdin().read_line(&mut userinput).unwrap();
+        if !userinput.trim().to_lowercase().eq("y") {
+            println!("Operation cancelled.");
+            std::process::exit(1);
+        }
+    }
+
+    _ = pgclient.execute(query_string, &[&oldname, &get_ssh_client()])?;
+
+    info!(
+        "({}) Deleted token for server '{}'",
+        &get_ssh_client(),
+        &oldname
+    );
+
+    println!(
+        "{}",
+        "Done.".if_supports_color(Stdout, owo_colors::OwoColorize::green)
+    );
+
+    Ok(())
+}
+
 pub fn disable(pgclient: &mut Client, servername: Option<&str>, force: bool) -> Result<(), Error> {
     println!("Disable server");
-    let query_string = r"UPDATE server SET disabled = true WHERE name = $1";
+    let query_string = r"UPDATE server
+                          SET disabled = true, modified_at = NOW(), modified_by = $2
+                          WHERE name = $1";
 
     let oldname = ask_for(&ListObject::ServerName, servername, None, pgclient);
 
-    if oldname.eq("") {
+    if oldname.is_empty() {
         exit_with_message("Server name cannot be empty.");
     }
 
@@ -394,7 +539,7 @@ pub fn disable(pgclient: &mut Client, servername: Option<&str>, force: bool) ->
         }
     }
 
-    _ = pgclient.execute(query_string, &[&oldname])?;
+    _ = pgclient.execute(query_string, &[&oldname, &get_ssh_client()])?;
 
     info!("({}) Disabled server '{}'", &get_ssh_client(), &oldname);
 
@@ -412,11 +557,13 @@ pub fn disable_dns(
     force: bool,
 ) -> Result<(), Error> {
     println!("Disable server DNS lookup");
-    let query_string = r"UPDATE server SET use_dns = false WHERE name = $1";
+    let query_string = r"UPDATE server
+                          SET use_dns = false, modified_at = NOW(), modified_by = $2
+                          WHERE name = $1";
 
     let oldname = ask_for(&ListObject::ServerName, servername, None, pgclient);
 
-    if oldname.eq("") {
+    if oldname.is_empty() {
         exit_with_message("Server name cannot be empty.");
     }
 
@@ -444,7 +591,7 @@ pub fn disable_dns(
         }
     }
 
-    _ = pgclient.execute(query_string, &[&oldname])?;
+    _ = pgclient.execute(query_string, &[&oldname, &get_ssh_client()])?;
 
     info!(
         "({}) Disabled server DNS lookup '{}'",
@@ -462,11 +609,13 @@ pub fn disable_dns(
 
 pub fn enable(pgclient: &mut Client, servername: Option<&str>, force: bool) -> Result<(), Error> {
     println!("Enable server");
-    let query_string = r"UPDATE server SET disabled = false WHERE name = $1";
+    let query_string = r"UPDATE server
+                          SET disabled = false, modified_at = NOW(), modified_by = $2
+                          WHERE name = $1";
 
     let oldname = ask_for(&ListObject::ServerName, servername, None, pgclient);
 
-    if oldname.eq("") {
+    if oldname.is_empty() {
         exit_with_message("Server name cannot be empty.");
     }
 
@@ -494,7 +643,7 @@ pub fn enable(pgclient: &mut Client, servername: Option<&str>, force: bool) -> R
         }
     }
 
-    _ = pgclient.execute(query_string, &[&oldname])?;
+    _ = pgclient.execute(query_string, &[&oldname, &get_ssh_client()])?;
 
     info!("({}) Enabled server '{}'", &get_ssh_client(), &oldname);
 
@@ -512,11 +661,13 @@ pub fn enable_dns(
     force: bool,
 ) -> Result<(), Error> {
     println!("Enable server DNS lookup");
-    let query_string = r"UPDATE server SET use_dns = true WHERE name = $1";
+    let query_string = r"UPDATE server
+                          SET use_dns = true, modified_at = NOW(), modified_by = $2
+                          WHERE name = $1";
 
     let oldname = ask_for(&ListObject::ServerName, servername, None, pgclient);
 
-    if oldname.eq("") {
+    if oldname.is_empty() {
         exit_with_message("Server name cannot be empty.");
     }
 
@@ -544,7 +695,7 @@ pub fn enable_dns(
         }
     }
 
-    _ = pgclient.execute(query_string, &[&oldname])?;
+    _ = pgclient.execute(query_string, &[&oldname, &get_ssh_client()])?;
 
     info!(
         "({}) Enabled server DNS lookup '{}'",
@@ -560,15 +711,20 @@ pub fn enable_dns(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn list(
     pgclient: &mut Client,
     servername: Option<&str>,
     ip: Option<&str>,
     id: Option<&str>,
+    added_by: Option<&str>,
+    added_after: Option<&str>,
     exact: bool,
     json: bool,
 ) -> std::result::Result<(), Error> {
-    let query_string = r"SELECT id, name, ip, disabled::CHAR, use_dns::CHAR, comment
+    let query_string = r"SELECT id, name, ip, disabled::CHAR, use_dns::CHAR, comment,
+                                 created_at::TIMESTAMP(0)::VARCHAR AS created_at, created_by,
+                                 modified_at::TIMESTAMP(0)::VARCHAR AS modified_at, modified_by
                           FROM server
                           ORDER BY id, name";
 
@@ -582,9 +738,12 @@ pub fn list(
             disabled: row.get("disabled"),
             use_dns: row.get("use_dns"),
             comment: row.get("comment"),
+            metadata: Metadata::from_row(&row),
         });
     }
 
+    metadata::retain(&mut res, |x| &x.metadata, added_by, added_after);
+
     if let Some(name) = servername {
         if exact {
             res.retain(|x| x.name.eq(&name));
@@ -618,7 +777,9 @@ pub fn list(
         let mut table = Table::new();
         table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
 
-        table.set_titles(row!["name", "ip", "disabled", "dns", "comment"]);
+        table.set_titles(row![
+            "name", "ip", "disabled", "dns", "comment", "added", "modified"
+        ]);
 
         for r in res {
             let (name, ip, disabled) = match &r.disabled[..] {
@@ -648,7 +809,9 @@ pub fn list(
                 ip,
                 disabled,
                 use_dns,
-                r.comment.unwrap_or_else(|| "-".to_string())
+                r.comment.unwrap_or_else(|| "-".to_string()),
+                r.metadata.added(),
+                r.metadata.modified()
             ]);
         }
 
@@ -671,12 +834,14 @@ pub fn update(
     let query_string = r"UPDATE server
                           SET name = $1,
                               ip = $2,
-                              comment = $3
+                              comment = $3,
+                              modified_at = NOW(),
+                              modified_by = $5
                           WHERE name = $4";
 
     let oldservername = ask_for(&ListObject::ServerName, servername, None, pgclient);
 
-    if oldservername.eq("") {
+    if oldservername.is_empty() {
         exit_with_message("Server name cannot be empty.");
     }
 
@@ -703,7 +868,7 @@ pub fn update(
         pgclient,
     );
 
-    if newservername.to_lowercase().eq("") {
+    if newservername.to_lowercase().is_empty() {
         newservername.clone_from(&oldservername);
     } else if !pgclient
         .query(
@@ -723,7 +888,7 @@ pub fn update(
         ),
     );
 
-    let newipaddr = if newip.eq("") {
+    let newipaddr = if newip.is_empty() {
         oldserverip
     } else {
         match newip.parse::<std::net::IpAddr>() {
@@ -753,7 +918,7 @@ pub fn update(
         ),
     );
 
-    let newcommentopt = if newcomment.to_lowercase().eq("") {
+    let newcommentopt = if newcomment.to_lowercase().is_empty() {
         oldservercomment
     } else if newcomment.trim().to_lowercase().eq("null") {
         None
@@ -763,7 +928,13 @@ pub fn update(
 
     pgclient.query(
         query_string,
-        &[&newservername, &newipaddr, &newcommentopt, &oldservername],
+        &[
+            &newservername,
+            &newipaddr,
+            &newcommentopt,
+            &oldservername,
+            &get_ssh_client(),
+        ],
     )?;
 
     info!(
@@ -783,3 +954,10 @@ pub fn update(
 
     Ok(())
 }
+
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
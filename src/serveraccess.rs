@@ -6,7 +6,11 @@ use serde_derive::Serialize;
 use std::io::{self, Write};
 use std::net::IpAddr;
 
+use crate::access::ServerAccess;
+use crate::key::KEY_TYPES;
 use crate::logging::get_ssh_client;
+use crate::metadata::{self, Metadata};
+use crate::serverauth::split_options;
 use crate::{ask_for, exit_with_message, set_or_ask_for, ListObject};
 
 #[derive(Debug, Serialize)]
@@ -19,6 +23,10 @@ pub struct ServerAccessQuery {
     pub sshcommand: Option<String>,
     pub sshoption: Option<String>,
     pub servergroup: Option<String>,
+    pub keytypes: Option<String>,
+    pub verify_required: bool,
+    #[serde(flatten)]
+    pub metadata: Metadata,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -33,13 +41,15 @@ pub fn add(
     servername: Option<&str>,
     servergroup: Option<&str>,
     comment: Option<&str>,
+    keytypes: Option<&str>,
+    verify_required: bool,
 ) -> Result<(), Error> {
     println!("Add server access");
     let query_string;
 
     let newname = ask_for(&ListObject::ServerAccess, serveraccess, None, pgclient);
 
-    if newname.eq("") {
+    if newname.is_empty() {
         exit_with_message("Server access name cannot be empty.");
     }
 
@@ -54,7 +64,7 @@ pub fn add(
 
     let mut newuser = set_or_ask_for(sshuser, "SSH user [default: administrator]");
 
-    if newuser.eq("") {
+    if newuser.is_empty() {
         newuser = "administrator".to_string();
     }
 
@@ -76,6 +86,11 @@ pub fn add(
         _ => Some(newoption),
     };
 
+    // not asked for, no key policy by default
+    let newkeytypes = keytypes.filter(|k| !matches!(&k.trim().to_lowercase()[..], "" | "null"));
+
+    check_policy(newkeytypes, verify_required, newoption.as_deref());
+
     let newservername = if servergroup.is_none() {
         ask_for(&ListObject::ServerName, servername, None, pgclient)
     } else {
@@ -104,8 +119,8 @@ pub fn add(
                     exit_with_message("Server group not found.");
                 }
 
-                query_string = r"INSERT INTO serveraccess (name, sshuser, sshfrom, sshcommand, sshoption, comment, servergroup_id)
-                                  SELECT $1, $2, $3, $4, $5, $6, id
+                query_string = r"INSERT INTO serveraccess (name, sshuser, sshfrom, sshcommand, sshoption, comment, created_by, keytypes, verify_required, servergroup_id)
+                                  SELECT $1, $2, $3, $4, $5, $6, $8, $9, $10, id
                                   FROM servergroup
                                   WHERE servergroup.name = $7".to_string();
                 Some(newgroupname)
@@ -121,8 +136,8 @@ pub fn add(
             exit_with_message("Server not found.");
         }
 
-        query_string = r"INSERT INTO serveraccess (name, sshuser, sshfrom, sshcommand, sshoption, comment, server_id)
-                          SELECT $1, $2, $3, $4, $5, $6, id
+        query_string = r"INSERT INTO serveraccess (name, sshuser, sshfrom, sshcommand, sshoption, comment, created_by, keytypes, verify_required, server_id)
+                          SELECT $1, $2, $3, $4, $5, $6, $8, $9, $10, id
                           FROM server
                           WHERE server.name = $7".to_string();
         newservername
@@ -144,6 +159,9 @@ pub fn add(
             &newoption,
             &newcomment,
             &newservername,
+            &get_ssh_client(),
+            &newkeytypes,
+            &verify_required,
         ],
     )?;
 
@@ -163,7 +181,7 @@ pub fn delete(pgclient: &mut Client, serveraccess: Option<&str>, force: bool) ->
 
     let oldserveraccess = ask_for(&ListObject::ServerAccess, serveraccess, None, pgclient);
 
-    if oldserveraccess.eq("") {
+    if oldserveraccess.is_empty() {
         exit_with_message("Server access cannot be empty.");
     }
 
@@ -217,6 +235,8 @@ pub fn list(
     ip: Option<&str>,
     user: Option<&str>,
     servergroup: Option<&str>,
+    added_by: Option<&str>,
+    added_after: Option<&str>,
     exact: bool,
     json: bool,
 ) -> Result<(), Error> {
@@ -235,7 +255,13 @@ pub fn list(
                   serveraccess.sshfrom,
                   serveraccess.sshcommand,
                   serveraccess.sshoption,
-                  sg.name AS servergroup
+                  sg.name AS servergroup,
+                  serveraccess.keytypes,
+                  serveraccess.verify_required,
+                  serveraccess.created_at::TIMESTAMP(0)::VARCHAR AS created_at,
+                  serveraccess.created_by,
+                  serveraccess.modified_at::TIMESTAMP(0)::VARCHAR AS modified_at,
+                  serveraccess.modified_by
            FROM serveraccess
            LEFT JOIN (serveraccess AS sa
                       JOIN (servergroup AS sg
@@ -263,7 +289,13 @@ pub fn list(
                   sa.sshfrom,
                   sa.sshcommand,
                   sa.sshoption,
-                  '-' AS servergroup
+                  '-' AS servergroup,
+                  sa.keytypes,
+                  sa.verify_required,
+                  sa.created_at::TIMESTAMP(0)::VARCHAR AS created_at,
+                  sa.created_by,
+                  sa.modified_at::TIMESTAMP(0)::VARCHAR AS modified_at,
+                  sa.modified_by
            FROM serveraccess AS sa,
                 server AS s
            WHERE sa.server_id = s.id
@@ -275,7 +307,13 @@ pub fn list(
                   sa.sshfrom,
                   sa.sshcommand,
                   sa.sshoption,
-                  sg.name AS servergroup
+                  sg.name AS servergroup,
+                  sa.keytypes,
+                  sa.verify_required,
+                  sa.created_at::TIMESTAMP(0)::VARCHAR AS created_at,
+                  sa.created_by,
+                  sa.modified_at::TIMESTAMP(0)::VARCHAR AS modified_at,
+                  sa.modified_by
            FROM serveraccess AS sa,
                 servergroup AS sg
            WHERE sa.servergroup_id = sg.id
@@ -297,9 +335,14 @@ pub fn list(
             sshcommand: row.get("sshcommand"),
             sshoption: row.get("sshoption"),
             servergroup: row.get("servergroup"),
+            keytypes: row.get("keytypes"),
+            verify_required: row.get("verify_required"),
+            metadata: Metadata::from_row(&row),
         });
     }
 
+    metadata::retain(&mut res, |x| &x.metadata, added_by, added_after);
+
     if let Some(name) = serveraccess {
         if exact {
             res.retain(|x| x.name.eq(&name));
@@ -373,11 +416,20 @@ pub fn list(
             "ssh from",
             "ssh command",
             "ssh option",
-            "server group"
+            "server group",
+            "key policy",
+            "added",
+            "modified"
         ]);
 
         for r in res {
             let ip = r.ip.map_or_else(|| "-".to_string(), |ip| ip.to_string());
+            let policy = match (&r.keytypes, r.verify_required) {
+                (Some(keytypes), true) => format!("{keytypes}, verify-required"),
+                (Some(keytypes), false) => keytypes.clone(),
+                (None, true) => "verify-required".to_string(),
+                (None, false) => "-".to_string(),
+            };
             table.add_row(row![
                 r.name,
                 r.sshuser,
@@ -386,7 +438,10 @@ pub fn list(
                 r.sshfrom.unwrap_or_else(|| "-".to_string()),
                 r.sshcommand.unwrap_or_else(|| "-".to_string()),
                 r.sshoption.unwrap_or_else(|| "-".to_string()),
-                r.servergroup.unwrap_or_else(|| "-".to_string())
+                r.servergroup.unwrap_or_else(|| "-".to_string()),
+                policy,
+                r.metadata.added(),
+                r.metadata.modified()
             ]);
         }
 
@@ -409,6 +464,8 @@ pub fn update(
     servername: Option<&str>,
     servergroup: Option<&str>,
     comment: Option<&str>,
+    keytypes: Option<&str>,
+    verify_required: Option<bool>,
 ) -> Result<(), Error> {
     println!("Update server access");
     let query_string = r"UPDATE serveraccess
@@ -419,12 +476,16 @@ pub fn update(
                               sshoption = $5,
                               comment = $6,
                               server_id = $7,
-                              servergroup_id = $8
+                              servergroup_id = $8,
+                              keytypes = $11,
+                              verify_required = $12,
+                              modified_at = NOW(),
+                              modified_by = $10
                           WHERE id = $9";
 
     let newserveraccess = ask_for(&ListObject::ServerAccess, serveraccess, None, pgclient);
 
-    if newserveraccess.eq("") {
+    if newserveraccess.is_empty() {
         exit_with_message("Server access name cannot be empty.");
     }
 
@@ -437,7 +498,9 @@ pub fn update(
                   sshoption,
                   server_id,
                   servergroup_id,
-                  comment
+                  comment,
+                  keytypes,
+                  verify_required
            FROM serveraccess
            WHERE name = $1
            LIMIT 1",
@@ -457,6 +520,8 @@ pub fn update(
     let oldserveraccessserverid: Option<i64> = res[0].get("server_id");
     let oldserveraccessservergroupid: Option<i64> = res[0].get("servergroup_id");
     let oldserveraccesscomment: Option<String> = res[0].get("comment");
+    let oldserveraccesskeytypes: Option<String> = res[0].get("keytypes");
+    let oldserveraccessverifyrequired: bool = res[0].get("verify_required");
 
     let mut newserveraccessname = ask_for(
         &ListObject::ServerAccess,
@@ -468,7 +533,7 @@ pub fn update(
         pgclient,
     );
 
-    if newserveraccessname.eq("") {
+    if newserveraccessname.is_empty() {
         newserveraccessname.clone_from(&oldserveraccessname);
     }
 
@@ -480,7 +545,7 @@ pub fn update(
         ),
     );
 
-    if newsshuser.eq("") {
+    if newsshuser.is_empty() {
         newsshuser.clone_from(&oldserveraccesssshuser);
     }
 
@@ -495,7 +560,7 @@ pub fn update(
         ),
     );
 
-    let newfromopt = if newfrom.eq("") {
+    let newfromopt = if newfrom.is_empty() {
         oldserveraccesssshfrom.clone()
     } else if newfrom.trim().to_lowercase().eq("null") {
         None
@@ -514,7 +579,7 @@ pub fn update(
         ),
     );
 
-    let newcommandopt = if newcommand.eq("") {
+    let newcommandopt = if newcommand.is_empty() {
         oldserveraccesssshcommand
     } else if newcommand.trim().to_lowercase().eq("null") {
         None
@@ -533,7 +598,7 @@ pub fn update(
         ),
     );
 
-    let newoptionopt = if newoption.eq("") {
+    let newoptionopt = if newoption.is_empty() {
         oldserveraccesssshoption
     } else if newoption.trim().to_lowercase().eq("null") {
         None
@@ -541,6 +606,20 @@ pub fn update(
         Some(newoption)
     };
 
+    // not asked for, unchanged w/o --keytypes / --verify-required
+    let newkeytypesopt = match keytypes.map(|k| k.trim().to_lowercase()).as_deref() {
+        None | Some("") => oldserveraccesskeytypes,
+        Some("null") => None,
+        Some(_) => keytypes.map(str::to_string),
+    };
+    let newverifyrequired = verify_required.unwrap_or(oldserveraccessverifyrequired);
+
+    check_policy(
+        newkeytypesopt.as_deref(),
+        newverifyrequired,
+        newoptionopt.as_deref(),
+    );
+
     let oldserveraccessservername: String = if oldserveraccessserverid.is_some() {
         let res = pgclient.query(
             r"SELECT name FROM server WHERE id = $1 LIMIT 1",
@@ -643,7 +722,7 @@ pub fn update(
         ),
     );
 
-    let newcommentopt = if newcomment.to_lowercase().eq("") {
+    let newcommentopt = if newcomment.to_lowercase().is_empty() {
         oldserveraccesscomment
     } else if newcomment.trim().to_lowercase().eq("null") {
         None
@@ -663,6 +742,9 @@ pub fn update(
             &newserverid,
             &newservergroupid,
             &oldserveraccessid,
+            &get_ssh_client(),
+            &newkeytypesopt,
+            &newverifyrequired,
         ],
     )?;
 
@@ -692,3 +774,87 @@ pub fn update(
 
     Ok(())
 }
+
+// comma-separated key types, 'sk': all security key (FIDO) types
+pub fn parse_keytypes(keytypes: &str) -> Result<Vec<String>, String> {
+    let mut res: Vec<String> = Vec::new();
+
+    for keytype in keytypes.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        let expanded = if keytype.eq("sk") {
+            KEY_TYPES
+                .iter()
+                .filter(|t| t.starts_with("sk-"))
+                .copied()
+                .collect()
+        } else if KEY_TYPES.contains(&keytype) {
+            vec![keytype]
+        } else {
+            return Err(format!("unknown key type '{keytype}'"));
+        };
+
+        for keytype in expanded {
+            if !res.iter().any(|t| t.eq(keytype)) {
+                res.push(keytype.to_string());
+            }
+        }
+    }
+
+    if res.is_empty() {
+        return Err("no key types".to_string());
+    }
+
+    Ok(res)
+}
+
+// Err: why the key doesn't satisfy the key policy of the server access
+pub fn key_policy(sa: &ServerAccess, sshkey: &str) -> Result<(), String> {
+    let keytype = sshkey.split_whitespace().next().unwrap_or_default();
+
+    if let Some(keytypes) = &sa.keytypes {
+        let allowed = parse_keytypes(keytypes).map_err(|e| format!("invalid key policy ({e})"))?;
+
+        if !allowed.iter().any(|t| t.eq(keytype)) {
+            return Err(format!("key type '{keytype}' not allowed ({keytypes})"));
+        }
+    }
+
+    // sshd refuses verify-required for keys w/o user verification, i.e. all non-sk keys
+    if sa.verify_required && !keytype.starts_with("sk-") {
+        return Err("verify-required needs a security key (sk-*)".to_string());
+    }
+
+    Ok(())
+}
+
+fn check_policy(keytypes: Option<&str>, verify_required: bool, sshoption: Option<&str>) {
+    let keytypes = match keytypes.map(parse_keytypes) {
+        Some(Ok(keytypes)) => Some(keytypes),
+        Some(Err(e)) => exit_with_message(&format!("Invalid key types: {e}.")),
+        None => None,
+    };
+
+    let sk_only = keytypes
+        .as_ref()
+        .is_some_and(|k| k.iter().all(|t| t.starts_with("sk-")));
+
+    if verify_required
+        && keytypes
+            .as_ref()
+            .is_some_and(|k| !k.iter().any(|t| t.starts_with("sk-")))
+    {
+        exit_with_message("verify-required needs security key types (e.g. 'sk').");
+    }
+
+    if (sk_only || verify_required)
+        && sshoption.is_some_and(|o| {
+            split_options(o)
+                .iter()
+                .any(|o| o.eq_ignore_ascii_case("no-touch-required"))
+        })
+    {
+        println!(
+            "{} sshoption 'no-touch-required' allows security keys w/o user presence (touch).",
+            "warning:".if_supports_color(Stdout, owo_colors::OwoColorize::yellow)
+        );
+    }
+}
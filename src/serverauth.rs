@@ -1,58 +1,453 @@
-use log::{error, warn};
+use log::{error, info, warn};
+use owo_colors::{OwoColorize, Stream::Stdout};
 use postgres::{Client, Error};
+use prettytable::{format, Table};
 use serde_derive::Serialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::ffi::OsStr;
-use std::fs::{self, remove_dir_all, OpenOptions};
+use std::fs;
 use std::io;
 use std::io::prelude::*;
 use std::net::{IpAddr, ToSocketAddrs};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use walkdir::{DirEntry, WalkDir};
 
+use crate::access::{expand, load, AccessGraph, Key, Server, ServerAccess, User, UserAccess};
 use crate::exit_with_message;
+use crate::logging::get_ssh_client;
+use crate::serveraccess::key_policy;
 
 #[derive(Debug, Serialize)]
-struct AuthorizedKeys {
-    pub keys: Vec<String>,
+pub struct Grant {
+    pub serveraccess: String,
+    pub useraccess: i64,
 }
 
 #[derive(Debug, Serialize)]
-struct AuthorizedUser {
+pub struct AuthorizedKey {
+    pub keyid: i64,
+    pub fingerprint: String,
+    pub email: String,
+    pub options: Vec<String>,
+    pub granted_by: Vec<Grant>,
+    pub line: String,
+}
+
+// cert-authority line, principals: emails & user group names
+#[derive(Debug, Serialize)]
+pub struct AuthorizedCA {
+    pub name: String,
+    pub fingerprint: String,
+    pub sshkey: String,
+    pub comment: Option<String>,
+    pub principals: Vec<String>,
+    pub options: Vec<String>,
+    pub granted_by: Vec<Grant>,
+    pub line: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthorizedKeys {
+    pub keys: Vec<AuthorizedKey>,
+    pub cert_authorities: Vec<AuthorizedCA>,
+}
+
+impl AuthorizedKeys {
+    // authorized_keys file contents
+    pub fn lines(&self) -> impl Iterator<Item = &String> {
+        self.keys
+            .iter()
+            .map(|k| &k.line)
+            .chain(self.cert_authorities.iter().map(|c| &c.line))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthorizedUser {
     pub user: String,
     pub authorized_keys: AuthorizedKeys,
+    // AuthorizedPrincipalsFile contents (alternative to cert-authority lines)
+    pub authorized_principals: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
-struct ServerAuth {
+pub struct ServerAuth {
+    pub servername: String,
     pub serverip: String,
     pub sshuser: AuthorizedUser,
 }
 
+#[derive(Debug, Serialize)]
+struct AuthorizedKeyRecord<'a, T> {
+    pub servername: &'a str,
+    pub serverip: &'a str,
+    pub sshuser: &'a str,
+    #[serde(flatten)]
+    pub key: &'a T,
+}
+
+// key not deployed due to the key policy of the server access or conflicting key options
+#[derive(Debug, Serialize)]
+struct FilteredKey {
+    pub servername: String,
+    pub serverip: IpAddr,
+    pub sshuser: String,
+    pub serveraccess: String,
+    pub email: String,
+    pub keyid: i64,
+    pub fingerprint: String,
+    pub reason: String,
+}
+
 #[derive(Debug)]
 struct AuthQuery {
-    _userid: i64,
-    _keyid: i64,
+    userid: i64,
+    keyid: i64,
     host: std::net::IpAddr,
+    servername: String,
+    serveraccess: String,
+    useraccess: i64,
     sshuser: String,
     sshfrom: Option<String>,
     sshcommand: Option<String>,
     sshoption: Option<String>,
+    expiry_time: Option<String>,
     sshkey: String,
+    fingerprint: String,
     email: String,
     comment: Option<String>,
 }
 
-#[allow(clippy::too_many_lines)]
-fn generate(pgclient: &mut Client, ip: Option<&str>) -> Vec<ServerAuth> {
-    let Ok(dns_enabled) = pgclient.query(
+#[derive(Debug)]
+struct CAQuery {
+    caid: i64,
+    host: std::net::IpAddr,
+    servername: String,
+    serveraccess: String,
+    useraccess: i64,
+    sshuser: String,
+    sshfrom: Option<String>,
+    sshcommand: Option<String>,
+    sshoption: Option<String>,
+    expiry_time: Option<String>,
+    name: String,
+    sshkey: String,
+    fingerprint: String,
+    comment: Option<String>,
+    principal: String,
+}
+
+// split option list at commas (w/o splitting quoted values)
+pub fn split_options(options: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for c in options.chars() {
+        match c {
+            _ if escaped => {
+                escaped = false;
+                current.push(c);
+            }
+            '\\' => {
+                escaped = true;
+                current.push(c);
+            }
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            ',' if !quoted => {
+                if !current.trim().is_empty() {
+                    res.push(current.trim().to_string());
+                }
+                current.clear();
+            }
+            _ => current.push(c),
+        }
+    }
+
+    if !current.trim().is_empty() {
+        res.push(current.trim().to_string());
+    }
+
+    res
+}
+
+pub fn options(
+    sshfrom: Option<&str>,
+    sshcommand: Option<&str>,
+    sshoption: Option<&str>,
+    expiry_time: Option<&str>,
+) -> Vec<String> {
+    let mut options = Vec::new();
+
+    if let Some(from) = sshfrom {
+        options.push(format!(r#"from="{from}""#));
+    }
+
+    if let Some(command) = sshcommand {
+        options.push(format!(r#"command="{command}""#));
+    }
+
+    if let Some(option) = sshoption {
+        options.append(&mut split_options(option));
+    }
+
+    if let Some(expiry_time) = expiry_time {
+        options.push(format!(r#"expiry-time="{expiry_time}""#));
+    }
+
+    options
+}
+
+// sshoption plus the options required by the key policy
+fn sshoption(sa: &ServerAccess) -> Option<String> {
+    let verify = sa.verify_required
+        && !sa.sshoption.as_deref().is_some_and(|o| {
+            split_options(o)
+                .iter()
+                .any(|o| o.eq_ignore_ascii_case("verify-required"))
+        });
+
+    match (&sa.sshoption, verify) {
+        (Some(option), true) => Some(format!("{option},verify-required")),
+        (None, true) => Some("verify-required".to_string()),
+        (option, false) => option.clone(),
+    }
+}
+
+// serveraccess options combined w/ the restrictions of a key
+#[derive(Debug, Default, PartialEq)]
+pub struct KeyOptions {
+    pub sshfrom: Option<String>,
+    pub sshcommand: Option<String>,
+    pub sshoption: Option<String>,
+    pub expiry_time: Option<String>,
+}
+
+// Err: why the key options can't be combined w/ the serveraccess options
+pub fn merge_options(sa: &ServerAccess, key: &Key) -> Result<KeyOptions, String> {
+    let sshcommand = match (&sa.sshcommand, &key.sshcommand) {
+        (Some(a), Some(b)) if a.ne(b) => {
+            return Err(format!(
+                "conflicting commands (serveraccess: '{a}', key: '{b}')"
+            ))
+        }
+        (a, b) => a.clone().or_else(|| b.clone()),
+    };
+
+    Ok(KeyOptions {
+        sshfrom: merge_from(sa.sshfrom.as_deref(), key.sshfrom.as_deref())?,
+        sshcommand,
+        sshoption: merge_sshoption(sshoption(sa).as_deref(), key.sshoption.as_deref())?,
+        expiry_time: key.expiry_time.clone(),
+    })
+}
+
+// expiry-time values (YYYYMMDDHHMM compare like dates), None: never expires
+fn earliest(a: Option<String>, b: Option<String>) -> Option<String> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn latest(a: Option<String>, b: Option<String>) -> Option<String> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        _ => None,
+    }
+}
+
+// (address, prefix length) of an address or CIDR range
+pub fn parse_cidr(entry: &str) -> Option<(IpAddr, u8)> {
+    let (addr, prefix) = entry.split_once('/').unwrap_or((entry, ""));
+    let ip = addr.parse::<IpAddr>().ok()?;
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+
+    if prefix.is_empty() {
+        return Some((ip, max));
+    }
+
+    prefix
+        .parse::<u8>()
+        .ok()
+        .filter(|p| *p <= max)
+        .map(|p| (ip, p))
+}
+
+fn cidr_contains(net: (IpAddr, u8), other: (IpAddr, u8)) -> bool {
+    let (a, b, bits) = match (net.0, other.0) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => (u128::from(u32::from(a)), u128::from(u32::from(b)), 32),
+        (IpAddr::V6(a), IpAddr::V6(b)) => (u128::from(a), u128::from(b), 128),
+        _ => return false,
+    };
+
+    net.1 <= other.1 && (net.1 == 0 || (a ^ b) >> (bits - net.1) == 0)
+}
+
+// intersection of two from= pattern-lists: address ranges are narrowed down,
+// other patterns (host names, wildcards) are kept if both lists contain them,
+// negated patterns of both lists apply
+fn merge_from(sa: Option<&str>, key: Option<&str>) -> Result<Option<String>, String> {
+    let (Some(a), Some(b)) = (sa, key) else {
+        return Ok(sa.or(key).map(str::to_string));
+    };
+
+    let patterns = |list: &str| {
+        list.split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<String>>()
+    };
+
+    let (a, b) = (patterns(a), patterns(b));
+    let mut merged: Vec<String> = Vec::new();
+
+    for x in a.iter().filter(|p| !p.starts_with('!')) {
+        for y in b.iter().filter(|p| !p.starts_with('!')) {
+            let narrower = if x.eq("*") || x.eq_ignore_ascii_case(y) {
+                y
+            } else if y.eq("*") {
+                x
+            } else {
+                match (parse_cidr(x), parse_cidr(y)) {
+                    (Some(n), Some(m)) if cidr_contains(n, m) => y,
+                    (Some(n), Some(m)) if cidr_contains(m, n) => x,
+                    _ => continue,
+                }
+            };
+
+            if !merged.contains(narrower) {
+                merged.push(narrower.clone());
+            }
+        }
+    }
+
+    if merged.is_empty() {
+        return Err(format!(
+            "from= lists don't overlap (serveraccess: '{}', key: '{}')",
+            a.join(","),
+            b.join(",")
+        ));
+    }
+
+    for negated in a.iter().chain(b.iter()).filter(|p| p.starts_with('!')) {
+        if !merged.contains(negated) {
+            merged.push(negated.clone());
+        }
+    }
+
+    Ok(Some(merged.join(",")))
+}
+
+// re-enabled by their name after 'restrict'
+const PERMITS: [&str; 5] = [
+    "agent-forwarding",
+    "port-forwarding",
+    "pty",
+    "user-rc",
+    "x11-forwarding",
+];
+
+// union of both option lists, a feature permitted by one list & disabled by the other is a conflict
+fn merge_sshoption(sa: Option<&str>, key: Option<&str>) -> Result<Option<String>, String> {
+    let (Some(a), Some(b)) = (sa, key) else {
+        return Ok(sa.or(key).map(str::to_string));
+    };
+
+    let (a, b) = (split_options(a), split_options(b));
+    let has = |list: &[String], option: &str| list.iter().any(|o| o.eq_ignore_ascii_case(option));
+
+    for permit in PERMITS {
+        let disabled = |list: &[String]| {
+            if has(list, &format!("no-{permit}")) {
+                Some(format!("no-{permit}"))
+            } else if has(list, "restrict") && !has(list, permit) {
+                Some("restrict".to_string())
+            } else {
+                None
+            }
+        };
+
+        if let (true, Some(option)) = (has(&a, permit), disabled(&b)) {
+            return Err(format!(
+                "conflicting options (serveraccess: '{permit}', key: '{option}')"
+            ));
+        }
+
+        if let (Some(option), true) = (disabled(&a), has(&b, permit)) {
+            return Err(format!(
+                "conflicting options (serveraccess: '{option}', key: '{permit}')"
+            ));
+        }
+    }
+
+    // w/o conflicts, every permit is allowed by both lists, so 'restrict' goes first
+    let mut merged: Vec<String> = Vec::new();
+
+    if has(&a, "restrict") || has(&b, "restrict") {
+        merged.push("restrict".to_string());
+    }
+
+    for option in a.iter().chain(b.iter()) {
+        if !merged.iter().any(|m| m.eq_ignore_ascii_case(option)) {
+            merged.push(option.clone());
+        }
+    }
+
+    Ok(Some(merged.join(",")))
+}
+
+fn render_line(options: &[String], auth: &AuthQuery) -> String {
+    let mut l = String::new();
+
+    if !options.is_empty() {
+        l.push_str(&options.join(","));
+        l.push(' ');
+    }
+
+    l.push_str(&auth.sshkey);
+    l.push(' ');
+    l.push_str(&auth.email);
+    if let Some(comment) = &auth.comment {
+        l.push_str(r" (");
+        l.push_str(comment);
+        l.push(')');
+    }
+
+    l
+}
+
+fn render_ca_line(ca: &AuthorizedCA) -> String {
+    let mut options = vec![
+        "cert-authority".to_string(),
+        format!(r#"principals="{}""#, ca.principals.join(",")),
+    ];
+    options.extend(ca.options.iter().cloned());
+
+    let mut l = format!("{} {} {}", options.join(","), ca.sshkey, ca.name);
+    if let Some(comment) = &ca.comment {
+        l.push_str(r" (");
+        l.push_str(comment);
+        l.push(')');
+    }
+
+    l
+}
+
+pub fn update_dns(pgclient: &mut Client) -> Result<(), Error> {
+    let dns_enabled = pgclient.query(
         "SELECT ip, name FROM server WHERE use_dns AND NOT disabled",
         &[],
-    ) else {
-        exit_with_message("Could not generate list of addresses to resolve.")
-    };
+    )?;
 
-    let update_query = r"UPDATE server SET ip = $1 WHERE name = $2";
+    let update_query =
+        r"UPDATE server SET ip = $1, modified_at = NOW(), modified_by = $3 WHERE name = $2";
 
     for name in dns_enabled {
         let mut n = name.get::<&str, String>("name");
@@ -73,7 +468,10 @@ fn generate(pgclient: &mut Client, ip: Option<&str>) -> Vec<ServerAuth> {
 
         if !iplist.contains(&name.get::<&str, IpAddr>("ip")) {
             if pgclient
-                .query(update_query, &[&iplist[0], &n.trim_end_matches(":80")])
+                .query(
+                    update_query,
+                    &[&iplist[0], &n.trim_end_matches(":80"), &get_ssh_client()],
+                )
                 .is_ok()
             {
                 warn!(
@@ -99,135 +497,486 @@ fn generate(pgclient: &mut Client, ip: Option<&str>) -> Vec<ServerAuth> {
         }
     }
 
-    // Built with simplicity in mind... not performance :D
-    let auth_query = r#"SELECT DISTINCT "user".id AS userid,
-                                        sshkeys.id AS keyid,
-                                        server.ip,
-                                        serveraccess.sshuser,
-                                        serveraccess.sshfrom,
-                                        serveraccess.sshcommand,
-                                        serveraccess.sshoption,
-                                        sshkeys.sshkey,
-                                        "user".email,
-                                        SUBSTRING(sshkeys.comment, 1, 64) AS COMMENT
-                        FROM useraccess
-                        LEFT JOIN (useraccess AS ua
-                                   JOIN (usergroup AS ug
-                                         JOIN user_usergroup AS utug ON ug.id = utug.usergroup_id) ON ua.usergroup_id = ug.id
-                                   OR ua.usergroup_id IN
-                                     (WITH RECURSIVE subgroups AS
-                                        (SELECT supergroup_id
-                                         FROM usergroup_usergroup
-                                         WHERE subgroup_id = ug.id
-                                         UNION SELECT u.supergroup_id
-                                         FROM usergroup_usergroup u
-                                         INNER JOIN subgroups x ON x.supergroup_id = u.subgroup_id) SELECT DISTINCT supergroup_id
-                                      FROM subgroups)) ON useraccess.id = ua.id
-                        JOIN "user" ON utug.user_id = "user".id
-                        OR useraccess.user_id = "user".id
-                        JOIN serveraccess
-                        LEFT JOIN (serveraccess AS sa
-                                   JOIN (servergroup AS sg
-                                         JOIN server_servergroup AS stsg ON sg.id = stsg.servergroup_id) ON sa.servergroup_id = sg.id
-                                   OR sa.servergroup_id IN
-                                     (WITH RECURSIVE subgroups AS
-                                        (SELECT supergroup_id
-                                         FROM servergroup_servergroup
-                                         WHERE subgroup_id = sg.id
-                                         UNION SELECT s.supergroup_id
-                                         FROM servergroup_servergroup s
-                                         JOIN subgroups x ON x.supergroup_id = s.subgroup_id) SELECT DISTINCT supergroup_id
-                                      FROM subgroups)) ON serveraccess.id = sa.id ON useraccess.serveraccess_id = serveraccess.id
-                        JOIN server ON stsg.server_id = server.id
-                        OR serveraccess.server_id = server.id
-                        JOIN sshkeys ON "user".id = sshkeys.user_id
-                        WHERE NOT "user".disabled
-                          AND useraccess.best_before > NOW()
-                          AND NOT server.disabled
-                        ORDER BY "user".id,
-                                 sshkeys.id"#;
+    Ok(())
+}
+
+// active (user, server) pairs per useraccess
+struct ActiveGrant<'a> {
+    userid: i64,
+    user: &'a User,
+    server: &'a Server,
+    useraccess: &'a UserAccess,
+    serveraccess: &'a ServerAccess,
+}
 
-    let mut serverauth: Vec<ServerAuth> = Vec::new();
+// resolve group nesting in memory (instead of recursive CTEs in SQL)
+fn grants(graph: &AccessGraph) -> Vec<ActiveGrant<'_>> {
+    let mut memo = HashMap::new();
+    let mut members: HashMap<i64, BTreeSet<i64>> = HashMap::new();
 
-    let Ok(res) = pgclient.query(auth_query, &[]) else {
-        exit_with_message("Could not generate auth list.")
-    };
+    for (userid, groups) in &graph.user_usergroups {
+        for group in groups {
+            for g in expand(*group, &graph.usergroup_parents, &mut memo) {
+                members.entry(g).or_default().insert(*userid);
+            }
+        }
+    }
 
-    let mut hm = HashMap::new();
-
-    for row in res {
-        let auth: AuthQuery = AuthQuery {
-            _userid: row.get("userid"),
-            _keyid: row.get("keyid"),
-            host: row.get("ip"),
-            sshuser: row.get("sshuser"),
-            sshfrom: row.get("sshfrom"),
-            sshcommand: row.get("sshcommand"),
-            sshoption: row.get("sshoption"),
-            sshkey: row.get("sshkey"),
-            email: row.get("email"),
-            comment: row.get("comment"),
+    let mut memo = HashMap::new();
+    let mut hosts: HashMap<i64, BTreeSet<i64>> = HashMap::new();
+
+    for (serverid, groups) in &graph.server_servergroups {
+        for group in groups {
+            for g in expand(*group, &graph.servergroup_parents, &mut memo) {
+                hosts.entry(g).or_default().insert(*serverid);
+            }
+        }
+    }
+
+    let mut seen = BTreeSet::new();
+    let mut grants = Vec::new();
+
+    for ua in graph.useraccess.iter().filter(|ua| !ua.expired) {
+        let Some(sa) = graph.serveraccess.get(&ua.serveraccess_id) else {
+            continue;
         };
 
-        let mut l = String::new();
+        let users = ua
+            .user_id
+            .into_iter()
+            .chain(
+                ua.usergroup_id
+                    .and_then(|g| members.get(&g))
+                    .into_iter()
+                    .flatten()
+                    .copied(),
+            )
+            .collect::<BTreeSet<i64>>();
 
-        // sshfrom
-        if let Some(from) = &auth.sshfrom {
-            l.push_str(r#"from=""#);
-            l.push_str(from);
-            l.push('"');
-            if auth.sshcommand.is_some() || auth.sshoption.is_some() {
-                l.push(',');
-            } else {
-                l.push(' ');
+        let servers = sa
+            .server_id
+            .into_iter()
+            .chain(
+                sa.servergroup_id
+                    .and_then(|g| hosts.get(&g))
+                    .into_iter()
+                    .flatten()
+                    .copied(),
+            )
+            .collect::<BTreeSet<i64>>();
+
+        for userid in users {
+            let Some(user) = graph.users.get(&userid).filter(|u| !u.disabled) else {
+                continue;
+            };
+
+            for serverid in &servers {
+                let Some(server) = graph.servers.get(serverid).filter(|s| !s.disabled) else {
+                    continue;
+                };
+
+                if !seen.insert((userid, *serverid, ua.id)) {
+                    continue;
+                }
+
+                grants.push(ActiveGrant {
+                    userid,
+                    user,
+                    server,
+                    useraccess: ua,
+                    serveraccess: sa,
+                });
             }
         }
+    }
 
-        // sshcommand
-        if let Some(command) = &auth.sshcommand {
-            l.push_str(r#"command=""#);
-            l.push_str(command);
-            l.push('"');
-            if auth.sshoption.is_some() {
-                l.push(',');
-            } else {
-                l.push(' ');
+    grants
+}
+
+fn resolve(graph: &AccessGraph) -> Vec<AuthQuery> {
+    let mut rows = Vec::new();
+
+    for g in grants(graph) {
+        for key in graph.keys.get(&g.userid).into_iter().flatten() {
+            // blocked keys are never deployed, even if still attached to a user
+            if key.expired || key.blocked || key_policy(g.serveraccess, &key.sshkey).is_err() {
+                continue;
             }
+
+            let Ok(options) = merge_options(g.serveraccess, key) else {
+                continue;
+            };
+
+            rows.push(AuthQuery {
+                userid: g.userid,
+                keyid: key.id,
+                host: g.server.ip,
+                servername: g.server.name.clone(),
+                serveraccess: g.serveraccess.name.clone(),
+                useraccess: g.useraccess.id,
+                sshuser: g.serveraccess.sshuser.clone(),
+                sshfrom: options.sshfrom,
+                sshcommand: options.sshcommand,
+                sshoption: options.sshoption,
+                // time-limited access expires on the servers, too
+                expiry_time: earliest(options.expiry_time, g.useraccess.expiry_time.clone()),
+                sshkey: key.sshkey.clone(),
+                fingerprint: key.fingerprint.clone(),
+                email: g.user.email.clone(),
+                comment: key.comment.clone(),
+            });
         }
+    }
+
+    rows
+}
+
+// (unexpired, unblocked) keys of users w/ access, which don't satisfy the key policy
+// or whose options conflict w/ the serveraccess options
+fn filtered(graph: &AccessGraph) -> Vec<FilteredKey> {
+    let mut seen = BTreeSet::new();
+    let mut res = Vec::new();
+
+    for g in grants(graph) {
+        for key in graph.keys.get(&g.userid).into_iter().flatten() {
+            if key.expired || key.blocked {
+                continue;
+            }
+
+            let Err(reason) = key_policy(g.serveraccess, &key.sshkey)
+                .and_then(|()| merge_options(g.serveraccess, key).map(|_| ()))
+            else {
+                continue;
+            };
+
+            if !seen.insert((g.server.ip, &g.serveraccess.name, key.id)) {
+                continue;
+            }
 
-        // sshoption
-        if let Some(option) = &auth.sshoption {
-            l.push_str(option);
-            l.push(' ');
+            res.push(FilteredKey {
+                servername: g.server.name.clone(),
+                serverip: g.server.ip,
+                sshuser: g.serveraccess.sshuser.clone(),
+                serveraccess: g.serveraccess.name.clone(),
+                email: g.user.email.clone(),
+                keyid: key.id,
+                fingerprint: key.fingerprint.clone(),
+                reason,
+            });
         }
+    }
 
-        // key
-        l.push_str(&auth.sshkey);
-        l.push(' ');
-        l.push_str(&auth.email);
-        if let Some(comment) = &auth.comment {
-            l.push_str(r" (");
-            l.push_str(comment);
-            l.push(')');
+    res.sort_by(|a, b| {
+        (a.serverip, &a.sshuser, &a.email, a.keyid)
+            .cmp(&(b.serverip, &b.sshuser, &b.email, b.keyid))
+    });
+
+    res
+}
+
+// one row per CA & principal, the user group of a group grant is a principal, too
+fn resolve_cas(graph: &AccessGraph) -> Vec<CAQuery> {
+    let mut rows = Vec::new();
+
+    for g in grants(graph) {
+        let Some(caids) = graph
+            .serveraccess_certauthorities
+            .get(&g.useraccess.serveraccess_id)
+        else {
+            continue;
+        };
+
+        let principals = Some(g.user.email.clone())
+            .into_iter()
+            .chain(
+                g.useraccess
+                    .usergroup_id
+                    .and_then(|id| graph.usergroups.get(&id))
+                    .cloned(),
+            )
+            .collect::<Vec<String>>();
+
+        for caid in caids {
+            let Some(ca) = graph.certauthorities.get(caid).filter(|ca| !ca.blocked) else {
+                continue;
+            };
+
+            for principal in &principals {
+                rows.push(CAQuery {
+                    caid: *caid,
+                    host: g.server.ip,
+                    servername: g.server.name.clone(),
+                    serveraccess: g.serveraccess.name.clone(),
+                    useraccess: g.useraccess.id,
+                    sshuser: g.serveraccess.sshuser.clone(),
+                    sshfrom: g.serveraccess.sshfrom.clone(),
+                    sshcommand: g.serveraccess.sshcommand.clone(),
+                    sshoption: sshoption(g.serveraccess),
+                    expiry_time: g.useraccess.expiry_time.clone(),
+                    name: ca.name.clone(),
+                    sshkey: ca.sshkey.clone(),
+                    fingerprint: ca.fingerprint.clone(),
+                    comment: ca.comment.clone(),
+                    principal: principal.clone(),
+                });
+            }
         }
+    }
+
+    rows
+}
 
-        hm.entry((auth.host, auth.sshuser))
-            .or_insert_with(Vec::new)
-            .push(l);
+pub fn generate_all(pgclient: &mut Client) -> Result<Vec<ServerAuth>, Error> {
+    let graph = load(pgclient)?;
+
+    Ok(build(resolve(&graph), resolve_cas(&graph)))
+}
+
+fn generate(pgclient: &mut Client, ip: Option<&str>) -> Vec<ServerAuth> {
+    if update_dns(pgclient).is_err() {
+        exit_with_message("Could not generate list of addresses to resolve.");
     }
 
+    let Ok(graph) = load(pgclient) else {
+        exit_with_message("Could not generate auth list.")
+    };
+
+    let mut rows = resolve(&graph);
+    let mut cas = resolve_cas(&graph);
+
     if let Some(ip) = ip {
-        hm.retain(|k, _| k.0.to_string().contains(ip));
+        rows.retain(|r| r.host.to_string().contains(ip));
+        cas.retain(|r| r.host.to_string().contains(ip));
+    }
+
+    build(rows, cas)
+}
+
+// entries w/ the same id (i.e. the same line) are valid until the latest expiry of their grants
+fn extend_expiry<T, K: Eq + std::hash::Hash>(
+    entries: &mut [T],
+    id: impl Fn(&T) -> K,
+    expiry: impl Fn(&mut T) -> &mut Option<String>,
+) {
+    let mut expiries: HashMap<K, Option<String>> = HashMap::new();
+
+    for entry in entries.iter_mut() {
+        let e = match expiries.remove(&id(entry)) {
+            Some(other) => latest(other, expiry(entry).clone()),
+            None => expiry(entry).clone(),
+        };
+        expiries.insert(id(entry), e);
+    }
+
+    for entry in entries.iter_mut() {
+        *expiry(entry) = expiries[&id(entry)].clone();
+    }
+}
+
+// servername, keys & CAs per (host, sshuser)
+type UserEntries = (String, Vec<AuthorizedKey>, Vec<AuthorizedCA>);
+
+#[allow(clippy::too_many_lines)]
+fn build(mut rows: Vec<AuthQuery>, mut cas: Vec<CAQuery>) -> Vec<ServerAuth> {
+    // one line per key (CA principal) & option set
+    extend_expiry(
+        &mut rows,
+        |r| {
+            (
+                r.host,
+                r.sshuser.clone(),
+                r.keyid,
+                r.sshfrom.clone(),
+                r.sshcommand.clone(),
+                r.sshoption.clone(),
+            )
+        },
+        |r| &mut r.expiry_time,
+    );
+    extend_expiry(
+        &mut cas,
+        |c| {
+            (
+                c.host,
+                c.sshuser.clone(),
+                (c.caid, c.principal.clone()),
+                c.sshfrom.clone(),
+                c.sshcommand.clone(),
+                c.sshoption.clone(),
+            )
+        },
+        |c| &mut c.expiry_time,
+    );
+
+    rows.sort_by(|a, b| {
+        (
+            a.userid,
+            a.keyid,
+            &a.sshuser,
+            &a.sshfrom,
+            &a.sshcommand,
+            &a.sshoption,
+            &a.expiry_time,
+            &a.serveraccess,
+            a.useraccess,
+            a.host,
+        )
+            .cmp(&(
+                b.userid,
+                b.keyid,
+                &b.sshuser,
+                &b.sshfrom,
+                &b.sshcommand,
+                &b.sshoption,
+                &b.expiry_time,
+                &b.serveraccess,
+                b.useraccess,
+                b.host,
+            ))
+    });
+
+    cas.sort_by(|a, b| {
+        (
+            a.caid,
+            &a.sshuser,
+            &a.sshfrom,
+            &a.sshcommand,
+            &a.sshoption,
+            &a.expiry_time,
+            &a.serveraccess,
+            a.useraccess,
+            a.host,
+            &a.principal,
+        )
+            .cmp(&(
+                b.caid,
+                &b.sshuser,
+                &b.sshfrom,
+                &b.sshcommand,
+                &b.sshoption,
+                &b.expiry_time,
+                &b.serveraccess,
+                b.useraccess,
+                b.host,
+                &b.principal,
+            ))
+    });
+
+    let mut hm: BTreeMap<(IpAddr, String), UserEntries> = BTreeMap::new();
+
+    for auth in rows {
+        let options = options(
+            auth.sshfrom.as_deref(),
+            auth.sshcommand.as_deref(),
+            auth.sshoption.as_deref(),
+            auth.expiry_time.as_deref(),
+        );
+        let line = render_line(&options, &auth);
+
+        let (_, keys, _) = hm
+            .entry((auth.host, auth.sshuser.clone()))
+            .or_insert_with(|| (auth.servername.clone(), Vec::new(), Vec::new()));
+
+        let grant = Grant {
+            serveraccess: auth.serveraccess,
+            useraccess: auth.useraccess,
+        };
+
+        if let Some(key) = keys.iter_mut().find(|k| k.line.eq(&line)) {
+            key.granted_by.push(grant);
+        } else {
+            keys.push(AuthorizedKey {
+                keyid: auth.keyid,
+                fingerprint: auth.fingerprint,
+                email: auth.email,
+                options,
+                granted_by: vec![grant],
+                line,
+            });
+        }
+    }
+
+    // one line per CA & option set (w/ the principals of all matching grants)
+    for ca in cas {
+        let options = options(
+            ca.sshfrom.as_deref(),
+            ca.sshcommand.as_deref(),
+            ca.sshoption.as_deref(),
+            ca.expiry_time.as_deref(),
+        );
+
+        let (_, _, authorities) = hm
+            .entry((ca.host, ca.sshuser.clone()))
+            .or_insert_with(|| (ca.servername.clone(), Vec::new(), Vec::new()));
+
+        let grant = Grant {
+            serveraccess: ca.serveraccess,
+            useraccess: ca.useraccess,
+        };
+
+        if let Some(authority) = authorities
+            .iter_mut()
+            .find(|a| a.fingerprint.eq(&ca.fingerprint) && a.options.eq(&options))
+        {
+            if !authority.principals.contains(&ca.principal) {
+                authority.principals.push(ca.principal);
+            }
+            if !authority
+                .granted_by
+                .iter()
+                .any(|g| g.serveraccess.eq(&grant.serveraccess) && g.useraccess == grant.useraccess)
+            {
+                authority.granted_by.push(grant);
+            }
+        } else {
+            authorities.push(AuthorizedCA {
+                name: ca.name,
+                fingerprint: ca.fingerprint,
+                sshkey: ca.sshkey,
+                comment: ca.comment,
+                principals: vec![ca.principal],
+                options,
+                granted_by: vec![grant],
+                line: String::new(),
+            });
+        }
     }
 
-    for (k, v) in hm {
-        let (host, user) = k;
+    let mut serverauth: Vec<ServerAuth> = Vec::new();
+
+    for ((host, user), (servername, keys, authorities)) in hm {
+        let mut cert_authorities = Vec::new();
+        let mut authorized_principals = BTreeSet::new();
+
+        for mut authority in authorities {
+            authority.principals.sort();
+            authority.line = render_ca_line(&authority);
+
+            for principal in &authority.principals {
+                if authority.options.is_empty() {
+                    authorized_principals.insert(principal.clone());
+                } else {
+                    authorized_principals.insert(format!(
+                        "{} {}",
+                        authority.options.join(","),
+                        principal
+                    ));
+                }
+            }
+
+            cert_authorities.push(authority);
+        }
 
         serverauth.push(ServerAuth {
+            servername,
             serverip: host.to_string(),
             sshuser: AuthorizedUser {
-                user: user.to_string(),
-                authorized_keys: AuthorizedKeys { keys: v },
+                user,
+                authorized_keys: AuthorizedKeys {
+                    keys,
+                    cert_authorities,
+                },
+                authorized_principals: authorized_principals.into_iter().collect(),
             },
         });
     }
@@ -239,6 +988,9 @@ pub fn list(
     pgclient: &mut Client,
     ip: Option<&str>,
     servername: Option<&str>,
+    json: bool,
+    ndjson: bool,
+    filtered: bool,
 ) -> Result<(), Error> {
     let query_string = r"SELECT ip FROM server WHERE name = $1";
 
@@ -257,37 +1009,436 @@ pub fn list(
         serverip = ip;
     }
 
+    if filtered {
+        return list_filtered(pgclient, serverip, json || ndjson);
+    }
+
     let serverauth = generate(pgclient, serverip);
 
-    for auth in serverauth {
-        println!("==> {}@{} <==\n", &auth.sshuser.user, &auth.serverip);
-        for key in &auth.sshuser.authorized_keys.keys {
-            println!("{key}");
+    if json {
+        println!("{}", serde_json::to_string(&serverauth).unwrap_or_default());
+    } else if ndjson {
+        for auth in &serverauth {
+            for key in &auth.sshuser.authorized_keys.keys {
+                let record = AuthorizedKeyRecord {
+                    servername: &auth.servername,
+                    serverip: &auth.serverip,
+                    sshuser: &auth.sshuser.user,
+                    key,
+                };
+                println!("{}", serde_json::to_string(&record).unwrap_or_default());
+            }
+            for key in &auth.sshuser.authorized_keys.cert_authorities {
+                let record = AuthorizedKeyRecord {
+                    servername: &auth.servername,
+                    serverip: &auth.serverip,
+                    sshuser: &auth.sshuser.user,
+                    key,
+                };
+                println!("{}", serde_json::to_string(&record).unwrap_or_default());
+            }
+        }
+    } else {
+        for auth in serverauth {
+            println!("==> {}@{} <==\n", &auth.sshuser.user, &auth.serverip);
+            for line in auth.sshuser.authorized_keys.lines() {
+                println!("{line}");
+            }
+            println!();
         }
-        println!();
     }
 
     Ok(())
 }
 
-fn is_hidden(entry: &DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map_or(false, |s| s.starts_with('.'))
-}
+fn list_filtered(pgclient: &mut Client, ip: Option<&str>, json: bool) -> Result<(), Error> {
+    let graph = load(pgclient)?;
 
-pub fn write(pgclient: &mut Client, dir: Option<&OsStr>, force: bool) {
-    let serverauth = crate::serverauth::generate(pgclient, None);
+    let mut res = filtered(&graph);
 
-    let workdir = match dir {
-        Some(dir) => PathBuf::from(dir),
+    if let Some(ip) = ip {
+        res.retain(|r| r.serverip.to_string().contains(ip));
+    }
+
+    if json {
+        println!("{}", serde_json::to_string(&res).unwrap_or_default());
+    } else if !res.is_empty() {
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+        table.set_titles(row![
+            "server",
+            "ip",
+            "ssh user",
+            "serveraccess",
+            "email",
+            "key id",
+            "reason"
+        ]);
+
+        for r in res {
+            table.add_row(row![
+                r.servername,
+                r.serverip,
+                r.sshuser,
+                r.serveraccess,
+                r.email,
+                r.keyid,
+                r.reason
+            ]);
+        }
+
+        table.printstd();
+    }
+
+    Ok(())
+}
+
+// after adding / updating a key: server accesses which won't deploy it
+pub fn warn_filtered(pgclient: &mut Client, keyid: i64) -> Result<(), Error> {
+    let graph = load(pgclient)?;
+    let mut seen = BTreeSet::new();
+
+    for f in filtered(&graph).into_iter().filter(|f| f.keyid == keyid) {
+        if seen.insert((f.serveraccess.clone(), f.reason.clone())) {
+            println!(
+                "{} Key won't be deployed via server access '{}': {}.",
+                "warning:".if_supports_color(Stdout, owo_colors::OwoColorize::yellow),
+                f.serveraccess,
+                f.reason
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// read-only (no DNS updates), for sshd's AuthorizedKeysCommand
+pub fn authorized_keys(
+    pgclient: &mut Client,
+    server: &str,
+    sshuser: &str,
+    fingerprint: Option<&str>,
+) -> Result<(), Error> {
+    let host: IpAddr = if let Ok(ip) = server.parse() {
+        ip
+    } else {
+        let res = pgclient.query(r"SELECT ip FROM server WHERE name = $1", &[&server])?;
+
+        if res.is_empty() {
+            exit_with_message("Server not found.");
+        }
+
+        res[0].get("ip")
+    };
+
+    let graph = load(pgclient)?;
+
+    let mut rows = resolve(&graph);
+    rows.retain(|r| r.host == host && r.sshuser.eq(sshuser));
+
+    let mut cas = resolve_cas(&graph);
+    cas.retain(|r| r.host == host && r.sshuser.eq(sshuser));
+
+    for auth in build(rows, cas) {
+        for key in &auth.sshuser.authorized_keys.keys {
+            if fingerprint.is_none_or(|f| key.fingerprint.eq(f)) {
+                println!("{}", key.line);
+            }
+        }
+
+        // %f is the fingerprint of the certified key, not of the CA
+        for ca in &auth.sshuser.authorized_keys.cert_authorities {
+            println!("{}", ca.line);
+        }
+    }
+
+    Ok(())
+}
+
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry.depth() > 0
+        && entry
+            .file_name()
+            .to_str()
+            .is_some_and(|s| s.starts_with('.'))
+}
+
+// <ip>/<user>/authorized_keys, w/ CAs also <ip>/<user>/authorized_principals
+// and <ip>/trusted_user_ca_keys (for AuthorizedPrincipalsFile & TrustedUserCAKeys)
+fn render_tree(serverauth: &[ServerAuth]) -> BTreeMap<PathBuf, Vec<String>> {
+    let mut tree = BTreeMap::new();
+    let mut trusted: BTreeMap<PathBuf, BTreeSet<String>> = BTreeMap::new();
+
+    for auth in serverauth {
+        let userdir = PathBuf::from(&auth.serverip).join(&auth.sshuser.user);
+
+        tree.insert(
+            userdir.join("authorized_keys"),
+            auth.sshuser.authorized_keys.lines().cloned().collect(),
+        );
+
+        if auth.sshuser.authorized_keys.cert_authorities.is_empty() {
+            continue;
+        }
+
+        tree.insert(
+            userdir.join("authorized_principals"),
+            auth.sshuser.authorized_principals.clone(),
+        );
+
+        trusted
+            .entry(PathBuf::from(&auth.serverip).join("trusted_user_ca_keys"))
+            .or_default()
+            .extend(
+                auth.sshuser
+                    .authorized_keys
+                    .cert_authorities
+                    .iter()
+                    .map(|ca| format!("{} {}", ca.sshkey, ca.name)),
+            );
+    }
+
+    for (path, keys) in trusted {
+        tree.insert(path, keys.into_iter().collect());
+    }
+
+    tree
+}
+
+pub fn read_tree(workdir: &Path) -> Result<BTreeMap<PathBuf, Vec<String>>, String> {
+    let mut tree = BTreeMap::new();
+
+    if !workdir.is_dir() {
+        return Ok(tree);
+    }
+
+    let walker = WalkDir::new(workdir).min_depth(1).into_iter();
+    for entry in walker.filter_entry(|e| !is_hidden(e)).flatten() {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let Ok(contents) = fs::read_to_string(entry.path()) else {
+            return Err(format!("Could not read '{}'.", entry.path().display()));
+        };
+
+        let Ok(path) = entry.path().strip_prefix(workdir) else {
+            return Err("Could not read workdir.".to_string());
+        };
+
+        tree.insert(
+            path.to_path_buf(),
+            contents.lines().map(str::to_string).collect(),
+        );
+    }
+
+    Ok(tree)
+}
+
+fn diff_header(path: &Path, state: &str) {
+    let target = if path.ends_with("authorized_keys") {
+        path.parent().unwrap_or(path)
+    } else {
+        path
+    };
+
+    println!("==> {} ({}) <==\n", target.display(), state);
+}
+
+// removed ('-') and added ('+') lines, in file order (longest common subsequence)
+fn diff_lines<'a>(old: &'a [String], new: &'a [String]) -> Vec<(char, &'a String)> {
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut res = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            res.push(('-', &old[i]));
+            i += 1;
+        } else {
+            res.push(('+', &new[j]));
+            j += 1;
+        }
+    }
+
+    res
+}
+
+pub fn diff(pgclient: &mut Client, dir: Option<&OsStr>) {
+    let workdir = match dir {
+        Some(dir) => PathBuf::from(dir),
+        _ => exit_with_message("Could not diff authorized_keys."),
+    };
+
+    let generated = render_tree(&generate(pgclient, None));
+    let current = read_tree(&workdir).unwrap_or_else(|e| exit_with_message(&e));
+
+    let paths = generated
+        .keys()
+        .chain(current.keys())
+        .collect::<BTreeSet<&PathBuf>>();
+
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+
+    for path in paths {
+        let old = current.get(path);
+        let new = generated.get(path);
+
+        if old == new {
+            continue;
+        }
+
+        let old = old.map(Vec::as_slice).unwrap_or_default();
+        let new = new.map(Vec::as_slice).unwrap_or_default();
+
+        let state = if old.is_empty() && !current.contains_key(path) {
+            added += 1;
+            "added"
+        } else if new.is_empty() && !generated.contains_key(path) {
+            removed += 1;
+            "removed"
+        } else {
+            changed += 1;
+            "changed"
+        };
+
+        diff_header(path, state);
+
+        for (sign, line) in diff_lines(old, new) {
+            if sign == '-' {
+                println!(
+                    "{}",
+                    format!("- {line}").if_supports_color(Stdout, owo_colors::OwoColorize::red)
+                );
+            } else {
+                println!(
+                    "{}",
+                    format!("+ {line}").if_supports_color(Stdout, owo_colors::OwoColorize::green)
+                );
+            }
+        }
+
+        println!();
+    }
+
+    println!("{added} added, {removed} removed, {changed} changed");
+}
+
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+// hidden, so a leftover of an interrupted run is neither read nor committed
+fn temp_path(file: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(file.file_name().unwrap_or_default());
+    name.push(".oerec-tmp");
+
+    file.with_file_name(name)
+}
+
+// every new or changed file is written next to its target & renamed over it, so a crash leaves
+// each file either old or new; unchanged files (mtimes) & hidden entries ('.git') are left alone
+fn update_tree(
+    workdir: &Path,
+    current: &BTreeMap<PathBuf, Vec<String>>,
+    generated: &BTreeMap<PathBuf, Vec<String>>,
+) -> io::Result<()> {
+    fs::create_dir_all(workdir)?;
+
+    let mut dirs = BTreeSet::new();
+
+    for (path, lines) in generated {
+        if current.get(path) == Some(lines) {
+            continue;
+        }
+
+        let file = workdir.join(path);
+        let dir = file.parent().unwrap_or(workdir);
+        fs::create_dir_all(dir)?;
+
+        let temp = temp_path(&file);
+        let mut out = fs::File::create(&temp)?;
+        for line in lines {
+            out.write_all(line.as_bytes())?;
+            out.write_all(b"\n")?;
+        }
+        out.sync_all()?;
+
+        fs::rename(&temp, &file)?;
+
+        dirs.extend(
+            dir.ancestors()
+                .take_while(|d| d.starts_with(workdir))
+                .map(Path::to_path_buf),
+        );
+    }
+
+    for dir in &dirs {
+        sync_dir(dir)?;
+    }
+
+    // stale files last, the new tree is complete by now
+    let mut stale_dirs = BTreeSet::new();
+
+    for path in current.keys().filter(|p| !generated.contains_key(*p)) {
+        let file = workdir.join(path);
+        fs::remove_file(&file)?;
+        stale_dirs.extend(
+            path.ancestors()
+                .skip(1)
+                .filter(|d| !d.as_os_str().is_empty())
+                .map(Path::to_path_buf),
+        );
+    }
+
+    // directories left empty, deepest first (ignores errors, non-empty dirs stay)
+    for dir in stale_dirs.iter().rev() {
+        _ = fs::remove_dir(workdir.join(dir));
+    }
+
+    for dir in stale_dirs
+        .iter()
+        .filter_map(|d| workdir.join(d).parent().map(Path::to_path_buf))
+    {
+        if dir.is_dir() {
+            sync_dir(&dir)?;
+        }
+    }
+
+    sync_dir(workdir)
+}
+
+pub fn write(pgclient: &mut Client, dir: Option<&OsStr>, force: bool, git: bool) {
+    let serverauth = crate::serverauth::generate(pgclient, None);
+
+    let workdir = match dir {
+        Some(dir) => PathBuf::from(dir),
         _ => exit_with_message("Could not write authorized_keys."),
     };
 
+    if workdir.exists() && !workdir.is_dir() {
+        exit_with_message(&format!("'{}' is not a directory.", workdir.display()));
+    }
+
     if workdir.is_dir() && !force {
         print!(
-            "Directory '{}' already exists. Do you want to delete *all* entries in the tree? [y/N]: ",
+            "Directory '{}' already exists. Do you want to replace the tree (hidden entries are kept)? [y/N]: ",
             &workdir.display()
         );
         let mut userinput = String::new();
@@ -299,36 +1450,1056 @@ pub fn write(pgclient: &mut Client, dir: Option<&OsStr>, force: bool) {
         }
     }
 
-    let walker = WalkDir::new(&workdir).min_depth(1).max_depth(1).into_iter();
-    for dir in walker.filter_entry(|e| !is_hidden(e)).flatten() {
-        if remove_dir_all(dir.path()).is_err() {
-            exit_with_message("Could not clean workdir.");
+    let current = match read_tree(&workdir) {
+        Ok(current) => current,
+        Err(e) => exit_with_message(&e),
+    };
+
+    if let Err(e) = update_tree(&workdir, &current, &render_tree(&serverauth)) {
+        exit_with_message(&format!("Could not write authorized_keys: {e}"));
+    }
+
+    if git {
+        let names = serverauth
+            .iter()
+            .map(|a| (a.serverip.clone(), a.servername.clone()))
+            .collect::<BTreeMap<String, String>>();
+
+        git_commit(&workdir, &names);
+    }
+}
+
+fn run_git(workdir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(workdir)
+        .args(args)
+        .output()
+        .ok()?;
+
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        None
+    }
+}
+
+fn git_commit(workdir: &Path, names: &BTreeMap<String, String>) {
+    if !workdir.join(".git").exists() && run_git(workdir, &["init", "--quiet"]).is_none() {
+        exit_with_message("Could not create git repository.");
+    }
+
+    // hidden entries (deploy state, ...) are not part of the history
+    if run_git(workdir, &["add", "--all", "--", ".", ":(exclude).*"]).is_none() {
+        exit_with_message("Could not add authorized_keys to git.");
+    }
+
+    let Some(status) = run_git(workdir, &["diff", "--cached", "--name-status"]) else {
+        exit_with_message("Could not get git status.")
+    };
+
+    if status.trim().is_empty() {
+        println!("Nothing to commit.");
+        return;
+    }
+
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut body = Vec::new();
+
+    for line in status.lines() {
+        let mut fields = line.split('\t');
+        let (Some(code), Some(path)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+
+        let state = match code {
+            "A" => "added",
+            "D" => "removed",
+            _ => "changed",
+        };
+        *counts.entry(state).or_default() += 1;
+
+        let parts = path.split('/').collect::<Vec<&str>>();
+        let (ip, target) = match parts[..] {
+            [ip, user, "authorized_keys"] => (ip, format!("{user}@{ip}")),
+            [ip, user, file] => (ip, format!("{user}@{ip} {file}")),
+            [ip, file] => (ip, format!("{ip} {file}")),
+            _ => continue,
+        };
+
+        match names.get(ip) {
+            Some(name) => body.push(format!("{state:<8} {target} ({name})")),
+            None => body.push(format!("{state:<8} {target}")),
         }
     }
 
-    for auth in serverauth {
-        let outdir = workdir.join(&auth.serverip).join(&auth.sshuser.user);
+    let message = format!(
+        "Update authorized_keys: {}\n\n{}\n\nOperator: {}\n",
+        counts
+            .iter()
+            .map(|(state, n)| format!("{n} {state}"))
+            .collect::<Vec<String>>()
+            .join(", "),
+        body.join("\n"),
+        get_ssh_client()
+    );
+
+    // commits must not fail just because no identity is configured
+    let mut args = Vec::new();
+    if run_git(workdir, &["config", "user.email"]).is_none() {
+        args.extend(["-c", "user.name=oerec", "-c", "user.email=oerec@localhost"]);
+    }
+    args.extend(["commit", "--quiet", "--message", &message]);
 
-        if fs::create_dir_all(&outdir).is_err() {
-            exit_with_message("Could not write authorized_keys.");
+    if run_git(workdir, &args).is_none() {
+        exit_with_message("Could not commit authorized_keys.");
+    }
+
+    info!(
+        "({}) Committed authorized_keys ({})",
+        &get_ssh_client(),
+        counts
+            .iter()
+            .map(|(state, n)| format!("{n} {state}"))
+            .collect::<Vec<String>>()
+            .join(", ")
+    );
+}
+
+pub fn history(
+    pgclient: &mut Client,
+    dir: Option<&OsStr>,
+    server: Option<&str>,
+    sshuser: Option<&str>,
+) -> Result<(), Error> {
+    let Some(workdir) = dir.map(PathBuf::from) else {
+        exit_with_message("Workdir cannot be empty.")
+    };
+
+    let Some(server) = server else {
+        exit_with_message("Server cannot be empty.")
+    };
+
+    let host: IpAddr = if let Ok(ip) = server.parse() {
+        ip
+    } else {
+        let res = pgclient.query(r"SELECT ip FROM server WHERE name = $1", &[&server])?;
+
+        if res.is_empty() {
+            exit_with_message("Server not found.");
         }
 
-        for key in &auth.sshuser.authorized_keys.keys {
-            let Ok(mut file) = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(outdir.join("authorized_keys"))
-            else {
-                exit_with_message("Could not write authorized_keys.")
-            };
+        res[0].get("ip")
+    };
 
-            if file.write(key.as_bytes()).is_err() {
-                exit_with_message("Could not write authorized_keys.");
-            };
+    let mut path = PathBuf::from(host.to_string());
+    if let Some(sshuser) = sshuser {
+        path.push(sshuser);
+    }
+
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(&workdir)
+        .args(["log", "--patch", "--date=iso", "--"])
+        .arg(&path)
+        .status();
+
+    if !status.is_ok_and(|s| s.success()) {
+        exit_with_message("Could not show history (is workdir a git repository?).");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::{find_cycles, CertAuthority};
+
+    // fields of the original SQL-only implementation
+    type BaselineRow = (
+        i64,
+        i64,
+        IpAddr,
+        String,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        String,
+        String,
+        Option<String>,
+    );
+
+    fn baseline(rows: &[AuthQuery]) -> BTreeSet<BaselineRow> {
+        rows.iter()
+            .map(|r| {
+                (
+                    r.userid,
+                    r.keyid,
+                    r.host,
+                    r.sshuser.clone(),
+                    r.sshfrom.clone(),
+                    r.sshcommand.clone(),
+                    r.sshoption.clone(),
+                    r.sshkey.clone(),
+                    r.email.clone(),
+                    r.comment.clone(),
+                )
+            })
+            .collect()
+    }
+
+    // the original SQL-only implementation (query unchanged), used as reference for `resolve`
+    fn query_rows(pgclient: &mut Client) -> BTreeSet<BaselineRow> {
+        let auth_query = r#"SELECT DISTINCT "user".id AS userid,
+                                            sshkeys.id AS keyid,
+                                            server.ip,
+                                            serveraccess.sshuser,
+                                            serveraccess.sshfrom,
+                                            serveraccess.sshcommand,
+                                            serveraccess.sshoption,
+                                            sshkeys.sshkey,
+                                            "user".email,
+                                            SUBSTRING(sshkeys.comment, 1, 64) AS COMMENT
+                            FROM useraccess
+                            LEFT JOIN (useraccess AS ua
+                                       JOIN (usergroup AS ug
+                                             JOIN user_usergroup AS utug ON ug.id = utug.usergroup_id) ON ua.usergroup_id = ug.id
+                                       OR ua.usergroup_id IN
+                                         (WITH RECURSIVE subgroups AS
+                                            (SELECT supergroup_id
+                                             FROM usergroup_usergroup
+                                             WHERE subgroup_id = ug.id
+                                             UNION SELECT u.supergroup_id
+                                             FROM usergroup_usergroup u
+                                             INNER JOIN subgroups x ON x.supergroup_id = u.subgroup_id) SELECT DISTINCT supergroup_id
+                                          FROM subgroups)) ON useraccess.id = ua.id
+                            JOIN "user" ON utug.user_id = "user".id
+                            OR useraccess.user_id = "user".id
+                            JOIN serveraccess
+                            LEFT JOIN (serveraccess AS sa
+                                       JOIN (servergroup AS sg
+                                             JOIN server_servergroup AS stsg ON sg.id = stsg.servergroup_id) ON sa.servergroup_id = sg.id
+                                       OR sa.servergroup_id IN
+                                         (WITH RECURSIVE subgroups AS
+                                            (SELECT supergroup_id
+                                             FROM servergroup_servergroup
+                                             WHERE subgroup_id = sg.id
+                                             UNION SELECT s.supergroup_id
+                                             FROM servergroup_servergroup s
+                                             JOIN subgroups x ON x.supergroup_id = s.subgroup_id) SELECT DISTINCT supergroup_id
+                                          FROM subgroups)) ON serveraccess.id = sa.id ON useraccess.serveraccess_id = serveraccess.id
+                            JOIN server ON stsg.server_id = server.id
+                            OR serveraccess.server_id = server.id
+                            JOIN sshkeys ON "user".id = sshkeys.user_id
+                            WHERE NOT "user".disabled
+                              AND useraccess.best_before > NOW()
+                              AND NOT server.disabled
+                            ORDER BY "user".id,
+                                     sshkeys.id"#;
+
+        let Ok(res) = pgclient.query(auth_query, &[]) else {
+            panic!("could not query auth list")
+        };
+
+        res.iter()
+            .map(|row| {
+                (
+                    row.get("userid"),
+                    row.get("keyid"),
+                    row.get("ip"),
+                    row.get("sshuser"),
+                    row.get("sshfrom"),
+                    row.get("sshcommand"),
+                    row.get("sshoption"),
+                    row.get("sshkey"),
+                    row.get("email"),
+                    row.get("comment"),
+                )
+            })
+            .collect()
+    }
+
+    fn user(email: &str, disabled: bool) -> User {
+        User {
+            email: email.to_string(),
+            disabled,
+        }
+    }
+
+    fn server(name: &str, ip: &str, disabled: bool) -> Server {
+        Server {
+            name: name.to_string(),
+            ip: ip.parse().unwrap(),
+            disabled,
+        }
+    }
+
+    fn key(id: i64) -> Key {
+        Key {
+            id,
+            sshkey: format!("ssh-ed25519 AAAAkey{id}"),
+            fingerprint: format!("SHA256:key{id}"),
+            comment: None,
+            sshfrom: None,
+            sshcommand: None,
+            sshoption: None,
+            expiry_time: None,
+            expired: false,
+            blocked: false,
+        }
+    }
+
+    fn serveraccess(
+        name: &str,
+        server_id: Option<i64>,
+        servergroup_id: Option<i64>,
+    ) -> ServerAccess {
+        ServerAccess {
+            name: name.to_string(),
+            sshuser: "root".to_string(),
+            sshfrom: None,
+            sshcommand: None,
+            sshoption: None,
+            server_id,
+            servergroup_id,
+            keytypes: None,
+            verify_required: false,
+        }
+    }
+
+    fn useraccess(
+        id: i64,
+        user_id: Option<i64>,
+        usergroup_id: Option<i64>,
+        serveraccess_id: i64,
+    ) -> UserAccess {
+        UserAccess {
+            id,
+            user_id,
+            usergroup_id,
+            serveraccess_id,
+            until: "2256-05-11 00:00:00".to_string(),
+            expiry_time: None,
+            expired: false,
+        }
+    }
+
+    // alice (1) in devs (1) -> ops (2), bob (2) in ops (2)
+    // web01 (1) in web (1) -> all (2), db01 (2) in all (2)
+    fn graph() -> AccessGraph {
+        let mut graph = AccessGraph::default();
+
+        graph.users.insert(1, user("alice@x", false));
+        graph.users.insert(2, user("bob@x", false));
+        graph.keys.insert(1, vec![key(1)]);
+        graph.keys.insert(2, vec![key(2)]);
+        graph.user_usergroups.insert(1, vec![1]);
+        graph.user_usergroups.insert(2, vec![2]);
+        graph.usergroup_parents.insert(1, vec![2]);
+
+        graph.servers.insert(1, server("web01", "10.0.0.1", false));
+        graph.servers.insert(2, server("db01", "10.0.0.2", false));
+        graph.server_servergroups.insert(1, vec![1]);
+        graph.server_servergroups.insert(2, vec![2]);
+        graph.servergroup_parents.insert(1, vec![2]);
+
+        graph
+    }
+
+    fn hosts(rows: &[AuthQuery]) -> Vec<(i64, String)> {
+        let mut hosts = rows
+            .iter()
+            .map(|r| (r.userid, r.servername.clone()))
+            .collect::<Vec<(i64, String)>>();
+        hosts.sort();
+        hosts
+    }
+
+    #[test]
+    fn diff_lines_keeps_order_and_duplicates() {
+        let lines = |l: &[&str]| l.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+
+        let old = lines(&["a", "b", "c"]);
+        let new = lines(&["a", "b", "b", "c"]);
+        assert_eq!(diff_lines(&old, &new), vec![('+', &new[2])]);
+        assert_eq!(diff_lines(&new, &old), vec![('-', &new[2])]);
+
+        let new = lines(&["c", "a", "b"]);
+        assert_eq!(diff_lines(&old, &new), vec![('+', &new[0]), ('-', &old[2])]);
+
+        assert!(diff_lines(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn expand_follows_nested_groups() {
+        let parents = HashMap::from([(1, vec![2]), (2, vec![3, 4]), (4, vec![5])]);
+        let mut memo = HashMap::new();
+
+        assert_eq!(expand(2, &parents, &mut memo), BTreeSet::from([2, 3, 4, 5]));
+        assert_eq!(
+            expand(1, &parents, &mut memo),
+            BTreeSet::from([1, 2, 3, 4, 5])
+        );
+        assert_eq!(expand(3, &parents, &mut memo), BTreeSet::from([3]));
+    }
+
+    #[test]
+    fn expand_terminates_on_cycles() {
+        let parents = HashMap::from([(1, vec![2]), (2, vec![1])]);
+        let mut memo = HashMap::new();
+
+        assert_eq!(expand(1, &parents, &mut memo), BTreeSet::from([1, 2]));
+        assert_eq!(expand(2, &parents, &mut memo), BTreeSet::from([1, 2]));
+    }
+
+    #[test]
+    fn find_cycles_reports_usergroup_cycles() {
+        let mut graph = graph();
+        assert!(find_cycles(&graph.usergroup_parents).is_empty());
+
+        // 3 -> 4 -> 5 -> 3, 1 -> 2 stays acyclic
+        graph.usergroup_parents.insert(3, vec![4]);
+        graph.usergroup_parents.insert(4, vec![5]);
+        graph.usergroup_parents.insert(5, vec![3]);
+        assert_eq!(find_cycles(&graph.usergroup_parents), vec![vec![3, 4, 5]]);
+
+        // two cycles sharing group 2
+        graph.usergroup_parents.insert(2, vec![1, 6]);
+        graph.usergroup_parents.insert(6, vec![2]);
+        assert_eq!(
+            find_cycles(&graph.usergroup_parents),
+            vec![vec![1, 2], vec![2, 6], vec![3, 4, 5]]
+        );
+    }
+
+    #[test]
+    fn find_cycles_reports_servergroup_cycles() {
+        let mut graph = graph();
+        assert!(find_cycles(&graph.servergroup_parents).is_empty());
+
+        // entered via 4, reported from the smallest id
+        graph.servergroup_parents.insert(4, vec![5]);
+        graph.servergroup_parents.insert(5, vec![6]);
+        graph.servergroup_parents.insert(6, vec![3]);
+        graph.servergroup_parents.insert(3, vec![5]);
+        assert_eq!(find_cycles(&graph.servergroup_parents), vec![vec![3, 5, 6]]);
+    }
+
+    #[test]
+    fn find_cycles_reports_self_loops() {
+        let mut graph = graph();
+        graph.usergroup_parents.insert(2, vec![2]);
+        graph.servergroup_parents.insert(7, vec![7, 1]);
+
+        assert_eq!(find_cycles(&graph.usergroup_parents), vec![vec![2]]);
+        assert_eq!(find_cycles(&graph.servergroup_parents), vec![vec![7]]);
+    }
+
+    #[test]
+    fn resolve_expands_user_and_server_groups() {
+        let mut graph = graph();
+        graph
+            .serveraccess
+            .insert(1, serveraccess("all-root", None, Some(2)));
+        graph.useraccess.push(useraccess(1, None, Some(2), 1));
+
+        assert_eq!(
+            hosts(&resolve(&graph)),
+            vec![
+                (1, "db01".to_string()),
+                (1, "web01".to_string()),
+                (2, "db01".to_string()),
+                (2, "web01".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_does_not_expand_downwards() {
+        let mut graph = graph();
+        graph
+            .serveraccess
+            .insert(1, serveraccess("web-root", None, Some(1)));
+        graph.useraccess.push(useraccess(1, None, Some(1), 1));
+
+        assert_eq!(hosts(&resolve(&graph)), vec![(1, "web01".to_string())]);
+    }
+
+    #[test]
+    fn resolve_deduplicates_paths() {
+        let mut graph = graph();
+        // alice is in ops directly and via devs
+        graph.user_usergroups.insert(1, vec![1, 2]);
+        graph
+            .serveraccess
+            .insert(1, serveraccess("web01-root", Some(1), Some(1)));
+        graph.useraccess.push(useraccess(1, Some(1), Some(2), 1));
+
+        assert_eq!(
+            hosts(&resolve(&graph)),
+            vec![(1, "web01".to_string()), (2, "web01".to_string())]
+        );
+    }
+
+    #[test]
+    fn resolve_skips_disabled_and_expired() {
+        let mut graph = graph();
+        graph.users.insert(2, user("bob@x", true));
+        graph.servers.insert(2, server("db01", "10.0.0.2", true));
+        graph
+            .serveraccess
+            .insert(1, serveraccess("all-root", None, Some(2)));
+        graph
+            .serveraccess
+            .insert(2, serveraccess("web01-root", Some(1), None));
+        graph.useraccess.push(useraccess(1, None, Some(2), 1));
+        graph.useraccess.push(useraccess(2, Some(1), None, 2));
+        graph.useraccess[1].expired = true;
+
+        let rows = resolve(&graph);
+        assert_eq!(hosts(&rows), vec![(1, "web01".to_string())]);
+        assert_eq!(rows[0].serveraccess, "all-root");
+    }
+
+    #[test]
+    fn build_adds_cert_authorities() {
+        let mut graph = graph();
+        graph.usergroups.insert(2, "ops".to_string());
+        graph.certauthorities.insert(
+            1,
+            CertAuthority {
+                name: "users-ca".to_string(),
+                sshkey: "ssh-ed25519 AAAAca".to_string(),
+                fingerprint: "SHA256:ca".to_string(),
+                comment: None,
+                blocked: false,
+            },
+        );
+        graph
+            .serveraccess
+            .insert(1, serveraccess("web01-root", Some(1), None));
+        graph.serveraccess_certauthorities.insert(1, vec![1]);
+        graph.useraccess.push(useraccess(1, None, Some(2), 1));
+
+        let serverauth = build(resolve(&graph), resolve_cas(&graph));
+        assert_eq!(serverauth.len(), 1);
+
+        let sshuser = &serverauth[0].sshuser;
+        let cas = &sshuser.authorized_keys.cert_authorities;
+        assert_eq!(cas.len(), 1);
+        assert_eq!(cas[0].granted_by.len(), 1);
+        assert_eq!(
+            cas[0].line,
+            r#"cert-authority,principals="alice@x,bob@x,ops" ssh-ed25519 AAAAca users-ca"#
+        );
+        assert_eq!(
+            sshuser.authorized_principals,
+            vec!["alice@x", "bob@x", "ops"]
+        );
+        assert_eq!(sshuser.authorized_keys.lines().count(), 3);
+
+        assert_eq!(
+            render_tree(&serverauth)
+                .keys()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<String>>(),
+            vec![
+                "10.0.0.1/root/authorized_keys",
+                "10.0.0.1/root/authorized_principals",
+                "10.0.0.1/trusted_user_ca_keys",
+            ]
+        );
+    }
 
-            if file.write_all("\n".as_bytes()).is_err() {
-                exit_with_message("Could not write authorized_keys.");
+    #[test]
+    fn build_uses_latest_expiry_time() {
+        let mut graph = graph();
+        graph
+            .serveraccess
+            .insert(1, serveraccess("web01-root", Some(1), None));
+        graph.serveraccess.insert(
+            2,
+            ServerAccess {
+                sshuser: "deploy".to_string(),
+                ..serveraccess("web01-deploy", Some(1), None)
+            },
+        );
+
+        // alice: two time-limited grants for root, a time-limited & a group grant for deploy
+        for (id, user, group, sa, expiry_time) in [
+            (1, Some(1), None, 1, Some("202701010000")),
+            (2, Some(1), None, 1, Some("202801010000")),
+            (3, Some(1), None, 2, Some("202701010000")),
+            (4, None, Some(2), 2, None),
+        ] {
+            graph.useraccess.push(UserAccess {
+                expiry_time: expiry_time.map(str::to_string),
+                ..useraccess(id, user, group, sa)
+            });
+        }
+
+        // the key's own expiry-time applies, if earlier
+        graph.keys.get_mut(&2).unwrap()[0].expiry_time = Some("202612312359".to_string());
+
+        let lines = build(resolve(&graph), Vec::new())
+            .iter()
+            .flat_map(|a| {
+                a.sshuser
+                    .authorized_keys
+                    .lines()
+                    .cloned()
+                    .collect::<Vec<String>>()
+            })
+            .collect::<Vec<String>>();
+
+        assert_eq!(
+            lines,
+            vec![
+                "ssh-ed25519 AAAAkey1 alice@x",
+                r#"expiry-time="202612312359" ssh-ed25519 AAAAkey2 bob@x"#,
+                r#"expiry-time="202801010000" ssh-ed25519 AAAAkey1 alice@x"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_skips_expired_keys() {
+        let mut graph = graph();
+        graph
+            .serveraccess
+            .insert(1, serveraccess("web01-root", Some(1), None));
+        graph.useraccess.push(useraccess(1, Some(1), None, 1));
+        graph.keys.get_mut(&1).unwrap().push(Key {
+            expired: true,
+            ..key(2)
+        });
+
+        let rows = resolve(&graph);
+        assert_eq!(rows.iter().map(|r| r.keyid).collect::<Vec<i64>>(), vec![1]);
+    }
+
+    #[test]
+    fn resolve_applies_key_policy() {
+        let mut graph = graph();
+        graph.serveraccess.insert(
+            1,
+            ServerAccess {
+                keytypes: Some("sk".to_string()),
+                verify_required: true,
+                ..serveraccess("web01-root", Some(1), None)
+            },
+        );
+        graph.useraccess.push(useraccess(1, Some(1), None, 1));
+        graph.keys.get_mut(&1).unwrap().push(Key {
+            sshkey: "sk-ssh-ed25519@openssh.com AAAAsk3".to_string(),
+            ..key(3)
+        });
+
+        let rows = resolve(&graph);
+        assert_eq!(rows.iter().map(|r| r.keyid).collect::<Vec<i64>>(), vec![3]);
+        assert_eq!(rows[0].sshoption.as_deref(), Some("verify-required"));
+
+        let filtered = filtered(&graph);
+        assert_eq!(
+            filtered
+                .iter()
+                .map(|f| (f.keyid, f.reason.as_str()))
+                .collect::<Vec<(i64, &str)>>(),
+            vec![(1, "key type 'ssh-ed25519' not allowed (sk)")]
+        );
+    }
+
+    #[test]
+    fn resolve_skips_blocked_keys() {
+        let mut graph = graph();
+        graph
+            .serveraccess
+            .insert(1, serveraccess("web01-root", Some(1), None));
+        graph.useraccess.push(useraccess(1, Some(1), None, 1));
+        graph.keys.get_mut(&1).unwrap().push(Key {
+            blocked: true,
+            ..key(2)
+        });
+
+        let rows = resolve(&graph);
+        assert_eq!(rows.iter().map(|r| r.keyid).collect::<Vec<i64>>(), vec![1]);
+    }
+
+    #[test]
+    fn merge_options_intersects_restrictions() {
+        let sa = ServerAccess {
+            sshfrom: Some("10.0.0.0/8, *.example.com, !10.9.9.9".to_string()),
+            sshoption: Some("no-pty".to_string()),
+            ..serveraccess("web01-deploy", Some(1), None)
+        };
+        let ci = Key {
+            sshfrom: Some("10.1.0.0/16,192.168.0.0/16,*.example.com".to_string()),
+            sshcommand: Some("/usr/bin/deploy".to_string()),
+            sshoption: Some("restrict,port-forwarding,no-pty".to_string()),
+            expiry_time: Some("202701010000".to_string()),
+            ..key(2)
+        };
+
+        assert_eq!(
+            merge_options(&sa, &ci),
+            Ok(KeyOptions {
+                sshfrom: Some("10.1.0.0/16,*.example.com,!10.9.9.9".to_string()),
+                sshcommand: Some("/usr/bin/deploy".to_string()),
+                sshoption: Some("restrict,no-pty,port-forwarding".to_string()),
+                expiry_time: Some("202701010000".to_string()),
+            })
+        );
+
+        // w/o key restrictions, the serveraccess options are used as is
+        assert_eq!(
+            merge_options(&sa, &key(1)).map(|o| o.sshfrom),
+            Ok(sa.sshfrom.clone())
+        );
+
+        let conflicts = [
+            Key {
+                sshfrom: Some("192.168.0.0/16".to_string()),
+                ..key(3)
+            },
+            Key {
+                sshoption: Some("pty".to_string()),
+                ..key(3)
+            },
+        ];
+        assert_eq!(
+            conflicts
+                .iter()
+                .map(|k| merge_options(&sa, k).unwrap_err())
+                .collect::<Vec<String>>(),
+            vec![
+                "from= lists don't overlap (serveraccess: '10.0.0.0/8,*.example.com,!10.9.9.9', key: '192.168.0.0/16')",
+                "conflicting options (serveraccess: 'no-pty', key: 'pty')",
+            ]
+        );
+
+        let sa = ServerAccess {
+            sshcommand: Some("/usr/bin/backup".to_string()),
+            ..serveraccess("web01-backup", Some(1), None)
+        };
+        assert!(merge_options(&sa, &ci).is_err());
+    }
+
+    #[test]
+    fn resolve_merges_key_options() {
+        let mut graph = graph();
+        graph.serveraccess.insert(
+            1,
+            ServerAccess {
+                sshfrom: Some("10.0.0.0/8".to_string()),
+                ..serveraccess("web01-root", Some(1), None)
+            },
+        );
+        graph.useraccess.push(useraccess(1, Some(1), None, 1));
+        graph.keys.get_mut(&1).unwrap().push(Key {
+            sshfrom: Some("10.1.0.0/16".to_string()),
+            expiry_time: Some("202701010000".to_string()),
+            ..key(2)
+        });
+        graph.keys.get_mut(&1).unwrap().push(Key {
+            sshfrom: Some("192.168.0.0/16".to_string()),
+            ..key(3)
+        });
+
+        let auth = build(resolve(&graph), Vec::new());
+        assert_eq!(
+            auth[0]
+                .sshuser
+                .authorized_keys
+                .keys
+                .iter()
+                .map(|k| k.options.join(","))
+                .collect::<Vec<String>>(),
+            vec![
+                r#"from="10.0.0.0/8""#,
+                r#"from="10.1.0.0/16",expiry-time="202701010000""#,
+            ]
+        );
+
+        assert_eq!(
+            filtered(&graph)
+                .iter()
+                .map(|f| f.keyid)
+                .collect::<Vec<i64>>(),
+            vec![3]
+        );
+    }
+
+    // simple LCG, good enough for generating fixtures
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, n: usize) -> usize {
+            self.0 = self
+                .0
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            usize::try_from(self.0 >> 33).unwrap() % n
+        }
+
+        fn chance(&mut self, percent: usize) -> bool {
+            self.next(100) < percent
+        }
+    }
+
+    fn insert(
+        pgclient: &mut Client,
+        query: &str,
+        params: &[&(dyn postgres::types::ToSql + Sync)],
+    ) -> i64 {
+        pgclient
+            .query_one(&format!("{query} RETURNING id"), params)
+            .unwrap()
+            .get("id")
+    }
+
+    // nested groups only link to "later" groups to keep them acyclic
+    fn link(
+        pgclient: &mut Client,
+        rng: &mut Lcg,
+        table: &str,
+        columns: &str,
+        from: &[i64],
+        to: &[i64],
+        nested: bool,
+    ) {
+        for (i, a) in from.iter().enumerate() {
+            let start = if nested { i + 1 } else { 0 };
+            if start >= to.len() {
+                continue;
+            }
+
+            for _ in 0..rng.next(3) {
+                let b = to[start + rng.next(to.len() - start)];
+                pgclient
+                    .execute(
+                        &format!("INSERT INTO {table} ({columns}) VALUES ($1, $2) ON CONFLICT DO NOTHING"),
+                        &[a, &b],
+                    )
+                    .unwrap();
             }
         }
     }
+
+    #[allow(clippy::too_many_lines)]
+    fn insert_fixtures(pgclient: &mut Client, seed: u64) {
+        let mut rng = Lcg(seed);
+
+        let mut users = Vec::new();
+        let mut keys = 0;
+        for u in 0..40 {
+            let disabled = rng.chance(10);
+            let id = insert(
+                pgclient,
+                r#"INSERT INTO "user" (email, name, disabled) VALUES ($1, $2, $3)"#,
+                &[
+                    &format!("fixture{u}@example.com"),
+                    &format!("fixture{u}"),
+                    &disabled,
+                ],
+            );
+            users.push(id);
+
+            for _ in 0..rng.next(4) {
+                keys += 1;
+                let comment = match rng.next(3) {
+                    0 => None,
+                    1 => Some(format!("key {keys}")),
+                    _ => Some("x".repeat(100)),
+                };
+                // no expired keys, the reference query doesn't know about key expiry
+                let best_before = rng.chance(20).then_some("2256-05-11");
+                insert(
+                    pgclient,
+                    r"INSERT INTO sshkeys (user_id, sshkey, fingerprint, comment, best_before)
+                      VALUES ($1, $2, $3, $4, CAST($5::TEXT AS TIMESTAMP))",
+                    &[
+                        &id,
+                        &format!("ssh-ed25519 AAAAfixture{keys}"),
+                        &format!("SHA256:fixture{keys}"),
+                        &comment,
+                        &best_before,
+                    ],
+                );
+            }
+        }
+
+        let mut usergroups = Vec::new();
+        for g in 0..15 {
+            usergroups.push(insert(
+                pgclient,
+                r"INSERT INTO usergroup (name) VALUES ($1)",
+                &[&format!("fixture-ug{g}")],
+            ));
+        }
+
+        let mut servers = Vec::new();
+        for s in 0..30 {
+            let disabled = rng.chance(10);
+            let ip: IpAddr = format!("10.99.0.{}", s + 1).parse().unwrap();
+            servers.push(insert(
+                pgclient,
+                r"INSERT INTO server (name, ip, disabled) VALUES ($1, $2, $3)",
+                &[&format!("fixture-srv{s}"), &ip, &disabled],
+            ));
+        }
+
+        let mut servergroups = Vec::new();
+        for g in 0..10 {
+            servergroups.push(insert(
+                pgclient,
+                r"INSERT INTO servergroup (name) VALUES ($1)",
+                &[&format!("fixture-sg{g}")],
+            ));
+        }
+
+        let mut serveraccess = Vec::new();
+        for a in 0..25 {
+            let sshuser = ["root", "deploy", "admin"][rng.next(3)];
+            let sshfrom = rng.chance(30).then_some("10.0.0.0/8");
+            let sshoption = rng.chance(30).then_some("no-pty,no-port-forwarding");
+            let server_id = rng.chance(50).then(|| servers[rng.next(servers.len())]);
+            let servergroup_id = (server_id.is_none() || rng.chance(10))
+                .then(|| servergroups[rng.next(servergroups.len())]);
+            serveraccess.push(insert(
+                pgclient,
+                r"INSERT INTO serveraccess (name, sshuser, sshfrom, sshoption, server_id, servergroup_id)
+                  VALUES ($1, $2, $3, $4, $5, $6)",
+                &[&format!("fixture-sa{a}"), &sshuser, &sshfrom, &sshoption, &server_id, &servergroup_id],
+            ));
+        }
+
+        // CAs (the first one blocked) only show up in load, not in the reference
+        for c in 0..3 {
+            let ca = insert(
+                pgclient,
+                r"INSERT INTO certauthority (name, sshkey, fingerprint) VALUES ($1, $2, $3)",
+                &[
+                    &format!("fixture-ca{c}"),
+                    &format!("ssh-ed25519 AAAAfixtureca{c}"),
+                    &format!("SHA256:fixtureca{c}"),
+                ],
+            );
+            let serveraccess_id = serveraccess[rng.next(serveraccess.len())];
+            pgclient
+                .execute(
+                    r"INSERT INTO serveraccess_certauthority (serveraccess_id, certauthority_id) VALUES ($1, $2)",
+                    &[&serveraccess_id, &ca],
+                )
+                .unwrap();
+        }
+        pgclient
+            .execute(
+                r"INSERT INTO blocked_keys (fingerprint) VALUES ('SHA256:fixtureca0')",
+                &[],
+            )
+            .unwrap();
+
+        link(
+            pgclient,
+            &mut rng,
+            "user_usergroup",
+            "user_id, usergroup_id",
+            &users,
+            &usergroups,
+            false,
+        );
+        link(
+            pgclient,
+            &mut rng,
+            "usergroup_usergroup",
+            "subgroup_id, supergroup_id",
+            &usergroups,
+            &usergroups,
+            true,
+        );
+        link(
+            pgclient,
+            &mut rng,
+            "server_servergroup",
+            "server_id, servergroup_id",
+            &servers,
+            &servergroups,
+            false,
+        );
+        link(
+            pgclient,
+            &mut rng,
+            "servergroup_servergroup",
+            "subgroup_id, supergroup_id",
+            &servergroups,
+            &servergroups,
+            true,
+        );
+
+        for _ in 0..60 {
+            let user_id = rng.chance(40).then(|| users[rng.next(users.len())]);
+            let usergroup_id = (user_id.is_none() || rng.chance(10))
+                .then(|| usergroups[rng.next(usergroups.len())]);
+            let serveraccess_id = serveraccess[rng.next(serveraccess.len())];
+            let expired = rng.chance(15);
+            let limited = rng.chance(20);
+            pgclient
+                .execute(
+                    r"INSERT INTO useraccess (user_id, usergroup_id, serveraccess_id, best_before)
+                      VALUES ($1, $2, $3, CASE WHEN $4 THEN NOW() - INTERVAL '1 day'
+                                               WHEN $5 THEN '2030-01-01'
+                                               ELSE '2256-05-11' END)",
+                    &[
+                        &user_id,
+                        &usergroup_id,
+                        &serveraccess_id,
+                        &expired,
+                        &limited,
+                    ],
+                )
+                .unwrap();
+        }
+    }
+
+    // needs a database w/ the oere schema, e.g.:
+    // OEREC_TEST_DB="host=/tmp user=oerec dbname=oere" cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn resolve_matches_sql() {
+        let Ok(params) = std::env::var("OEREC_TEST_DB") else {
+            panic!("OEREC_TEST_DB not set");
+        };
+        let mut pgclient = Client::connect(&params, postgres::NoTls).unwrap();
+
+        for seed in [1, 42, 1337] {
+            pgclient.batch_execute("BEGIN").unwrap();
+            insert_fixtures(&mut pgclient, seed);
+
+            let sql = query_rows(&mut pgclient);
+            let graph = load(&mut pgclient).unwrap();
+            let resolved = baseline(&resolve(&graph));
+
+            let blocked = graph
+                .certauthorities
+                .values()
+                .filter(|ca| ca.name.starts_with("fixture-ca"))
+                .map(|ca| (ca.name.as_str(), ca.blocked))
+                .collect::<BTreeSet<(&str, bool)>>();
+
+            pgclient.batch_execute("ROLLBACK").unwrap();
+
+            assert!(!sql.is_empty(), "no auth generated for seed {seed}");
+            assert_eq!(
+                blocked,
+                BTreeSet::from([
+                    ("fixture-ca0", true),
+                    ("fixture-ca1", false),
+                    ("fixture-ca2", false)
+                ]),
+                "seed {seed}"
+            );
+            assert_eq!(sql, resolved, "seed {seed}");
+        }
+    }
 }
@@ -28,7 +28,7 @@ pub fn add(
 
     let newname = ask_for(&ListObject::ServerGroup, servergroup, None, pgclient);
 
-    if newname.eq("") {
+    if newname.is_empty() {
         exit_with_message("Server group name cannot be empty.");
     }
 
@@ -95,7 +95,7 @@ pub fn add_to_servergroup(
         pgclient,
     );
 
-    if newsubgroupname.eq("") {
+    if newsubgroupname.is_empty() {
         exit_with_message("Group name cannot be empty.");
     }
 
@@ -115,7 +115,7 @@ pub fn add_to_servergroup(
         pgclient,
     );
 
-    if newsupergroupname.eq("") {
+    if newsupergroupname.is_empty() {
         exit_with_message("Group name cannot be empty.");
     }
 
@@ -128,6 +128,28 @@ pub fn add_to_servergroup(
         exit_with_message("Server group not found.");
     }
 
+    // parent must not be (a member of) the new member group
+    let res = pgclient.query(
+        r"WITH RECURSIVE supergroups AS
+            (SELECT id
+             FROM servergroup
+             WHERE name = $1
+             UNION SELECT g.supergroup_id
+             FROM servergroup_servergroup g
+             JOIN supergroups x ON x.id = g.subgroup_id)
+          SELECT servergroup.id
+          FROM supergroups
+          JOIN servergroup ON servergroup.id = supergroups.id
+          WHERE servergroup.name = $2",
+        &[&newsupergroupname, &newsubgroupname],
+    )?;
+
+    if !res.is_empty() {
+        exit_with_message(&format!(
+            "Cannot add server group '{newsubgroupname}' to '{newsupergroupname}' (would create a cycle)."
+        ));
+    }
+
     let res = pgclient.query(
         r"SELECT subgroup_id, supergroup_id
            FROM servergroup_servergroup
@@ -183,7 +205,7 @@ pub fn delete(pgclient: &mut Client, servergroup: Option<&str>, force: bool) ->
 
     let oldservergroup = ask_for(&ListObject::ServerGroup, servergroup, None, pgclient);
 
-    if oldservergroup.eq("") {
+    if oldservergroup.is_empty() {
         exit_with_message("Server group cannot be empty.");
     }
 
@@ -255,7 +277,7 @@ pub fn delete_from_servergroup(
         pgclient,
     );
 
-    if oldsubgroupname.eq("") {
+    if oldsubgroupname.is_empty() {
         exit_with_message("Group name cannot be empty.");
     }
 
@@ -275,7 +297,7 @@ pub fn delete_from_servergroup(
         pgclient,
     );
 
-    if oldsupergroupname.eq("") {
+    if oldsupergroupname.is_empty() {
         exit_with_message("Group name cannot be empty.");
     }
 
@@ -496,7 +518,7 @@ pub fn update(
 
     let servergroupname = ask_for(&ListObject::ServerGroup, servergroup, None, pgclient);
 
-    if servergroupname.eq("") {
+    if servergroupname.is_empty() {
         exit_with_message("Server group name cannot be empty.");
     }
 
@@ -523,7 +545,7 @@ pub fn update(
         pgclient,
     );
 
-    if newservergroupname.to_lowercase().eq("") {
+    if newservergroupname.to_lowercase().is_empty() {
         newservergroupname.clone_from(&oldservergroupname);
     } else if !pgclient
         .query(
@@ -546,7 +568,7 @@ pub fn update(
         ),
     );
 
-    let newcommentopt = if newcomment.to_lowercase().eq("") {
+    let newcommentopt = if newcomment.to_lowercase().is_empty() {
         oldservergroupcomment
     } else if newcomment.trim().to_lowercase().eq("null") {
         None
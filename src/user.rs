@@ -7,6 +7,7 @@ use serde_derive::Serialize;
 use std::io::{self, Write};
 
 use crate::logging::get_ssh_client;
+use crate::metadata::{self, Metadata};
 use crate::{ask_for, exit_with_message, key, set_or_ask_for, user, ListObject};
 
 #[derive(Debug, ToSql, FromSql)]
@@ -28,6 +29,8 @@ struct UserQuery {
     pub usertype: String,
     pub disabled: String,
     pub comment: Option<String>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
 }
 
 pub fn add(
@@ -38,12 +41,12 @@ pub fn add(
     comment: Option<&str>,
 ) -> Result<(), Error> {
     println!("Add user");
-    let query_string = r#"INSERT INTO "user" (email, name, type, comment)
-                          VALUES ($1, $2, $3, $4)"#;
+    let query_string = r#"INSERT INTO "user" (email, name, type, comment, created_by)
+                          VALUES ($1, $2, $3, $4, $5)"#;
 
     let newemail = ask_for(&ListObject::UserEmail, email, None, pgclient);
 
-    if newemail.eq("") {
+    if newemail.is_empty() {
         exit_with_message("User email cannot be empty.");
     }
 
@@ -58,7 +61,7 @@ pub fn add(
 
     let newname = set_or_ask_for(username, "Name");
 
-    if newname.eq("") {
+    if newname.is_empty() {
         exit_with_message("User name cannot be empty.");
     }
 
@@ -86,7 +89,16 @@ pub fn add(
         _ => Some(newcomment),
     };
 
-    pgclient.query(query_string, &[&newemail, &newname, &newutype, &newcomment])?;
+    pgclient.query(
+        query_string,
+        &[
+            &newemail,
+            &newname,
+            &newutype,
+            &newcomment,
+            &get_ssh_client(),
+        ],
+    )?;
 
     info!(
         "({}) Added user '{}' ('{}')",
@@ -106,7 +118,16 @@ pub fn add(
     io::stdout().flush().unwrap();
     io::stdin().read_line(&mut userinput).unwrap();
     if !userinput.trim().to_lowercase().eq("n") {
-        key::add(pgclient, Some(&newemail), None, None)?;
+        key::add(
+            pgclient,
+            Some(&newemail),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )?;
     }
 
     Ok(())
@@ -127,7 +148,7 @@ pub fn add_to_usergroup(
 
     let newname = ask_for(&ListObject::UserGroup, usergroup, None, pgclient);
 
-    if newname.eq("") {
+    if newname.is_empty() {
         exit_with_message("User group name cannot be empty.");
     }
 
@@ -142,7 +163,7 @@ pub fn add_to_usergroup(
 
     let newemail = ask_for(&ListObject::UserEmail, email, None, pgclient);
 
-    if newemail.eq("") {
+    if newemail.is_empty() {
         exit_with_message("User email cannot be empty.");
     }
 
@@ -210,7 +231,7 @@ pub fn delete(pgclient: &mut Client, email: Option<&str>, force: bool) -> Result
 
     let oldemail = ask_for(&ListObject::UserEmail, email, None, pgclient);
 
-    if oldemail.eq("") {
+    if oldemail.is_empty() {
         exit_with_message("User email cannot be empty.");
     }
 
@@ -267,7 +288,7 @@ pub fn delete_from_usergroup(
 
     let oldemail = ask_for(&ListObject::UserEmail, email, None, pgclient);
 
-    if oldemail.eq("") {
+    if oldemail.is_empty() {
         exit_with_message("User email cannot be empty.");
     }
 
@@ -283,7 +304,7 @@ pub fn delete_from_usergroup(
 
     let oldusergroup = ask_for(&ListObject::UserGroup, usergroup, None, pgclient);
 
-    if oldusergroup.eq("") {
+    if oldusergroup.is_empty() {
         exit_with_message("Group name cannot be empty.");
     }
 
@@ -376,11 +397,13 @@ pub fn delete_from_usergroup(
 
 pub fn disable(pgclient: &mut Client, email: Option<&str>, force: bool) -> Result<(), Error> {
     println!("Disable user");
-    let query_string = r#"UPDATE "user" SET disabled = true WHERE email = $1"#;
+    let query_string = r#"UPDATE "user"
+                          SET disabled = true, modified_at = NOW(), modified_by = $2
+                          WHERE email = $1"#;
 
     let oldemail = ask_for(&ListObject::UserEmail, email, None, pgclient);
 
-    if oldemail.eq("") {
+    if oldemail.is_empty() {
         exit_with_message("User email cannot be empty.");
     }
 
@@ -408,7 +431,7 @@ pub fn disable(pgclient: &mut Client, email: Option<&str>, force: bool) -> Resul
         }
     }
 
-    _ = pgclient.execute(query_string, &[&oldemail])?;
+    _ = pgclient.execute(query_string, &[&oldemail, &get_ssh_client()])?;
 
     info!("({}) Disabled user '{}'", &get_ssh_client(), &oldemail);
 
@@ -422,11 +445,13 @@ pub fn disable(pgclient: &mut Client, email: Option<&str>, force: bool) -> Resul
 
 pub fn enable(pgclient: &mut Client, email: Option<&str>, force: bool) -> Result<(), Error> {
     println!("Enable user");
-    let query_string = r#"UPDATE "user" SET disabled = false WHERE email = $1"#;
+    let query_string = r#"UPDATE "user"
+                          SET disabled = false, modified_at = NOW(), modified_by = $2
+                          WHERE email = $1"#;
 
     let oldemail = ask_for(&ListObject::UserEmail, email, None, pgclient);
 
-    if oldemail.eq("") {
+    if oldemail.is_empty() {
         exit_with_message("User email cannot be empty.");
     }
 
@@ -451,7 +476,7 @@ pub fn enable(pgclient: &mut Client, email: Option<&str>, force: bool) -> Result
         }
     }
 
-    _ = pgclient.execute(query_string, &[&oldemail])?;
+    _ = pgclient.execute(query_string, &[&oldemail, &get_ssh_client()])?;
 
     info!("({}) Enabled user '{}'", &get_ssh_client(), &oldemail);
 
@@ -463,15 +488,20 @@ pub fn enable(pgclient: &mut Client, email: Option<&str>, force: bool) -> Result
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn list(
     pgclient: &mut Client,
     email: Option<&str>,
     name: Option<&str>,
     id: Option<&str>,
+    added_by: Option<&str>,
+    added_after: Option<&str>,
     exact: bool,
     json: bool,
 ) -> std::result::Result<(), Error> {
-    let query_string = r#"SELECT id, email, name, type::VARCHAR, disabled::CHAR, comment
+    let query_string = r#"SELECT id, email, name, type::VARCHAR, disabled::CHAR, comment,
+                                 created_at::TIMESTAMP(0)::VARCHAR AS created_at, created_by,
+                                 modified_at::TIMESTAMP(0)::VARCHAR AS modified_at, modified_by
                           FROM "user"
                           ORDER BY id"#;
 
@@ -485,9 +515,12 @@ pub fn list(
             usertype: row.get("type"),
             disabled: row.get("disabled"),
             comment: row.get("comment"),
+            metadata: Metadata::from_row(&row),
         });
     }
 
+    metadata::retain(&mut res, |x| &x.metadata, added_by, added_after);
+
     if let Some(email) = email {
         if exact {
             res.retain(|x| x.email.eq(&email));
@@ -521,7 +554,9 @@ pub fn list(
         let mut table = Table::new();
         table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
 
-        table.set_titles(row!["email", "name", "type", "disabled", "comment"]);
+        table.set_titles(row![
+            "email", "name", "type", "disabled", "comment", "added", "modified"
+        ]);
 
         for r in res {
             let (email, name, disabled) = match &r.disabled[..] {
@@ -548,7 +583,9 @@ pub fn list(
                 name,
                 r.usertype,
                 disabled,
-                r.comment.unwrap_or_else(|| "-".to_string())
+                r.comment.unwrap_or_else(|| "-".to_string()),
+                r.metadata.added(),
+                r.metadata.modified()
             ]);
         }
 
@@ -572,12 +609,14 @@ pub fn update(
            SET email = $1,
                name = $2,
                type = $3,
-               comment = $4
+               comment = $4,
+               modified_at = NOW(),
+               modified_by = $6
            WHERE email = $5"#;
 
     let olduseremail = ask_for(&ListObject::UserEmail, email, None, pgclient);
 
-    if olduseremail.eq("") {
+    if olduseremail.is_empty() {
         exit_with_message("User email cannot be empty.");
     }
 
@@ -605,7 +644,7 @@ pub fn update(
         pgclient,
     );
 
-    if newuseremail.to_lowercase().eq("") {
+    if newuseremail.to_lowercase().is_empty() {
         newuseremail.clone_from(&olduseremail);
     } else if !pgclient
         .query(
@@ -627,7 +666,7 @@ pub fn update(
         pgclient,
     );
 
-    if newusername.to_lowercase().eq("") {
+    if newusername.to_lowercase().is_empty() {
         newusername.clone_from(&oldusername);
     } else if !pgclient
         .query(
@@ -674,7 +713,7 @@ pub fn update(
         ),
     );
 
-    let newcommentopt = if newcomment.to_lowercase().eq("") {
+    let newcommentopt = if newcomment.to_lowercase().is_empty() {
         oldusercomment
     } else if newcomment.trim().to_lowercase().eq("null") {
         None
@@ -690,6 +729,7 @@ pub fn update(
             &newutype,
             &newcommentopt,
             &olduseremail,
+            &get_ssh_client(),
         ],
     )?;
 
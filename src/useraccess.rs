@@ -8,6 +8,7 @@ use std::net::IpAddr;
 use time::{format_description, Date, PrimitiveDateTime, Time};
 
 use crate::logging::get_ssh_client;
+use crate::metadata::{self, Metadata};
 use crate::{ask_for, exit_with_message, set_or_ask_for, ListObject};
 
 #[derive(Debug, Serialize)]
@@ -20,6 +21,8 @@ struct UserAccessQuery {
     pub usergroup: Option<String>,
     pub servergroup: Option<String>,
     pub until: String,
+    #[serde(flatten)]
+    pub metadata: Metadata,
 }
 
 #[allow(clippy::too_many_lines)]
@@ -63,8 +66,8 @@ pub fn add(
                 }
 
                 query_string =
-                    r"INSERT INTO useraccess (usergroup_id, serveraccess_id, comment, best_before)
-                                  SELECT usergroup.id, serveraccess.id, $1, $2
+                    r"INSERT INTO useraccess (usergroup_id, serveraccess_id, comment, best_before, created_by)
+                                  SELECT usergroup.id, serveraccess.id, $1, $2, $5
                                   FROM usergroup,serveraccess
                                   WHERE usergroup.name = $3
                                     AND serveraccess.name = $4"
@@ -82,18 +85,19 @@ pub fn add(
             exit_with_message("User not found.");
         }
 
-        query_string = r#"INSERT INTO useraccess (user_id, serveraccess_id, comment, best_before)
-                          SELECT "user".id, serveraccess.id, $1, $2
+        query_string =
+            r#"INSERT INTO useraccess (user_id, serveraccess_id, comment, best_before, created_by)
+                          SELECT "user".id, serveraccess.id, $1, $2, $5
                           FROM "user",serveraccess
                           WHERE "user".email = $3
                             AND serveraccess.name = $4"#
-            .to_string();
+                .to_string();
         newemail
     };
 
     let newname = ask_for(&ListObject::ServerAccess, serveraccess, None, pgclient);
 
-    if newname.eq("") {
+    if newname.is_empty() {
         exit_with_message("Server access name cannot be empty.");
     }
 
@@ -154,7 +158,13 @@ pub fn add(
 
     pgclient.query(
         &query_string,
-        &[&newcomment, &newuntil, &newemail, &newname],
+        &[
+            &newcomment,
+            &newuntil,
+            &newemail,
+            &newname,
+            &get_ssh_client(),
+        ],
     )?;
 
     info!(
@@ -254,7 +264,7 @@ pub fn delete(
 
     let oldname = ask_for(&ListObject::ServerAccess, serveraccess, None, pgclient);
 
-    if oldname.eq("") {
+    if oldname.is_empty() {
         exit_with_message("Server access name cannot be empty.");
     }
 
@@ -312,6 +322,8 @@ pub fn list(
     serveraccess: Option<&str>,
     servergroup: Option<&str>,
     usergroup: Option<&str>,
+    added_by: Option<&str>,
+    added_after: Option<&str>,
     exact: bool,
     expired: bool,
     disabled: bool,
@@ -335,7 +347,11 @@ pub fn list(
                   server.name AS servername,
                   ug.name AS usergroup,
                   sg.name AS servergroup,
-                  useraccess.best_before::VARCHAR AS UNTIL
+                  useraccess.best_before::VARCHAR AS UNTIL,
+                  useraccess.created_at::TIMESTAMP(0)::VARCHAR AS created_at,
+                  useraccess.created_by,
+                  useraccess.modified_at::TIMESTAMP(0)::VARCHAR AS modified_at,
+                  useraccess.modified_by
            FROM useraccess
            LEFT JOIN (useraccess AS ua
                       JOIN (usergroup AS ug
@@ -381,7 +397,11 @@ pub fn list(
                   s.ip,
                   s.name AS servername,
                   '-' AS servergroup,
-                  ua.best_before::VARCHAR AS UNTIL
+                  ua.best_before::VARCHAR AS UNTIL,
+                  ua.created_at::TIMESTAMP(0)::VARCHAR AS created_at,
+                  ua.created_by,
+                  ua.modified_at::TIMESTAMP(0)::VARCHAR AS modified_at,
+                  ua.modified_by
            FROM useraccess AS ua,
                 serveraccess AS sa,
                 "user" AS u,
@@ -400,7 +420,11 @@ pub fn list(
                   NULL::INET AS ip,
                   '-' AS servername,
                   sg.name AS servergroup,
-                  ua.best_before::VARCHAR AS UNTIL
+                  ua.best_before::VARCHAR AS UNTIL,
+                  ua.created_at::TIMESTAMP(0)::VARCHAR AS created_at,
+                  ua.created_by,
+                  ua.modified_at::TIMESTAMP(0)::VARCHAR AS modified_at,
+                  ua.modified_by
            FROM useraccess AS ua,
                 serveraccess AS sa,
                 "user" AS u,
@@ -418,7 +442,11 @@ pub fn list(
                   s.ip,
                   s.name AS servername,
                   '-' AS servergroup,
-                  ua.best_before::VARCHAR AS UNTIL
+                  ua.best_before::VARCHAR AS UNTIL,
+                  ua.created_at::TIMESTAMP(0)::VARCHAR AS created_at,
+                  ua.created_by,
+                  ua.modified_at::TIMESTAMP(0)::VARCHAR AS modified_at,
+                  ua.modified_by
            FROM useraccess AS ua,
                 serveraccess AS sa,
                 usergroup AS ug,
@@ -436,7 +464,11 @@ pub fn list(
                   NULL::INET AS ip,
                   '-' AS servername,
                   sg.name AS servergroup,
-                  ua.best_before::VARCHAR AS UNTIL
+                  ua.best_before::VARCHAR AS UNTIL,
+                  ua.created_at::TIMESTAMP(0)::VARCHAR AS created_at,
+                  ua.created_by,
+                  ua.modified_at::TIMESTAMP(0)::VARCHAR AS modified_at,
+                  ua.modified_by
            FROM useraccess AS ua,
                 serveraccess AS sa,
                 usergroup AS ug,
@@ -470,9 +502,12 @@ pub fn list(
             usergroup: row.get("usergroup"),
             servergroup: row.get("servergroup"),
             until: row.get("until"),
+            metadata: Metadata::from_row(&row),
         });
     }
 
+    metadata::retain(&mut res, |x| &x.metadata, added_by, added_after);
+
     if let Some(email) = email {
         if exact {
             res.retain(|x| x.email.eq(&email));
@@ -571,7 +606,8 @@ pub fn list(
             "ip",
             "servername",
             "server group",
-            "until"
+            "until",
+            "added"
         ]);
 
         for r in res {
@@ -599,7 +635,8 @@ pub fn list(
                 ip,
                 r.servername.unwrap_or_else(|| "-".to_string()),
                 r.servergroup.unwrap_or_else(|| "-".to_string()),
-                r.until
+                r.until,
+                r.metadata.added()
             ]);
         }
 
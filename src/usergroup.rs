@@ -26,7 +26,7 @@ pub fn add(
 
     let newname = ask_for(&ListObject::UserGroup, usergroup, None, pgclient);
 
-    if newname.eq("") {
+    if newname.is_empty() {
         exit_with_message("Group name cannot be empty.");
     }
 
@@ -93,7 +93,7 @@ pub fn add_to_usergroup(
         pgclient,
     );
 
-    if newsubgroupname.eq("") {
+    if newsubgroupname.is_empty() {
         exit_with_message("Group name cannot be empty.");
     }
 
@@ -113,7 +113,7 @@ pub fn add_to_usergroup(
         pgclient,
     );
 
-    if newsupergroupname.eq("") {
+    if newsupergroupname.is_empty() {
         exit_with_message("Group name cannot be empty.");
     }
 
@@ -126,6 +126,28 @@ pub fn add_to_usergroup(
         exit_with_message("User group not found.");
     }
 
+    // parent must not be (a member of) the new member group
+    let res = pgclient.query(
+        r"WITH RECURSIVE supergroups AS
+            (SELECT id
+             FROM usergroup
+             WHERE name = $1
+             UNION SELECT g.supergroup_id
+             FROM usergroup_usergroup g
+             JOIN supergroups x ON x.id = g.subgroup_id)
+          SELECT usergroup.id
+          FROM supergroups
+          JOIN usergroup ON usergroup.id = supergroups.id
+          WHERE usergroup.name = $2",
+        &[&newsupergroupname, &newsubgroupname],
+    )?;
+
+    if !res.is_empty() {
+        exit_with_message(&format!(
+            "Cannot add user group '{newsubgroupname}' to '{newsupergroupname}' (would create a cycle)."
+        ));
+    }
+
     let res = pgclient.query(
         r"SELECT subgroup_id, supergroup_id
            FROM usergroup_usergroup
@@ -181,7 +203,7 @@ pub fn delete(pgclient: &mut Client, usergroup: Option<&str>, force: bool) -> Re
 
     let oldusergroup = ask_for(&ListObject::UserGroup, usergroup, None, pgclient);
 
-    if oldusergroup.eq("") {
+    if oldusergroup.is_empty() {
         exit_with_message("User group cannot be empty.");
     }
 
@@ -253,7 +275,7 @@ pub fn delete_from_usergroup(
         pgclient,
     );
 
-    if oldsubgroupname.eq("") {
+    if oldsubgroupname.is_empty() {
         exit_with_message("Group name cannot be empty.");
     }
 
@@ -273,7 +295,7 @@ pub fn delete_from_usergroup(
         pgclient,
     );
 
-    if oldsupergroupname.eq("") {
+    if oldsupergroupname.is_empty() {
         exit_with_message("Group name cannot be empty.");
     }
 
@@ -457,7 +479,7 @@ pub fn update(
 
     let usergroupname = ask_for(&ListObject::UserGroup, usergroup, None, pgclient);
 
-    if usergroupname.eq("") {
+    if usergroupname.is_empty() {
         exit_with_message("User group name cannot be empty.");
     }
 
@@ -484,7 +506,7 @@ pub fn update(
         pgclient,
     );
 
-    if newusergroupname.to_lowercase().eq("") {
+    if newusergroupname.to_lowercase().is_empty() {
         newusergroupname.clone_from(&oldusergroupname);
     } else if !pgclient
         .query(
@@ -507,7 +529,7 @@ pub fn update(
         ),
     );
 
-    let newcommentopt = if newcomment.to_lowercase().eq("") {
+    let newcommentopt = if newcomment.to_lowercase().is_empty() {
         oldusergroupcomment
     } else if newcomment.trim().to_lowercase().eq("null") {
         None